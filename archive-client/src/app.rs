@@ -9,10 +9,16 @@ pub struct App {
     frame_counter: FrameCounter,
     sprite_painter: sprite::SpritePainter,
     sprite_texture: sprite::SpriteTexture,
+    atlas: sprite::Atlas,
     sprites: Vec<sprite::GpuSprite>,
     text_painter: text::TextPainter,
     inconsolata: wgpu_glyph::GlyphBrush<()>,
     last_fps: f64,
+    camera: camera::Camera,
+    hud: hud::Hud,
+    minimap: minimap::Minimap,
+    net_overlay: net_overlay::NetOverlay,
+    input: input::InputState,
 
     client: client::Client,
     client_rx: client::ClientReceiver,
@@ -29,27 +35,25 @@ impl App {
         let frame_counter = FrameCounter::default();
         let sprite_painter = sprite::SpritePainter::init(ctx, &global.global_bind_group_layout);
 
-        let texture_handle =
-            sprite::TextureHandle::new(device, queue, include_asset!("textures/missing.png"));
+        let (atlas, texture_handle) = sprite::AtlasBuilder::new()
+            .add(
+                sprite::MISSING_TEXTURE,
+                include_asset!("textures/missing.png"),
+            )
+            .add("player", include_asset!("textures/player.png"))
+            .add("bullet", include_asset!("textures/bullet.png"))
+            .add("obstacle", include_asset!("textures/obstacle.png"))
+            .add_image(
+                sprite::WHITE_TEXTURE,
+                image::RgbaImage::from_pixel(4, 4, image::Rgba([255; 4])),
+            )
+            .build(device, queue);
         let sprite_texture = sprite::SpriteTexture::init(
             device,
             &sprite_painter.texture_bind_group_layout,
             texture_handle,
         );
 
-        //let mut rng = random::new();
-
-        let mut sprites = Vec::new();
-        for i in 0..10 {
-            sprites.push(sprite::GpuSprite {
-                position: [i as f32 * 200. + 550., i as f32 * 100. + 550.], //[rng.gen32(), rng.gen32()],
-                size: [800., 800.],
-                rotation: i as f32 / 3.,
-                color: 0xffffffff,
-                ..Default::default()
-            });
-        }
-
         let text_painter = text::TextPainter::new(ctx, &global.global_bind_group_layout);
         let inconsolata =
             text::glyph_brush_from_font(ctx, include_asset!("fonts/Rubik-Regular.ttf").to_vec());
@@ -58,18 +62,24 @@ impl App {
             frame_counter,
             sprite_painter,
             sprite_texture,
-            sprites,
+            atlas,
+            sprites: Vec::new(),
             text_painter,
             inconsolata,
             last_fps: 0.,
+            camera: camera::Camera::new(),
+            hud: hud::Hud::new(ctx),
+            minimap: minimap::Minimap::new(ctx),
+            net_overlay: net_overlay::NetOverlay::new(ctx),
+            input: input::InputState::new(launch_config::bindings().clone()),
             client: client::Client::new(),
             client_rx,
         }
     }
 
     /// update is called for any WindowEvent not handled by the framework
-    fn _update(&mut self, _event: winit::event::WindowEvent) {
-        //empty
+    pub fn update(&mut self, event: &winit::event::WindowEvent) {
+        self.input.handle_event(event);
     }
 
     /// resize is called on WindowEvent::Resized events
@@ -83,7 +93,7 @@ impl App {
     }
 
     pub fn render(&mut self, ctx: &GraphicsContext, view: &wgpu::TextureView) {
-        let (_last_frametime, fps_opt) = self.frame_counter.tick();
+        let (last_frametime, fps_opt) = self.frame_counter.tick();
         if let Some(fps) = fps_opt {
             self.last_fps = fps;
         }
@@ -96,19 +106,48 @@ impl App {
                 Err(mpsc::TryRecvError::Disconnected) => panic!("client_rx disconnected"),
             }
         }
-        self.client.frame(mk_num!(0));
+        let screen_size = [ctx.config.width as f32, ctx.config.height as f32];
+        self.input.poll_gamepad();
+        for action in self.input.take_presses() {
+            if action == input::Action::ToggleNetOverlay {
+                self.net_overlay.toggle();
+            }
+        }
+        let input = self.input.sample(&self.camera, screen_size);
+        self.client.set_input(input);
+        let dt = Num::from_num(last_frametime.as_secs_f64());
+        self.client.frame(dt);
+
+        for event in self.client.take_events() {
+            self.hud.push_event(event);
+        }
+
+        let stats = match self.client.view() {
+            Some(mut view) => {
+                self.camera.update(&mut view, last_frametime.as_secs_f32());
+                sprite::extract_sprites(&mut view, &self.atlas, &mut self.sprites);
+                let statics_version = self.client.statics_version().unwrap_or(0);
+                self.minimap.update(
+                    ctx,
+                    &self.sprite_painter,
+                    &self.atlas,
+                    statics_version,
+                    &mut view,
+                );
+                hud::HudStats::from_view(&mut view)
+            }
+            None => {
+                self.sprites.clear();
+                self.minimap.clear();
+                Default::default()
+            }
+        };
+        self.hud.update(ctx, &self.atlas, stats);
+        self.net_overlay.update(ctx, &self.atlas, &self.client);
 
         // update the viewport
         let global_data = Global {
-            mvp: cgmath::ortho(
-                0.0,
-                ctx.config.width as f32,
-                ctx.config.height as f32,
-                0.0,
-                -1.0,
-                1.0,
-            )
-            .into(),
+            mvp: self.camera.view_proj(screen_size),
         };
         GlobalBuffer::write(ctx, global_data);
 
@@ -133,6 +172,15 @@ impl App {
         let sprites = self
             .sprite_painter
             .render(ctx, view, &self.sprite_texture, &self.sprites);
+        let minimap =
+            self.minimap
+                .render(ctx, view, &mut self.sprite_painter, &self.sprite_texture);
+        let hud = self
+            .hud
+            .render(ctx, view, &mut self.sprite_painter, &self.sprite_texture);
+        let net_overlay =
+            self.net_overlay
+                .render(ctx, view, &mut self.sprite_painter, &self.sprite_texture);
 
         let fps_str = format!("FPS: {}", self.last_fps.round() as i64);
         let fps_section = wgpu_glyph::Section {
@@ -143,13 +191,19 @@ impl App {
                 .with_scale(40.0)],
             ..Default::default()
         };
+        let mut sections = self.hud.sections();
+        sections.extend(self.net_overlay.sections());
+        sections.push(fps_section);
         let texts = self
             .text_painter
-            .render(ctx, view, &mut self.inconsolata, &[fps_section]);
-        ctx.queue.submit([clear, sprites, texts]);
+            .render(ctx, view, &mut self.inconsolata, &sections);
+        let overlays = minimap.into_iter().chain([hud]).chain(net_overlay);
+        ctx.queue
+            .submit([clear, sprites].into_iter().chain(overlays).chain([texts]));
     }
 
     pub fn post_frame(&mut self, ctx: &GraphicsContext) {
+        self.sprite_painter.post_frame(ctx);
         self.text_painter.post_frame(ctx);
     }
 }
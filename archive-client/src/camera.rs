@@ -0,0 +1,176 @@
+use archive_engine::*;
+
+// square world units visible on screen whatever the window size, so
+// bigger windows see the same amount of the arena, just sharper
+const VISIBLE_AREA: f32 = 24.0 * 24.0;
+
+// health lost for a full strength shake
+const SHAKE_HEALTH: f32 = 25.0;
+// trauma lost per second
+const SHAKE_DECAY: f32 = 1.5;
+// world units at full trauma
+const MAX_SHAKE: f32 = 0.4;
+
+pub type CameraQ = (
+    &'static ecs::Camera,
+    &'static ecs::Position,
+    Option<&'static ecs::Health>,
+);
+
+fn to_f32(num: Num) -> f32 {
+    num.0.to_num::<f32>()
+}
+
+#[derive(Default)]
+pub struct Camera {
+    // world space
+    center: [f32; 2],
+    // 0 to 1, shake strength is its square so small hits stay subtle
+    trauma: f32,
+    // drives the shake pattern
+    time: f32,
+    last_health: Option<u16>,
+}
+
+impl Camera {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // follows the entity the server put a Camera on, which is our player
+    pub fn update(&mut self, view: &mut ecs::Snapshot, dt: f32) {
+        self.time += dt;
+        self.trauma = (self.trauma - SHAKE_DECAY * dt).max(0.0);
+
+        let (pos, health) = match view.query_mut::<CameraQ>().into_iter().next() {
+            Some((_, (_, pos, health))) => (*pos, health.map(|h| h.value())),
+            // dead or not spawned yet, stay where we were
+            None => {
+                self.last_health = None;
+                return;
+            }
+        };
+        self.center = [to_f32(pos.xy.x), to_f32(pos.xy.y)];
+
+        if let (Some(last), Some(health)) = (self.last_health, health) {
+            if health < last {
+                self.shake((last - health) as f32 / SHAKE_HEALTH);
+            }
+        }
+        self.last_health = health;
+    }
+
+    pub fn center(&self) -> [f32; 2] {
+        self.center
+    }
+
+    pub fn shake(&mut self, trauma: f32) {
+        self.trauma = (self.trauma + trauma).min(1.0);
+    }
+
+    pub fn pixels_per_unit(screen_size: [f32; 2]) -> f32 {
+        (screen_size[0] * screen_size[1] / VISIBLE_AREA).sqrt()
+    }
+
+    // world space (y up) to clip space
+    pub fn view_proj(&self, screen_size: [f32; 2]) -> [[f32; 4]; 4] {
+        let scale = Self::pixels_per_unit(screen_size);
+        let half = [screen_size[0] / scale / 2.0, screen_size[1] / scale / 2.0];
+
+        // a few incommensurate sines look random enough and don't need an rng
+        let strength = MAX_SHAKE * self.trauma * self.trauma;
+        let t = self.time;
+        let shake = [
+            strength * ((t * 47.0).sin() + (t * 29.0).sin()) / 2.0,
+            strength * ((t * 53.0 + 1.3).sin() + (t * 31.0).sin()) / 2.0,
+        ];
+        let center = [self.center[0] + shake[0], self.center[1] + shake[1]];
+
+        cgmath::ortho(
+            center[0] - half[0],
+            center[0] + half[0],
+            center[1] - half[1],
+            center[1] + half[1],
+            -1.0,
+            1.0,
+        )
+        .into()
+    }
+
+    // pixels (y down) to world space, leaving out the shake so aiming
+    // doesn't jitter
+    pub fn screen_to_world(&self, screen: [f32; 2], screen_size: [f32; 2]) -> [f32; 2] {
+        let scale = Self::pixels_per_unit(screen_size);
+        [
+            self.center[0] + (screen[0] - screen_size[0] / 2.0) / scale,
+            self.center[1] - (screen[1] - screen_size[1] / 2.0) / scale,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Matrix4, Vector4};
+
+    fn to_clip(camera: &Camera, screen_size: [f32; 2], world: [f32; 2]) -> [f32; 2] {
+        let m: Matrix4<f32> = camera.view_proj(screen_size).into();
+        let clip = m * Vector4::new(world[0], world[1], 0.0, 1.0);
+        [clip.x, clip.y]
+    }
+
+    fn close(a: [f32; 2], b: [f32; 2]) -> bool {
+        (a[0] - b[0]).abs() < 1e-4 && (a[1] - b[1]).abs() < 1e-4
+    }
+
+    #[test]
+    fn test_view_proj() {
+        let camera = Camera {
+            center: [10.0, -4.0],
+            ..Default::default()
+        };
+        for screen_size in [[800.0, 600.0], [1920.0, 1080.0], [600.0, 800.0]] {
+            let center = to_clip(&camera, screen_size, [10.0, -4.0]);
+            assert!(close(center, [0.0, 0.0]), "{:?}", center);
+
+            // the corner of the visible area is the corner of the screen
+            let scale = Camera::pixels_per_unit(screen_size);
+            let (w, h) = (screen_size[0] / scale, screen_size[1] / scale);
+            let corner = to_clip(&camera, screen_size, [10.0 + w / 2.0, -4.0 + h / 2.0]);
+            assert!(close(corner, [1.0, 1.0]), "{:?}", corner);
+            assert!((w * h - VISIBLE_AREA).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn test_screen_to_world() {
+        let camera = Camera {
+            center: [10.0, -4.0],
+            ..Default::default()
+        };
+        let screen_size = [800.0, 600.0];
+        assert_eq!(
+            camera.screen_to_world([400.0, 300.0], screen_size),
+            [10.0, -4.0]
+        );
+        // top left of the screen is up and to the left in the world
+        let world = camera.screen_to_world([0.0, 0.0], screen_size);
+        let corner = to_clip(&camera, screen_size, world);
+        assert!(close(corner, [-1.0, 1.0]), "{:?}", corner);
+    }
+
+    #[test]
+    fn test_shake_decays() {
+        let mut camera = Camera::new();
+        let mut view = ecs::Snapshot::new();
+        camera.shake(1.0);
+        let still = Camera::default().view_proj([800.0, 600.0]);
+
+        camera.update(&mut view, 0.1);
+        assert_ne!(camera.view_proj([800.0, 600.0]), still);
+        camera.update(&mut view, 1.0);
+        assert_eq!(camera.trauma, 0.0);
+        // center stays put when there is nothing to follow
+        assert_eq!(camera.center, [0.0, 0.0]);
+    }
+}
@@ -1,21 +1,103 @@
+use std::collections::{BTreeMap, VecDeque};
 use std::sync::mpsc;
+use std::task::{Context, Poll};
+use std::time::Duration;
 
-use log::{error, info};
+use futures::{task::noop_waker_ref, FutureExt};
+use instant::Instant;
+use log::{debug, error, info, warn};
 
 use archive_engine::*;
 
+use super::{ClockSync, NetStats};
+
+// how many ticks behind the newest snapshot we render, so that there is
+// usually a newer snapshot to interpolate towards
+const INTERP_DELAY_TICKS: f64 = 6.0;
+// fraction of the error between the render clock and where it should be
+// that gets corrected each frame
+const CLOCK_CORRECTION: f64 = 0.05;
+// past this the render clock jumps instead of catching up smoothly
+const MAX_CLOCK_DRIFT_TICKS: f64 = 30.0;
+// between reconnect attempts. the server holds the slot for 10s by default
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_RECONNECTS: u32 = 8;
+// sent inputs kept for prediction, a second's worth
+const MAX_SENT_INPUTS: usize = 60;
+
+// our own player, which the server marks with a Camera
+type PredictQ = (
+    &'static ecs::Camera,
+    &'static mut ecs::Position,
+    &'static mut ecs::Rotation,
+);
+
+struct ReceivedSnapshot {
+    tick: u64,
+    snapshot: ecs::Snapshot,
+    // the same for snapshots with the same static entities
+    statics_version: u64,
+}
+
 #[derive(Default)]
 pub struct Client {
     realm: ecs::Realm,
-    snapshots: rtc::SnapshotBuf<ecs::Snapshot>,
+    // by DeltaSeq, so later deltas can use them as a base
+    snapshots: rtc::SnapshotBuf<ReceivedSnapshot>,
+    // the last statics_version handed out, it never goes back so versions
+    // from old sessions can't be confused with new ones
+    statics_versions: u64,
+    // realm tick -> DeltaSeq for buffered snapshots, for finding the pair
+    // to interpolate between
+    timeline: BTreeMap<u64, rtc::DeltaSeq>,
+    // the (fractional) realm tick being rendered
+    render_tick: f64,
+    // what the player is doing, sent once a tick
+    input: ecs::Input,
+    input_seq: rtc::InputSeq,
+    // what went out, newest last, for predicting our player
+    sent_inputs: VecDeque<ecs::Input>,
+    // seconds since the last input went out
+    input_clock: f64,
+    // GameEvents from the server, until the app takes them
+    events: Vec<ecs::GameEvent>,
+    // for the network overlay
+    net_stats: NetStats,
+    // where the server's realm is, from the pongs
+    clock: ClockSync,
+    pending_sends: Vec<SharedFuture<bool>>,
     session: Option<rtc::BoxedRtcSession>,
+    // how to get back into our slot, if the app gave us a way
+    rejoin: Option<Rejoin>,
+    reconnecting: Option<Reconnecting>,
+}
+
+// connects with a ticket however the app does it, the errors are only logged
+pub type Connector =
+    Box<dyn Fn(rtc::ArenaTicket) -> SharedFuture<Result<rtc::BoxedRtcSession, String>>>;
+
+// what the client needs to reconnect on its own when the session drops
+pub struct Rejoin {
+    pub ticket: rtc::ArenaTicket,
+    pub connect: Connector,
+}
+
+struct Reconnecting {
+    attempts: u32,
+    next_at: Instant,
+    pending: Option<SharedFuture<Result<rtc::BoxedRtcSession, String>>>,
 }
 
 // messages received "externally" to the client
 pub enum ClientMessageFromApp {
-    // TODO change this to be a server descriptor?
-    // better long term but harder to report errors
+    // a session that can't be resumed, e.g. a replay
     Connected(rtc::BoxedRtcSession),
+    // a session into an arena slot, which the client reclaims with
+    // rejoin if it drops
+    Joined {
+        session: rtc::BoxedRtcSession,
+        rejoin: Rejoin,
+    },
 }
 
 pub type ClientReceiver = mpsc::Receiver<ClientMessageFromApp>;
@@ -27,25 +109,475 @@ impl Client {
     pub fn recv_from_app(&mut self, msg: ClientMessageFromApp) {
         use ClientMessageFromApp::*;
         match msg {
-            Connected(session) => self.session = Some(session),
+            Connected(session) => {
+                self.rejoin = None;
+                self.start_session(session);
+            }
+            Joined { session, rejoin } => {
+                self.rejoin = Some(rejoin);
+                self.start_session(session);
+            }
         }
     }
-    pub fn frame(&mut self, _dt: Num) {
-        if self.session.is_none() {
+    pub fn frame(&mut self, dt: Num) {
+        self.poll_reconnect();
+        let session = match self.session.as_mut() {
+            Some(session) => session,
+            None => return,
+        };
+        if matches!(
+            session.get_state(),
+            rtc::SessionState::Disconnected | rtc::SessionState::Closed
+        ) {
+            self.lose_session();
             return;
         }
         loop {
             match self.session.as_mut().unwrap().try_recv() {
-                Ok(msg) => {
-                    info!("received: {:?}", msg);
-                }
+                Ok(msg) => self.recv_from_server(&msg),
                 Err(mpsc::TryRecvError::Empty) => break,
                 Err(mpsc::TryRecvError::Disconnected) => {
-                    error!("session disconnected");
-                    self.session = None;
+                    self.lose_session();
+                    return;
+                }
+            }
+        }
+        self.send_input(dt);
+        let now = Instant::now();
+        if let Some(id) = self.net_stats.poll_ping(now) {
+            self.send(rtc::encode_message(&rtc::ClientMessage::Ping { id }));
+        }
+        self.net_stats.update(now);
+        self.clock.update(now);
+        self.poll_sends();
+        self.advance_clock(dt);
+    }
+
+    fn start_session(&mut self, session: rtc::BoxedRtcSession) {
+        // seqs start over with each session
+        self.snapshots = Default::default();
+        self.timeline.clear();
+        self.input_seq = 0;
+        self.sent_inputs.clear();
+        self.net_stats = NetStats::new();
+        self.clock = ClockSync::new();
+        self.pending_sends.clear();
+        self.reconnecting = None;
+        if let Some(old) = self.session.replace(session) {
+            old.close();
+        }
+    }
+    // drops the session, and starts trying to get back into our slot if
+    // the app told us how
+    fn lose_session(&mut self) {
+        if let Some(session) = self.session.take() {
+            session.close();
+        }
+        self.pending_sends.clear();
+        if self.rejoin.is_none() {
+            error!("session disconnected");
+            return;
+        }
+        warn!("session disconnected, reconnecting");
+        self.reconnecting = Some(Reconnecting {
+            attempts: 0,
+            next_at: Instant::now(),
+            pending: None,
+        });
+    }
+    fn poll_reconnect(&mut self) {
+        let (rejoin, reconnecting) = match (&self.rejoin, &mut self.reconnecting) {
+            (Some(rejoin), Some(reconnecting)) => (rejoin, reconnecting),
+            _ => return,
+        };
+        let now = Instant::now();
+        if reconnecting.pending.is_none() {
+            if now < reconnecting.next_at {
+                return;
+            }
+            reconnecting.attempts += 1;
+            debug!("reconnect attempt {}", reconnecting.attempts);
+            reconnecting.pending = Some((rejoin.connect)(rejoin.ticket.clone()));
+        }
+        let pending = reconnecting.pending.as_mut().unwrap();
+        // the app's connector makes progress without us, so a noop waker
+        // and a poll a frame is enough
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let result = match pending.poll_unpin(&mut cx) {
+            Poll::Pending => return,
+            Poll::Ready(result) => result,
+        };
+        reconnecting.pending = None;
+        match result {
+            Ok(session) => {
+                info!("reconnected");
+                self.start_session(session);
+            }
+            Err(e) if reconnecting.attempts >= MAX_RECONNECTS => {
+                error!("giving up on reconnecting: {e}");
+                self.reconnecting = None;
+            }
+            Err(e) => {
+                warn!("reconnect failed: {e}");
+                reconnecting.next_at = now + RECONNECT_INTERVAL;
+            }
+        }
+    }
+
+    pub fn take_events(&mut self) -> Vec<ecs::GameEvent> {
+        std::mem::take(&mut self.events)
+    }
+    pub fn set_input(&mut self, input: ecs::Input) {
+        self.input = input;
+    }
+    pub fn input(&self) -> ecs::Input {
+        self.input
+    }
+    pub fn net_stats(&self) -> &NetStats {
+        &self.net_stats
+    }
+    pub fn clock(&self) -> &ClockSync {
+        &self.clock
+    }
+    // the (fractional) tick the server is on right now, once a pong has
+    // come back
+    pub fn server_tick(&self) -> Option<f64> {
+        self.clock.server_tick(Instant::now())
+    }
+    // snapshots we could still interpolate through
+    pub fn buffered_snapshots(&self) -> usize {
+        self.timeline.len()
+    }
+    // how far the render clock is behind the newest snapshot, in ticks
+    pub fn interp_delay(&self) -> Option<f64> {
+        let (&newest, _) = self.timeline.iter().next_back()?;
+        Some(newest as f64 - self.render_tick)
+    }
+
+    // the replicated state to draw this frame, None until the server has
+    // sent something
+    pub fn view(&self) -> Option<ecs::Snapshot> {
+        let (from, to, t) = self.view_pair()?;
+        let mut view = ecs::Snapshot::interpolate(&from.snapshot, &to.snapshot, Num::from_num(t));
+        self.predict(&mut view);
+        Some(view)
+    }
+    // changes whenever the static entities in view do, so what's drawn from
+    // them only has to be redone then
+    pub fn statics_version(&self) -> Option<u64> {
+        // the view has exactly the entities in `to`
+        let (_, to, _) = self.view_pair()?;
+        Some(to.statics_version)
+    }
+    // the snapshots being rendered between, and how far between
+    fn view_pair(&self) -> Option<(&ReceivedSnapshot, &ReceivedSnapshot, f64)> {
+        let floor = self.render_tick.max(0.0).floor() as u64;
+        let (&from_tick, &from_seq) = self
+            .timeline
+            .range(..=floor)
+            .next_back()
+            .or_else(|| self.timeline.iter().next())?;
+        let from = self.received(from_seq)?;
+
+        let to = self
+            .timeline
+            .range(from_tick + 1..)
+            .next()
+            .and_then(|(_, &seq)| self.received(seq));
+        let (to, t) = match to {
+            Some(to) => {
+                let span = (to.tick - from.tick) as f64;
+                let t = (self.render_tick - from.tick as f64) / span;
+                (to, t.clamp(0.0, 1.0))
+            }
+            // nothing newer yet, hold still
+            None => (from, 0.0),
+        };
+        Some((from, to, t))
+    }
+    // inputs the server can't have applied by the tick being rendered, about
+    // a round trip plus the interpolation delay's worth
+    pub fn pending_inputs(&self) -> usize {
+        let rtt = self.net_stats.rtt_ms.latest().unwrap_or(0.0) as f64 / 1000.0;
+        let ticks = rtt / ecs::TICK_DURATION.as_secs_f64() + self.interp_delay().unwrap_or(0.0);
+        (ticks.max(0.0).ceil() as usize).min(self.sent_inputs.len())
+    }
+    // draws our player where our inputs will take it instead of where the
+    // delayed snapshots have it. movement is simple enough to redo exactly,
+    // input_system sets the velocity and movement_system adds it on
+    fn predict(&self, view: &mut ecs::Snapshot) {
+        let mut offset = V2::default();
+        for input in self.sent_inputs.iter().rev().take(self.pending_inputs()) {
+            offset += input.movement() * ecs::PLAYER_SPEED;
+        }
+        for (_, (_, pos, rot)) in view.query_mut::<PredictQ>() {
+            pos.xy += offset;
+            rot.rad = self.input().aim();
+        }
+    }
+
+    fn received(&self, seq: rtc::DeltaSeq) -> Option<&ReceivedSnapshot> {
+        self.snapshots
+            .index(rtc::snapshot_index(seq))
+            .ok()
+            .flatten()
+    }
+
+    fn recv_from_server(&mut self, bytes: &[u8]) {
+        self.net_stats.record_in(bytes.len());
+        let message = match rtc::decode_message::<rtc::ServerMessage>(bytes) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("bad message from server: {e}");
+                return;
+            }
+        };
+        match message {
+            rtc::ServerMessage::Delta {
+                seq,
+                base,
+                tick,
+                delta,
+                checksum,
+            } => self.apply_delta(seq, base, tick, delta, checksum),
+            rtc::ServerMessage::Events { events, .. } => self.events.extend(events),
+            rtc::ServerMessage::Pong { id, tick } => {
+                let now = Instant::now();
+                if let Some(rtt) = self.net_stats.record_pong(id, now) {
+                    self.clock.record_pong(rtt, tick, now);
+                }
+            }
+        }
+    }
+
+    fn apply_delta(
+        &mut self,
+        seq: rtc::DeltaSeq,
+        base: Option<rtc::DeltaSeq>,
+        tick: u64,
+        delta: ecs::Delta,
+        checksum: Option<ecs::SnapshotChecksum>,
+    ) {
+        self.net_stats.record_seq(seq);
+        let mut empty = ecs::Snapshot::new();
+        // no statics at all is always version 0
+        let (base_snapshot, base_statics) = match base {
+            None => (&mut empty, 0),
+            Some(base) => match self.snapshots.index_mut(rtc::snapshot_index(base)) {
+                Ok(Some(received)) => (&mut received.snapshot, received.statics_version),
+                // we never got the base, or it's too old. the server moves
+                // on to a newer base once it hears our acks
+                _ => {
+                    debug!("no base {base} for snapshot {seq}");
                     return;
                 }
+            },
+        };
+        let snapshot = match delta.try_apply(base_snapshot) {
+            Ok(snapshot) => snapshot,
+            // not acking it means the server keeps diffing from an older base
+            Err(e) => {
+                warn!("bad delta {seq}: {e}");
+                return;
+            }
+        };
+        if checksum.map_or(false, |checksum| checksum != snapshot.checksum()) {
+            // keep showing what we have until a delta from scratch comes
+            warn!("snapshot {seq} doesn't match the server's, resyncing");
+            self.net_stats.desyncs += 1;
+            self.send(rtc::encode_message(&rtc::ClientMessage::Resync));
+            return;
+        }
+
+        let statics_version = if delta.touches(ecs::Blueprint::Static, base_snapshot, &snapshot) {
+            self.statics_versions += 1;
+            self.statics_versions
+        } else {
+            base_statics
+        };
+        let received = ReceivedSnapshot {
+            tick,
+            snapshot,
+            statics_version,
+        };
+        if let Err(e) = self.snapshots.add(rtc::snapshot_index(seq), received) {
+            warn!("dropping snapshot {seq}: {e:?}");
+            return;
+        }
+        self.timeline.insert(tick, seq);
+        self.send(rtc::encode_message(&rtc::ClientMessage::Ack { seq }));
+    }
+
+    // the server applies at most one input a tick, so don't send more
+    fn send_input(&mut self, dt: Num) {
+        let tick = ecs::TICK_DURATION.as_secs_f64();
+        self.input_clock += dt.0.to_num::<f64>();
+        if self.input_clock < tick {
+            return;
+        }
+        // after a long frame, don't try to catch up with a burst
+        self.input_clock = (self.input_clock - tick).min(tick);
+
+        let seq = self.input_seq;
+        self.input_seq += 1;
+        let input = self.input;
+        if self.sent_inputs.len() == MAX_SENT_INPUTS {
+            self.sent_inputs.pop_front();
+        }
+        self.sent_inputs.push_back(input);
+        self.send(rtc::encode_message(&rtc::ClientMessage::Input {
+            seq,
+            input,
+        }));
+    }
+
+    fn send(&mut self, msg: Vec<u8>) {
+        if let Some(session) = &self.session {
+            self.net_stats.record_out(msg.len());
+            self.pending_sends.push(session.send(msg));
+        }
+    }
+    // frames can't block on sends, so poll them once a frame and forget
+    // them once they finish
+    fn poll_sends(&mut self) {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        for mut send in std::mem::take(&mut self.pending_sends) {
+            match send.poll_unpin(&mut cx) {
+                Poll::Pending => self.pending_sends.push(send),
+                Poll::Ready(true) => (),
+                Poll::Ready(false) => warn!("failed to send to server"),
             }
         }
     }
+
+    fn advance_clock(&mut self, dt: Num) {
+        let newest = match self.timeline.keys().next_back() {
+            Some(&newest) => newest as f64,
+            None => return,
+        };
+        let target = newest - INTERP_DELAY_TICKS;
+        self.render_tick += dt.0.to_num::<f64>() / ecs::TICK_DURATION.as_secs_f64();
+        let error = target - self.render_tick;
+        if error.abs() > MAX_CLOCK_DRIFT_TICKS {
+            self.render_tick = target;
+        } else {
+            self.render_tick += error * CLOCK_CORRECTION;
+        }
+
+        // everything older than the snapshot we're interpolating from can go
+        let floor = self.render_tick.max(0.0).floor() as u64;
+        if let Some((&from_tick, _)) = self.timeline.range(..=floor).next_back() {
+            self.timeline = self.timeline.split_off(&from_tick);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct FakeSession(rtc::SessionState);
+    impl rtc::RtcSession for FakeSession {
+        fn get_state(&self) -> rtc::SessionState {
+            self.0
+        }
+        fn close(&self) {}
+        fn send(&self, _msg: Vec<u8>) -> SharedFuture<bool> {
+            Box::pin(futures::future::ready(true))
+        }
+        fn try_recv(&mut self) -> Result<Vec<u8>, mpsc::TryRecvError> {
+            Err(mpsc::TryRecvError::Empty)
+        }
+    }
+    fn fake(state: rtc::SessionState) -> rtc::BoxedRtcSession {
+        Box::new(FakeSession(state))
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let answer = rtc::JoinAnswer {
+            client_id: 3,
+            resume_token: "token".into(),
+        };
+        let calls = Rc::new(Cell::new(0));
+        let connect_calls = calls.clone();
+        let connect = move |ticket: rtc::ArenaTicket| -> SharedFuture<Result<_, String>> {
+            let resume = ticket.resume.unwrap();
+            assert_eq!(
+                (resume.client_id, resume.resume_token.as_str()),
+                (3, "token")
+            );
+            connect_calls.set(connect_calls.get() + 1);
+            Box::pin(futures::future::ready(Ok(fake(
+                rtc::SessionState::Connected,
+            ))))
+        };
+        let mut client = Client::new();
+        client.recv_from_app(ClientMessageFromApp::Joined {
+            session: fake(rtc::SessionState::Closed),
+            rejoin: Rejoin {
+                ticket: answer.resume_ticket(7),
+                connect: Box::new(connect),
+            },
+        });
+
+        let dt = Num::from_num(0.01);
+        client.frame(dt);
+        assert!(client.session.is_none());
+        assert!(client.reconnecting.is_some());
+        client.frame(dt);
+        assert_eq!(calls.get(), 1);
+        assert!(client.session.is_some());
+        assert!(client.reconnecting.is_none());
+    }
+
+    #[test]
+    fn test_no_rejoin() {
+        // e.g. a replay running out
+        let mut client = Client::new();
+        client.recv_from_app(ClientMessageFromApp::Connected(fake(
+            rtc::SessionState::Closed,
+        )));
+        client.frame(Num::from_num(0.01));
+        assert!(client.session.is_none());
+        assert!(client.reconnecting.is_none());
+    }
+
+    #[test]
+    fn test_predict() {
+        let mut realm = ecs::Realm::new();
+        realm.spawn_player(1);
+        let mut base = ecs::ServerSnapshot::new();
+        let delta = realm.with_camera_for(1, |realm| ecs::ServerDelta::diff(&mut base, realm));
+        let message = rtc::encode_message(&rtc::ServerMessage::Delta {
+            seq: 0,
+            base: None,
+            tick: 0,
+            delta: delta.into_delta(),
+            checksum: None,
+        });
+
+        let mut client = Client::new();
+        client.recv_from_app(ClientMessageFromApp::Connected(fake(
+            rtc::SessionState::Connected,
+        )));
+        client.recv_from_server(&message);
+
+        let aim = Num::from_num(1);
+        let right = ecs::Input::new(V2::new(1, 0), aim, 0);
+        client.set_input(right);
+        client.sent_inputs.extend([right; 10]);
+        // no interpolation delay yet, so only a round trip's worth is pending
+        let rtt = 2.5 * ecs::TICK_DURATION.as_secs_f64() * 1000.0;
+        client.net_stats.rtt_ms.push(rtt as f32);
+        assert_eq!(client.pending_inputs(), 3);
+
+        let step = right.movement() * ecs::PLAYER_SPEED;
+        let mut view = client.view().unwrap();
+        let (_, (_, pos, rot)) = view.query_mut::<PredictQ>().into_iter().next().unwrap();
+        assert_eq!(pos.xy, V2::default() + step + step + step);
+        assert_eq!(rot.rad, aim);
+    }
 }
@@ -0,0 +1,162 @@
+use std::collections::VecDeque;
+
+use instant::{Duration, Instant};
+
+use archive_engine::*;
+
+// pongs kept for filtering
+const SYNC_SAMPLES: usize = 16;
+// how fast the estimate may drift towards a new offset, in seconds per
+// second, so ticks never visibly run backwards
+const MAX_SLEW: f64 = 0.05;
+// past this the estimate jumps instead of slewing
+const MAX_SLEW_ERROR: f64 = 0.25;
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    rtt: f64,
+    // server seconds minus client seconds
+    offset: f64,
+}
+
+// estimates the server's clock from pongs. the server's time at a pong
+// is its tick, and the pong took about half the rtt to get here
+#[derive(Debug, Default)]
+pub struct ClockSync {
+    // client seconds are measured from here, the first pong
+    epoch: Option<Instant>,
+    samples: VecDeque<Sample>,
+    // where the samples say the offset should be
+    target: Option<f64>,
+    // the offset in use, slewing towards target
+    offset: Option<f64>,
+    last_update: Option<Instant>,
+}
+
+impl ClockSync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn secs(&mut self, now: Instant) -> f64 {
+        let epoch = *self.epoch.get_or_insert(now);
+        now.duration_since(epoch).as_secs_f64()
+    }
+
+    pub fn record_pong(&mut self, rtt: Duration, tick: u64, now: Instant) {
+        let rtt = rtt.as_secs_f64();
+        let server = tick as f64 * ecs::TICK_DURATION.as_secs_f64();
+        let offset = server + rtt / 2.0 - self.secs(now);
+        self.samples.push_back(Sample { rtt, offset });
+        while self.samples.len() > SYNC_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.target = Some(filtered_offset(&self.samples));
+        // the first sample is all there is to go on
+        if self.offset.is_none() {
+            self.offset = self.target;
+        }
+    }
+
+    // slews the offset towards the target, call once a frame
+    pub fn update(&mut self, now: Instant) {
+        let dt = match self.last_update {
+            Some(last) => now.duration_since(last).as_secs_f64(),
+            None => 0.0,
+        };
+        self.last_update = Some(now);
+        let (target, offset) = match (self.target, self.offset.as_mut()) {
+            (Some(target), Some(offset)) => (target, offset),
+            _ => return,
+        };
+        let error = target - *offset;
+        if error.abs() > MAX_SLEW_ERROR {
+            *offset = target;
+        } else {
+            let step = MAX_SLEW * dt;
+            *offset += error.clamp(-step, step);
+        }
+    }
+
+    // median of recent round trips, in seconds
+    pub fn rtt(&self) -> Option<f64> {
+        median(self.samples.iter().map(|sample| sample.rtt).collect())
+    }
+
+    // server seconds minus client seconds, as currently applied
+    pub fn offset(&self) -> Option<f64> {
+        self.offset
+    }
+
+    // the (fractional) tick the server's realm is on right now
+    pub fn server_tick(&self, now: Instant) -> Option<f64> {
+        let (epoch, offset) = (self.epoch?, self.offset?);
+        let secs = now.duration_since(epoch).as_secs_f64();
+        Some((secs + offset) / ecs::TICK_DURATION.as_secs_f64())
+    }
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    Some(values[values.len() / 2])
+}
+
+// slow pongs are the ones that got queued somewhere, and queueing is
+// never symmetric, so only the faster half gets a say
+fn filtered_offset(samples: &VecDeque<Sample>) -> f64 {
+    let rtt = median(samples.iter().map(|sample| sample.rtt).collect()).unwrap_or(0.0);
+    let offsets = samples
+        .iter()
+        .filter(|sample| sample.rtt <= rtt)
+        .map(|sample| sample.offset)
+        .collect();
+    median(offsets).unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_sync() {
+        let tick_secs = ecs::TICK_DURATION.as_secs_f64();
+        let start = Instant::now();
+        let mut clock = ClockSync::new();
+        assert_eq!(clock.server_tick(start), None);
+
+        // the server is 1000 ticks in when we start, pongs take 50ms but
+        // every third ping gets stuck in a queue for another 200ms on the
+        // way there, which would throw the offset off by 100ms
+        let rtt = 0.05;
+        for i in 0..SYNC_SAMPLES {
+            let now = start + Duration::from_millis(100 * i as u64);
+            let elapsed = now.duration_since(start).as_secs_f64();
+            let delay = if i % 3 == 1 { 0.2 } else { 0.0 };
+            let tick_secs_sent = 1000.0 * tick_secs + elapsed - rtt / 2.0;
+            let tick = (tick_secs_sent / tick_secs).round() as u64;
+            let measured = Duration::from_secs_f64(rtt + delay);
+            clock.record_pong(measured, tick, now);
+            clock.update(now);
+        }
+        assert!((clock.rtt().unwrap() - rtt).abs() < 1e-6);
+
+        let now = start + Duration::from_secs(2);
+        clock.update(now);
+        let expected = 1000.0 + 2.0 / tick_secs;
+        // within a tick, the rounding above is worth half of one
+        assert!((clock.server_tick(now).unwrap() - expected).abs() < 1.0);
+
+        // a small change in offset slews instead of jumping
+        let before = clock.offset().unwrap();
+        let tick = clock.server_tick(now).unwrap() as u64 + 3;
+        for _ in 0..SYNC_SAMPLES {
+            clock.record_pong(Duration::from_secs_f64(rtt), tick, now);
+        }
+        clock.update(now + Duration::from_millis(100));
+        let moved = clock.offset().unwrap() - before;
+        assert!(moved > 0.0 && moved <= MAX_SLEW * 0.1 + 1e-9);
+    }
+}
@@ -1,3 +1,9 @@
 mod client;
+mod clock_sync;
+mod net_stats;
+mod replay_session;
 
 pub use client::*;
+pub use clock_sync::*;
+pub use net_stats::*;
+pub use replay_session::*;
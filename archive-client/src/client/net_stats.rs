@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use instant::{Duration, Instant};
+
+use archive_engine::*;
+
+// samples kept for the graphs
+pub const HISTORY_LEN: usize = 60;
+// how many of the newest delta seqs loss is measured over
+const LOSS_WINDOW: rtc::DeltaSeq = 120;
+const PING_INTERVAL: Duration = Duration::from_millis(500);
+// pings that take longer than this count as lost and are forgotten
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+const BANDWIDTH_INTERVAL: Duration = Duration::from_secs(1);
+
+// a fixed number of samples, oldest first
+#[derive(Debug, Default, Clone)]
+pub struct History(VecDeque<f32>);
+
+impl History {
+    pub fn push(&mut self, value: f32) {
+        self.0.push_back(value);
+        while self.0.len() > HISTORY_LEN {
+            self.0.pop_front();
+        }
+    }
+    pub fn values(&self) -> impl Iterator<Item = f32> + '_ {
+        self.0.iter().copied()
+    }
+    pub fn latest(&self) -> Option<f32> {
+        self.0.back().copied()
+    }
+    pub fn max(&self) -> f32 {
+        self.values().fold(0.0, f32::max)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct NetStats {
+    // milliseconds, one sample per pong
+    pub rtt_ms: History,
+    // fraction of deltas lost, one sample per second
+    pub loss: History,
+    // bytes per second, one sample per second
+    pub bytes_in: History,
+    pub bytes_out: History,
+    // snapshots that didn't match the server's checksum
+    pub desyncs: u32,
+
+    next_ping: rtc::PingId,
+    // pings waiting on a pong, by when they went out
+    pings: BTreeMap<rtc::PingId, Instant>,
+    last_ping: Option<Instant>,
+    // the newest delta seqs we got, to count the gaps between them
+    seqs: BTreeSet<rtc::DeltaSeq>,
+    // bytes since the start of the current second
+    second_start: Option<Instant>,
+    second_in: usize,
+    second_out: usize,
+}
+
+impl NetStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_in(&mut self, bytes: usize) {
+        self.second_in += bytes;
+    }
+    pub fn record_out(&mut self, bytes: usize) {
+        self.second_out += bytes;
+    }
+    pub fn record_seq(&mut self, seq: rtc::DeltaSeq) {
+        self.seqs.insert(seq);
+        let newest = *self.seqs.iter().next_back().unwrap();
+        let oldest = newest.saturating_sub(LOSS_WINDOW - 1);
+        self.seqs = self.seqs.split_off(&oldest);
+    }
+
+    // the id of a ping to send, if one is due
+    pub fn poll_ping(&mut self, now: Instant) -> Option<rtc::PingId> {
+        if let Some(last) = self.last_ping {
+            if now.duration_since(last) < PING_INTERVAL {
+                return None;
+            }
+        }
+        self.pings
+            .retain(|_, &mut sent| now.duration_since(sent) < PING_TIMEOUT);
+        let id = self.next_ping;
+        self.next_ping = self.next_ping.wrapping_add(1);
+        self.pings.insert(id, now);
+        self.last_ping = Some(now);
+        Some(id)
+    }
+    // the round trip, unless the id is a duplicate or timed out
+    pub fn record_pong(&mut self, id: rtc::PingId, now: Instant) -> Option<Duration> {
+        let sent = self.pings.remove(&id)?;
+        let rtt = now.duration_since(sent);
+        self.rtt_ms.push(rtt.as_secs_f32() * 1000.0);
+        Some(rtt)
+    }
+
+    // fraction of the seqs in the window that never showed up
+    pub fn current_loss(&self) -> f32 {
+        let (oldest, newest) = match (self.seqs.iter().next(), self.seqs.iter().next_back()) {
+            (Some(&oldest), Some(&newest)) => (oldest, newest),
+            _ => return 0.0,
+        };
+        let expected = (newest - oldest + 1) as f32;
+        1.0 - self.seqs.len() as f32 / expected
+    }
+
+    // closes out the current second once it's over
+    pub fn update(&mut self, now: Instant) {
+        let start = *self.second_start.get_or_insert(now);
+        let elapsed = now.duration_since(start);
+        if elapsed < BANDWIDTH_INTERVAL {
+            return;
+        }
+        let secs = elapsed.as_secs_f32();
+        self.bytes_in.push(self.second_in as f32 / secs);
+        self.bytes_out.push(self.second_out as f32 / secs);
+        self.loss.push(self.current_loss());
+        self.second_in = 0;
+        self.second_out = 0;
+        self.second_start = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_net_stats() {
+        let start = Instant::now();
+        let mut stats = NetStats::new();
+
+        let id = stats.poll_ping(start).unwrap();
+        assert_eq!(stats.poll_ping(start + PING_INTERVAL / 2), None);
+        let rtt = stats.record_pong(id, start + Duration::from_millis(40));
+        assert_eq!(rtt, Some(Duration::from_millis(40)));
+        // a duplicate pong doesn't count twice
+        assert_eq!(
+            stats.record_pong(id, start + Duration::from_millis(80)),
+            None
+        );
+        assert_eq!(stats.rtt_ms.values().count(), 1);
+        assert!((stats.rtt_ms.latest().unwrap() - 40.0).abs() < 0.5);
+
+        // every fourth delta goes missing
+        for seq in (0..200).filter(|seq| seq % 4 != 3) {
+            stats.record_seq(seq);
+        }
+        assert!((stats.current_loss() - 0.25).abs() < 0.01);
+
+        stats.update(start);
+        stats.record_in(1000);
+        stats.record_out(200);
+        stats.update(start + BANDWIDTH_INTERVAL / 2);
+        assert_eq!(stats.bytes_in.latest(), None);
+        stats.update(start + BANDWIDTH_INTERVAL);
+        assert_eq!(stats.bytes_in.latest(), Some(1000.0));
+        assert_eq!(stats.bytes_out.latest(), Some(200.0));
+
+        for _ in 0..HISTORY_LEN * 2 {
+            stats.rtt_ms.push(1.0);
+        }
+        assert_eq!(stats.rtt_ms.values().count(), HISTORY_LEN);
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::VecDeque;
+use std::sync::mpsc;
+
+use instant::Instant;
+
+use archive_engine::*;
+
+// plays what the server sent one client back through Client, at the
+// pace it was sent, without a server. anything the client sends is
+// dropped
+pub struct ReplaySession {
+    // by the arena send they went out on, one a tick
+    messages: VecDeque<(u64, Vec<u8>)>,
+    first_tick: u64,
+    started: Option<Instant>,
+}
+
+impl ReplaySession {
+    pub fn new(replay: &ecs::Replay, client_id: rtc::ClientId) -> Result<Self, rtc::JoinError> {
+        // messages from another build won't decode
+        rtc::ProtocolInfo::check(replay.protocol)?;
+        let messages: VecDeque<_> = replay
+            .sent_to(client_id)
+            .into_iter()
+            .map(|(tick, message)| (tick, message.to_vec()))
+            .collect();
+        let first_tick = messages.front().map_or(0, |&(tick, _)| tick);
+        Ok(ReplaySession {
+            messages,
+            first_tick,
+            started: None,
+        })
+    }
+
+    // the next message if the recording is past it by now
+    fn recv_at(&mut self, now: Instant) -> Result<Vec<u8>, mpsc::TryRecvError> {
+        let started = *self.started.get_or_insert(now);
+        let elapsed = now.duration_since(started).as_secs_f64();
+        let tick = self.first_tick + (elapsed / ecs::TICK_DURATION.as_secs_f64()) as u64;
+        match self.messages.front() {
+            Some(&(sent, _)) if sent <= tick => Ok(self.messages.pop_front().unwrap().1),
+            Some(_) => Err(mpsc::TryRecvError::Empty),
+            None => Err(mpsc::TryRecvError::Disconnected),
+        }
+    }
+}
+
+impl rtc::RtcSession for ReplaySession {
+    fn get_state(&self) -> rtc::SessionState {
+        if self.messages.is_empty() {
+            rtc::SessionState::Closed
+        } else {
+            rtc::SessionState::Connected
+        }
+    }
+    fn close(&self) {}
+    fn send(&self, _msg: Vec<u8>) -> SharedFuture<bool> {
+        Box::pin(futures::future::ready(true))
+    }
+    fn try_recv(&mut self) -> Result<Vec<u8>, mpsc::TryRecvError> {
+        self.recv_at(Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rtc::RtcSession;
+
+    #[test]
+    fn test_replay_session() {
+        let mut realm = ecs::Realm::new();
+        realm.start_recording(true).unwrap();
+        realm.spawn_player(1);
+        for i in 0..3u8 {
+            realm.record_sent(i as u64, 1, &[i]);
+            realm.record_sent(i as u64, 2, &[100]);
+            // sends go on while the realm is paused, e.g. in the lobby
+            if i > 0 {
+                realm.run_systems();
+            }
+        }
+        let replay = realm.take_replay().unwrap();
+
+        let mut session = ReplaySession::new(&replay, 1).unwrap();
+        let start = Instant::now();
+        // the first tick's message is due right away, the next ones later
+        assert_eq!(session.recv_at(start), Ok(vec![0]));
+        assert_eq!(session.recv_at(start), Err(mpsc::TryRecvError::Empty));
+        assert_eq!(session.get_state(), rtc::SessionState::Connected);
+
+        let later = start + ecs::TICK_DURATION * 2;
+        assert_eq!(session.recv_at(later), Ok(vec![1]));
+        assert_eq!(session.recv_at(later), Ok(vec![2]));
+        assert_eq!(
+            session.recv_at(later),
+            Err(mpsc::TryRecvError::Disconnected)
+        );
+        assert_eq!(session.get_state(), rtc::SessionState::Closed);
+
+        let mut stale = replay;
+        stale.protocol.version -= 1;
+        assert!(ReplaySession::new(&stale, 1).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use log::warn;
+use once_cell::sync::OnceCell;
+
+// one controller, laid out like an xbox pad. sticks are in [-1, 1] with y
+// pointing up, before any deadzone
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GamepadState {
+    pub left_stick: [f32; 2],
+    pub right_stick: [f32; 2],
+    pub fire: bool,
+    pub reload: bool,
+    pub interact: bool,
+}
+
+// implemented with gilrs on native and the Gamepad API in the browser
+pub trait GamepadImpl {
+    // the pad that was used most recently, None if there isn't one
+    fn poll(&mut self) -> Option<GamepadState>;
+}
+
+pub type Gamepad = Box<dyn GamepadImpl>;
+
+pub trait GamepadBuilderImpl: Sync + Send {
+    fn create(&self) -> Gamepad;
+}
+
+type GamepadBuilder = Box<dyn GamepadBuilderImpl>;
+
+static GAMEPAD_BUILDER: OnceCell<GamepadBuilder> = OnceCell::new();
+
+// gamepads are optional, so unlike random this is fine to leave unregistered
+pub fn new() -> Option<Gamepad> {
+    GAMEPAD_BUILDER.get().map(|builder| builder.create())
+}
+
+// sets the global builder for gamepads.
+pub fn register(builder: impl GamepadBuilderImpl + 'static) {
+    let res = GAMEPAD_BUILDER.set(Box::new(builder));
+    if res.is_err() {
+        warn!("already registered gamepad");
+    }
+}
+
+// zeroes the stick inside the deadzone and rescales the rest, so output
+// still starts at 0 and reaches 1. radial so diagonals aren't favored
+pub fn radial_deadzone(stick: [f32; 2], deadzone: f32) -> [f32; 2] {
+    let len = stick[0].hypot(stick[1]);
+    if len <= deadzone {
+        return [0.0, 0.0];
+    }
+    let scaled = ((len - deadzone) / (1.0 - deadzone)).min(1.0);
+    [stick[0] / len * scaled, stick[1] / len * scaled]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn len(stick: [f32; 2]) -> f32 {
+        stick[0].hypot(stick[1])
+    }
+
+    #[test]
+    fn test_radial_deadzone() {
+        assert_eq!(radial_deadzone([0.1, -0.1], 0.2), [0.0, 0.0]);
+        assert_eq!(radial_deadzone([1.0, 0.0], 0.2), [1.0, 0.0]);
+        // halfway out of the deadzone is half speed
+        let half = radial_deadzone([0.0, -0.6], 0.2);
+        assert!((half[1] + 0.5).abs() < 1e-6, "{:?}", half);
+        // corners of a square gate don't go past 1
+        assert!((len(radial_deadzone([1.0, 1.0], 0.2)) - 1.0).abs() < 1e-6);
+    }
+}
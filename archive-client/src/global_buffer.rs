@@ -62,10 +62,12 @@ impl GlobalBuffer {
         *self.global_data.borrow()
     }
     pub fn write(ctx: &GraphicsContext, global_data: Global) {
-        let GraphicsContext { queue, global, .. } = ctx;
-
-        *global.global_data.borrow_mut() = global_data;
+        ctx.global.update(&ctx.queue, global_data);
+    }
+    // for buffers besides ctx.global, like the hud's
+    pub fn update(&self, queue: &wgpu::Queue, global_data: Global) {
+        *self.global_data.borrow_mut() = global_data;
 
-        queue.write_buffer(&global.global_buffer, 0, bytemuck::bytes_of(&global_data));
+        queue.write_buffer(&self.global_buffer, 0, bytemuck::bytes_of(&global_data));
     }
 }
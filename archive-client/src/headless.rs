@@ -0,0 +1,277 @@
+use std::num::NonZeroU32;
+use std::path::Path;
+
+use futures::executor::block_on;
+use image::RgbaImage;
+
+use crate::*;
+
+// a texture to render frames into, for a GraphicsContext from
+// GraphicsContext::headless
+pub struct OffscreenTarget {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    size: wgpu::Extent3d,
+}
+
+impl OffscreenTarget {
+    // the same size and format as ctx.config
+    pub fn new(ctx: &GraphicsContext) -> Self {
+        let size = wgpu::Extent3d {
+            width: ctx.config.width,
+            height: ctx.config.height,
+            depth_or_array_layers: 1,
+        };
+        let texture = ctx.device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: ctx.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        OffscreenTarget {
+            texture,
+            view,
+            size,
+        }
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    // reads back whatever has been rendered so far. blocks on the gpu
+    pub fn capture(&self, ctx: &GraphicsContext) -> RgbaImage {
+        assert_eq!(
+            ctx.config.format, HEADLESS_FORMAT,
+            "can only read back rgba"
+        );
+        let GraphicsContext { device, queue, .. } = ctx;
+        let row_bytes = self.size.width * 4;
+        // copies need rows padded out to the alignment
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row_bytes = (row_bytes + align - 1) / align * align;
+
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (padded_row_bytes * self.size.height) as _,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder =
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_row_bytes),
+                    rows_per_image: None,
+                },
+            },
+            self.size,
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let mapped = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        block_on(mapped).expect("failed to map readback buffer");
+
+        let padded = slice.get_mapped_range();
+        let pixels = padded
+            .chunks(padded_row_bytes as usize)
+            .flat_map(|row| &row[..row_bytes as usize])
+            .copied()
+            .collect();
+        RgbaImage::from_raw(self.size.width, self.size.height, pixels)
+            .expect("readback is exactly one frame")
+    }
+}
+
+// renders one frame of the app and reads it back
+pub fn render_frame(app: &mut App, ctx: &GraphicsContext, target: &OffscreenTarget) -> RgbaImage {
+    app.render(ctx, target.view());
+    app.post_frame(ctx);
+    target.capture(ctx)
+}
+
+pub fn save_png(image: &RgbaImage, path: impl AsRef<Path>) -> image::ImageResult<()> {
+    image.save_with_format(path, image::ImageFormat::Png)
+}
+
+// sets up the launch config headless rendering needs, None without an
+// adapter
+#[cfg(test)]
+pub(crate) fn test_context(width: u32, height: u32) -> Option<GraphicsContext> {
+    launch_config::register(launch_config::LaunchConfig {
+        sample_count: 1,
+        bindings: Default::default(),
+    });
+    block_on(GraphicsContext::headless(width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const SIZE: u32 = 128;
+    // software and hardware rasterizers round a bit differently
+    const CHANNEL_TOLERANCE: u8 = 8;
+    // fraction of pixels allowed past the tolerance, for edges
+    const PIXEL_TOLERANCE: f32 = 0.005;
+
+    // compares against tests/goldens/<name>.png, which are only written
+    // with UPDATE_GOLDENS set so a missing one can't quietly pass. mismatches
+    // leave <name>.actual.png next to them to look at
+    fn check_golden(name: &str, actual: &RgbaImage) {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/goldens");
+        let path = dir.join(format!("{name}.png"));
+        if std::env::var_os("UPDATE_GOLDENS").is_some() {
+            std::fs::create_dir_all(&dir).unwrap();
+            save_png(actual, &path).unwrap();
+            return;
+        }
+        assert!(
+            path.exists(),
+            "no golden at {}, run with UPDATE_GOLDENS=1 to write it",
+            path.display()
+        );
+
+        let expected = image::open(&path).unwrap().to_rgba8();
+        let wrong = if expected.dimensions() != actual.dimensions() {
+            usize::MAX
+        } else {
+            expected
+                .pixels()
+                .zip(actual.pixels())
+                .filter(|(a, b)| {
+                    a.0.iter()
+                        .zip(b.0.iter())
+                        .any(|(&a, &b)| (a as i16 - b as i16).abs() > CHANNEL_TOLERANCE as i16)
+                })
+                .count()
+        };
+        let allowed = (PIXEL_TOLERANCE * (actual.width() * actual.height()) as f32) as usize;
+        if wrong > allowed {
+            let actual_path = dir.join(format!("{name}.actual.png"));
+            save_png(actual, &actual_path).unwrap();
+            panic!(
+                "{name} doesn't match its golden ({wrong} pixels off), see {}",
+                actual_path.display()
+            );
+        }
+    }
+
+    // None means there's nothing to render with, not even the fallback
+    // adapter, and the test should skip
+    fn context() -> Option<GraphicsContext> {
+        let ctx = test_context(SIZE, SIZE);
+        if ctx.is_none() {
+            eprintln!("skipping, no gpu adapter (not even a software one)");
+        }
+        ctx
+    }
+
+    fn clear(ctx: &GraphicsContext, target: &OffscreenTarget) {
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: target.view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        ctx.queue.submit(Some(encoder.finish()));
+    }
+
+    #[test]
+    fn golden_sprites() {
+        let ctx = match context() {
+            Some(ctx) => ctx,
+            None => return,
+        };
+        let target = OffscreenTarget::new(&ctx);
+        let global_data = Global {
+            mvp: cgmath::ortho(0.0, SIZE as f32, SIZE as f32, 0.0, -1.0, 1.0).into(),
+        };
+        GlobalBuffer::write(&ctx, global_data);
+
+        let mut painter = sprite::SpritePainter::init(&ctx, &ctx.global.global_bind_group_layout);
+        let (atlas, texture_handle) = sprite::AtlasBuilder::new()
+            .add(
+                sprite::MISSING_TEXTURE,
+                include_asset!("textures/missing.png"),
+            )
+            .add("player", include_asset!("textures/player.png"))
+            .add_image(
+                sprite::WHITE_TEXTURE,
+                RgbaImage::from_pixel(4, 4, image::Rgba([255; 4])),
+            )
+            .build(&ctx.device, &ctx.queue);
+        let texture = sprite::SpriteTexture::init(
+            &ctx.device,
+            &painter.texture_bind_group_layout,
+            texture_handle,
+        );
+
+        let white = atlas.texel(sprite::WHITE_TEXTURE);
+        let player = atlas.region("player");
+        let sprites = [
+            sprite::GpuSprite::rect([8.0, 8.0], [48.0, 24.0], 0xff3030d0, white),
+            sprite::GpuSprite::line([8.0, 120.0], [120.0, 64.0], 4.0, 0xff40c040, white),
+            sprite::GpuSprite {
+                position: [88.0, 32.0],
+                size: [40.0, 40.0],
+                rotation: 0.5,
+                color: 0xffffffff,
+                uv_rect: player.uv_rect,
+                layer: player.layer,
+                ..Default::default()
+            },
+        ];
+        let commands = painter.render(&ctx, target.view(), &texture, &sprites);
+        ctx.queue.submit(Some(commands));
+        painter.post_frame(&ctx);
+
+        check_golden("sprites", &target.capture(&ctx));
+    }
+
+    #[test]
+    fn golden_text() {
+        let ctx = match context() {
+            Some(ctx) => ctx,
+            None => return,
+        };
+        let target = OffscreenTarget::new(&ctx);
+        clear(&ctx, &target);
+
+        let mut painter = text::TextPainter::new(&ctx, &ctx.global.global_bind_group_layout);
+        let mut brush =
+            text::glyph_brush_from_font(&ctx, include_asset!("fonts/Rubik-Regular.ttf").to_vec());
+        let sections = [wgpu_glyph::Section {
+            screen_position: (8.0, 8.0),
+            text: vec![wgpu_glyph::Text::new("archive 0123")
+                .with_color([1.0, 1.0, 1.0, 1.0])
+                .with_scale(24.0)],
+            ..Default::default()
+        }];
+        let commands = painter.render(&ctx, target.view(), &mut brush, &sections);
+        ctx.queue.submit(Some(commands));
+        painter.post_frame(&ctx);
+
+        check_golden("text", &target.capture(&ctx));
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::VecDeque;
+
+use archive_engine::*;
+use instant::{Duration, Instant};
+use wgpu::CommandBuffer;
+use wgpu_glyph::{HorizontalAlign, Layout, Section, Text, VerticalAlign};
+
+use crate::*;
+
+const KILL_FEED_TIME: Duration = Duration::from_secs(5);
+const KILL_FEED_LEN: usize = 5;
+
+// sizes are fractions of the window height, so the hud scales with it
+const MARGIN: f32 = 0.03;
+const BAR_WIDTH: f32 = 0.35;
+const BAR_HEIGHT: f32 = 0.03;
+const TEXT_SIZE: f32 = 0.035;
+const LINE_SPACING: f32 = 1.2;
+
+// rgba, red in the low byte
+const BAR_BACK: u32 = 0xc0202020;
+const BAR_HEALTHY: u32 = 0xff40c040;
+const BAR_LOW: u32 = 0xff3030d0;
+// below this fraction of health the bar turns red
+const LOW_HEALTH: f32 = 0.3;
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+pub type HudQ = (
+    &'static ecs::Camera,
+    Option<&'static ecs::Health>,
+    Option<&'static ecs::Weapon>,
+);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct HudStats {
+    // None while we don't have a player, e.g. after dying
+    pub health: Option<u16>,
+    pub weapon: Option<ecs::Weapon>,
+    pub players_alive: usize,
+}
+
+impl HudStats {
+    pub fn from_view(view: &mut ecs::Snapshot) -> Self {
+        let mut stats = HudStats::default();
+        // the camera is on our own player
+        if let Some((_, (_, health, weapon))) = view.query_mut::<HudQ>().into_iter().next() {
+            stats.health = health.map(|h| h.value());
+            stats.weapon = weapon.copied();
+        }
+        stats.players_alive = view.query_mut::<&ecs::Player>().into_iter().count();
+        stats
+    }
+}
+
+pub struct Hud {
+    // pixels, y down
+    global: GlobalBuffer,
+    quads: Vec<sprite::GpuSprite>,
+    // newest last, with when each entry goes away
+    kill_feed: VecDeque<(Instant, String)>,
+    // the text sections borrow these
+    health_text: String,
+    ammo_text: String,
+    players_text: String,
+    screen_size: [f32; 2],
+}
+
+impl Hud {
+    pub fn new(ctx: &GraphicsContext) -> Self {
+        Hud {
+            global: GlobalBuffer::new(&ctx.device),
+            quads: Vec::new(),
+            kill_feed: VecDeque::new(),
+            health_text: String::new(),
+            ammo_text: String::new(),
+            players_text: String::new(),
+            screen_size: [0.0, 0.0],
+        }
+    }
+
+    pub fn push_event(&mut self, event: ecs::GameEvent) {
+        let text = match event {
+            ecs::GameEvent::PlayerDied { client_id } => format!("player #{client_id} died"),
+        };
+        self.kill_feed
+            .push_back((Instant::now() + KILL_FEED_TIME, text));
+        while self.kill_feed.len() > KILL_FEED_LEN {
+            self.kill_feed.pop_front();
+        }
+    }
+
+    fn expire_kill_feed(&mut self, now: Instant) {
+        while let Some((expires, _)) = self.kill_feed.front() {
+            if *expires > now {
+                break;
+            }
+            self.kill_feed.pop_front();
+        }
+    }
+
+    // lays everything out for this frame's window size
+    pub fn update(&mut self, ctx: &GraphicsContext, atlas: &sprite::Atlas, stats: HudStats) {
+        self.expire_kill_feed(Instant::now());
+
+        let [width, height] = [ctx.config.width as f32, ctx.config.height as f32];
+        self.screen_size = [width, height];
+        let global_data = Global {
+            mvp: cgmath::ortho(0.0, width, height, 0.0, -1.0, 1.0).into(),
+        };
+        self.global.update(&ctx.queue, global_data);
+
+        self.quads.clear();
+        if let Some(health) = stats.health {
+            let fraction = (health as f32 / ecs::PLAYER_HEALTH as f32).clamp(0.0, 1.0);
+            let white = atlas.texel(sprite::WHITE_TEXTURE);
+            let [x, y] = [MARGIN * height, height - (MARGIN + BAR_HEIGHT) * height];
+            let [w, h] = [BAR_WIDTH * height, BAR_HEIGHT * height];
+            let fill = if fraction < LOW_HEALTH {
+                BAR_LOW
+            } else {
+                BAR_HEALTHY
+            };
+            let quad = |w: f32, color: u32| sprite::GpuSprite::rect([x, y], [w, h], color, white);
+            self.quads.push(quad(w, BAR_BACK));
+            self.quads.push(quad(w * fraction, fill));
+        }
+
+        self.health_text = stats.health.map_or(String::new(), |h| h.to_string());
+        self.ammo_text = stats.weapon.map_or(String::new(), |weapon| {
+            format!("{} / {}", weapon.ammo, weapon.magazine)
+        });
+        self.players_text = format!("{} alive", stats.players_alive);
+    }
+
+    pub fn sections(&self) -> Vec<Section<'_>> {
+        // both have a Center, so that one is spelled out
+        use HorizontalAlign::{Left, Right};
+        use VerticalAlign::{Bottom, Top};
+
+        let [width, height] = self.screen_size;
+        let margin = MARGIN * height;
+        let scale = TEXT_SIZE * height;
+        let mut sections = Vec::new();
+
+        if !self.health_text.is_empty() {
+            let x = margin + BAR_WIDTH * height + margin / 2.0;
+            let y = height - (MARGIN + BAR_HEIGHT / 2.0) * height;
+            let align = (Left, VerticalAlign::Center);
+            sections.push(label([x, y], align, &self.health_text, scale));
+        }
+        if !self.ammo_text.is_empty() {
+            let position = [width - margin, height - margin];
+            sections.push(label(position, (Right, Bottom), &self.ammo_text, scale));
+        }
+        let position = [width - margin, margin];
+        sections.push(label(position, (Right, Top), &self.players_text, scale));
+        // the kill feed goes under the player count, newest at the bottom
+        for (i, (_, line)) in self.kill_feed.iter().enumerate() {
+            let y = margin + (i + 1) as f32 * scale * LINE_SPACING;
+            sections.push(label([width - margin, y], (Right, Top), line, scale));
+        }
+        sections
+    }
+
+    pub fn render(
+        &self,
+        ctx: &GraphicsContext,
+        view: &wgpu::TextureView,
+        sprite_painter: &mut sprite::SpritePainter,
+        sprite_texture: &sprite::SpriteTexture,
+    ) -> CommandBuffer {
+        sprite_painter.render_overlay(ctx, view, &self.global, sprite_texture, &self.quads)
+    }
+}
+
+pub(crate) fn label(
+    position: [f32; 2],
+    (h_align, v_align): (HorizontalAlign, VerticalAlign),
+    text: &str,
+    scale: f32,
+) -> Section<'_> {
+    Section {
+        screen_position: (position[0], position[1]),
+        layout: Layout::default_single_line()
+            .h_align(h_align)
+            .v_align(v_align),
+        text: vec![Text::new(text).with_color(TEXT_COLOR).with_scale(scale)],
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_from_view() {
+        let mut realm = ecs::Realm::new();
+        realm.spawn_player(1);
+        realm.spawn_player(2);
+        let mut view = realm.with_camera_for(2, |realm| {
+            let mut base = ecs::ServerSnapshot::new();
+            let delta = ecs::ServerDelta::diff(&mut base, realm).into_delta();
+            delta.apply(&mut ecs::Snapshot::new())
+        });
+
+        let stats = HudStats::from_view(&mut view);
+        assert_eq!(stats.health, Some(ecs::PLAYER_HEALTH));
+        assert_eq!(stats.weapon, Some(ecs::Weapon::new(ecs::PLAYER_MAGAZINE)));
+        assert_eq!(stats.players_alive, 2);
+
+        // no camera, no player of our own
+        let stats = HudStats::from_view(&mut ecs::Snapshot::new());
+        assert_eq!(stats, HudStats::default());
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::{HashMap, HashSet};
+
+use archive_engine::*;
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
+
+use crate::camera::Camera;
+use crate::gamepad::{self, radial_deadzone, GamepadState};
+
+// 1 / sqrt(2), so moving diagonally isn't faster
+const DIAGONAL: Num = mk_num!(0.70710678118654752);
+
+// sticks drift, and aim needs a bigger deadzone since letting go of the
+// stick shouldn't snap the aim somewhere else
+const MOVE_DEADZONE: f32 = 0.2;
+const AIM_DEADZONE: f32 = 0.4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Fire,
+    Reload,
+    Interact,
+    // client only, never sent to the server
+    ToggleNetOverlay,
+}
+
+const BUTTONS: [(Action, ecs::Buttons); 3] = [
+    (Action::Fire, ecs::Input::FIRE),
+    (Action::Reload, ecs::Input::RELOAD),
+    (Action::Interact, ecs::Input::INTERACT),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+// everything that triggers each action. in json it looks like
+// {"Fire": [{"Mouse": "Left"}, {"Key": "Space"}]}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bindings(HashMap<Action, Vec<Binding>>);
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Action::*;
+        use Binding::*;
+        use VirtualKeyCode as K;
+        Bindings(HashMap::from([
+            (MoveUp, vec![Key(K::W), Key(K::Up)]),
+            (MoveDown, vec![Key(K::S), Key(K::Down)]),
+            (MoveLeft, vec![Key(K::A), Key(K::Left)]),
+            (MoveRight, vec![Key(K::D), Key(K::Right)]),
+            (Fire, vec![Mouse(MouseButton::Left)]),
+            (Reload, vec![Key(K::R)]),
+            (Interact, vec![Key(K::E)]),
+            (ToggleNetOverlay, vec![Key(K::F3)]),
+        ]))
+    }
+}
+
+impl Bindings {
+    // actions the json leaves out keep their default bindings
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        let overrides: HashMap<Action, Vec<Binding>> = serde_json::from_str(json)?;
+        let mut bindings = Self::default();
+        bindings.0.extend(overrides);
+        Ok(bindings)
+    }
+    fn triggers(&self, action: Action) -> &[Binding] {
+        self.0.get(&action).map_or(&[][..], Vec::as_slice)
+    }
+    fn actions_for(&self, binding: Binding) -> impl Iterator<Item = Action> + '_ {
+        self.0
+            .iter()
+            .filter(move |(_, triggers)| triggers.contains(&binding))
+            .map(|(&action, _)| action)
+    }
+}
+
+// whichever was touched last drives the player
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Device {
+    #[default]
+    KeyboardMouse,
+    Gamepad,
+}
+
+#[derive(Default)]
+pub struct InputState {
+    bindings: Bindings,
+    held: HashSet<Binding>,
+    // actions whose binding went down since the last take_presses
+    presses: Vec<Action>,
+    // in pixels, None until the cursor first moves over the window
+    cursor: Option<[f32; 2]>,
+
+    active: Device,
+    gamepad: Option<gamepad::Gamepad>,
+    // from the last poll, with deadzones applied
+    pad: GamepadState,
+    // the stick's last direction outside the deadzone, in radians
+    pad_aim: f32,
+}
+
+impl InputState {
+    pub fn new(bindings: Bindings) -> Self {
+        InputState {
+            bindings,
+            gamepad: gamepad::new(),
+            ..Default::default()
+        }
+    }
+
+    pub fn active_device(&self) -> Device {
+        self.active
+    }
+
+    // returns false for events that aren't input
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        let handled = match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => {
+                self.set_held(Binding::Key(*key), *state);
+                true
+            }
+            WindowEvent::MouseInput { button, state, .. } => {
+                self.set_held(Binding::Mouse(*button), *state);
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = Some([position.x as f32, position.y as f32]);
+                true
+            }
+            // we never hear about releases while unfocused
+            WindowEvent::Focused(false) => {
+                self.held.clear();
+                return true;
+            }
+            _ => false,
+        };
+        if handled {
+            self.active = Device::KeyboardMouse;
+        }
+        handled
+    }
+
+    // call once a frame, before sample
+    pub fn poll_gamepad(&mut self) {
+        let state = match self.gamepad.as_mut().and_then(|pad| pad.poll()) {
+            Some(state) => state,
+            // unplugged, hand control back
+            None => {
+                self.pad = Default::default();
+                self.active = Device::KeyboardMouse;
+                return;
+            }
+        };
+        self.pad = GamepadState {
+            left_stick: radial_deadzone(state.left_stick, MOVE_DEADZONE),
+            right_stick: radial_deadzone(state.right_stick, AIM_DEADZONE),
+            ..state
+        };
+        let [x, y] = self.pad.right_stick;
+        if [x, y] != [0.0, 0.0] {
+            self.pad_aim = y.atan2(x);
+        }
+        let GamepadState {
+            left_stick,
+            right_stick,
+            fire,
+            reload,
+            interact,
+        } = self.pad;
+        let touched = left_stick != [0.0, 0.0] || right_stick != [0.0, 0.0];
+        if touched || fire || reload || interact {
+            self.active = Device::Gamepad;
+        }
+    }
+
+    fn set_held(&mut self, binding: Binding, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                // key repeat sends more presses without releases
+                if self.held.insert(binding) {
+                    self.presses.extend(self.bindings.actions_for(binding));
+                }
+            }
+            ElementState::Released => {
+                self.held.remove(&binding);
+            }
+        }
+    }
+
+    // for toggles, which care about presses and not about holding
+    pub fn take_presses(&mut self) -> Vec<Action> {
+        std::mem::take(&mut self.presses)
+    }
+
+    pub fn held(&self, action: Action) -> bool {
+        let triggers = self.bindings.triggers(action);
+        triggers.iter().any(|binding| self.held.contains(binding))
+    }
+
+    // the input for this tick, from whichever device is active
+    pub fn sample(&self, camera: &Camera, screen_size: [f32; 2]) -> ecs::Input {
+        match self.active {
+            Device::KeyboardMouse => self.sample_keyboard_mouse(camera, screen_size),
+            Device::Gamepad => self.sample_gamepad(),
+        }
+    }
+
+    // the camera is centered on the player, so aim points from the middle
+    // of the screen to the cursor
+    fn sample_keyboard_mouse(&self, camera: &Camera, screen_size: [f32; 2]) -> ecs::Input {
+        let axis = |neg, pos| self.held(pos) as i32 - self.held(neg) as i32;
+        let x = axis(Action::MoveLeft, Action::MoveRight);
+        let y = axis(Action::MoveDown, Action::MoveUp);
+        let mut movement = V2::new(x, y);
+        if x != 0 && y != 0 {
+            movement *= DIAGONAL;
+        }
+
+        let aim = match self.cursor {
+            Some(cursor) => {
+                let target = camera.screen_to_world(cursor, screen_size);
+                let center = camera.center();
+                (target[1] - center[1]).atan2(target[0] - center[0])
+            }
+            None => 0.0,
+        };
+
+        let mut buttons = 0;
+        for (action, button) in BUTTONS {
+            if self.held(action) {
+                buttons |= button;
+            }
+        }
+        ecs::Input::new(movement, Num::from_num(aim), buttons)
+    }
+
+    fn sample_gamepad(&self) -> ecs::Input {
+        let [x, y] = self.pad.left_stick;
+        let movement = V2::new(x, y);
+
+        let mut buttons = 0;
+        for (held, button) in [
+            (self.pad.fire, ecs::Input::FIRE),
+            (self.pad.reload, ecs::Input::RELOAD),
+            (self.pad.interact, ecs::Input::INTERACT),
+        ] {
+            if held {
+                buttons |= button;
+            }
+        }
+        ecs::Input::new(movement, Num::from_num(self.pad_aim), buttons)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, rc::Rc};
+
+    const SCREEN: [f32; 2] = [800.0, 600.0];
+
+    #[test]
+    fn test_sample() {
+        let mut state = InputState::default();
+        state.set_held(Binding::Key(VirtualKeyCode::W), ElementState::Pressed);
+        state.set_held(Binding::Key(VirtualKeyCode::Right), ElementState::Pressed);
+        state.set_held(Binding::Mouse(MouseButton::Left), ElementState::Pressed);
+        // straight down from the middle of the screen
+        state.cursor = Some([400.0, 500.0]);
+
+        let input = state.sample(&Camera::new(), SCREEN);
+        assert_eq!(
+            input.movement(),
+            V2 {
+                x: DIAGONAL,
+                y: DIAGONAL
+            }
+        );
+        let aim = input.aim().0.to_num::<f32>();
+        assert!((aim + std::f32::consts::FRAC_PI_2).abs() < 1e-3, "{}", aim);
+        assert!(input.pressed(ecs::Input::FIRE));
+        assert!(!input.pressed(ecs::Input::RELOAD));
+
+        state.set_held(Binding::Key(VirtualKeyCode::W), ElementState::Released);
+        let input = state.sample(&Camera::new(), SCREEN);
+        assert_eq!(input.movement(), V2::new(1, 0));
+    }
+
+    struct FakeGamepad(Rc<Cell<Option<GamepadState>>>);
+    impl gamepad::GamepadImpl for FakeGamepad {
+        fn poll(&mut self) -> Option<GamepadState> {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn test_switch_devices() {
+        let pad = Rc::new(Cell::new(None));
+        let mut state = InputState {
+            gamepad: Some(Box::new(FakeGamepad(pad.clone()))),
+            ..Default::default()
+        };
+        state.set_held(Binding::Key(VirtualKeyCode::D), ElementState::Pressed);
+
+        // a resting stick doesn't steal control
+        pad.set(Some(GamepadState {
+            left_stick: [0.05, -0.1],
+            ..Default::default()
+        }));
+        state.poll_gamepad();
+        assert_eq!(state.active_device(), Device::KeyboardMouse);
+        assert_eq!(
+            state.sample(&Camera::new(), SCREEN).movement(),
+            V2::new(1, 0)
+        );
+
+        pad.set(Some(GamepadState {
+            left_stick: [0.0, 1.0],
+            right_stick: [-1.0, 0.0],
+            fire: true,
+            ..Default::default()
+        }));
+        state.poll_gamepad();
+        assert_eq!(state.active_device(), Device::Gamepad);
+        let input = state.sample(&Camera::new(), SCREEN);
+        assert_eq!(input.movement(), V2::new(0, 1));
+        let aim = input.aim().0.to_num::<f32>();
+        assert!((aim - std::f32::consts::PI).abs() < 1e-3, "{}", aim);
+        assert!(input.pressed(ecs::Input::FIRE));
+
+        // letting go of the aim stick keeps the last aim
+        pad.set(Some(GamepadState::default()));
+        state.poll_gamepad();
+        let aim = state.sample(&Camera::new(), SCREEN).aim().0.to_num::<f32>();
+        assert!((aim - std::f32::consts::PI).abs() < 1e-3, "{}", aim);
+
+        // unplugging hands control back
+        pad.set(None);
+        state.poll_gamepad();
+        assert_eq!(state.active_device(), Device::KeyboardMouse);
+    }
+
+    #[test]
+    fn test_bindings_from_json() {
+        let bindings = Bindings::from_json(r#"{"Fire": [{"Key": "Space"}]}"#).unwrap();
+        let mut state = InputState::new(bindings);
+        state.set_held(Binding::Mouse(MouseButton::Left), ElementState::Pressed);
+        assert!(!state.held(Action::Fire));
+        state.set_held(Binding::Key(VirtualKeyCode::Space), ElementState::Pressed);
+        state.set_held(Binding::Key(VirtualKeyCode::Space), ElementState::Pressed);
+        assert!(state.held(Action::Fire));
+        assert_eq!(state.take_presses(), vec![Action::Fire]);
+        // untouched actions keep their defaults
+        assert_eq!(
+            state.bindings.triggers(Action::Reload),
+            &[Binding::Key(VirtualKeyCode::R)]
+        );
+    }
+}
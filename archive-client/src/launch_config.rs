@@ -6,6 +6,7 @@ use wgpu::{MultisampleState, RenderPassColorAttachment};
 
 pub struct LaunchConfig {
     pub sample_count: u32,
+    pub bindings: input::Bindings,
 }
 
 static LAUNCH_CONFIG: OnceCell<LaunchConfig> = OnceCell::new();
@@ -21,6 +22,10 @@ pub fn register(launch_config: LaunchConfig) {
     }
 }
 
+pub fn bindings() -> &'static input::Bindings {
+    &get().bindings
+}
+
 pub fn color_attachment<'a>(
     ctx: &'a GraphicsContext,
     view: &'a wgpu::TextureView,
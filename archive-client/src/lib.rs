@@ -1,9 +1,16 @@
 mod app;
 mod assets;
+pub mod camera;
 pub mod client;
 mod frame_counter;
+pub mod gamepad;
 mod global_buffer;
+pub mod headless;
+pub mod hud;
+pub mod input;
 pub mod launch_config;
+pub mod minimap;
+pub mod net_overlay;
 pub mod sprite;
 pub mod text;
 mod types;
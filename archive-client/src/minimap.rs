@@ -0,0 +1,348 @@
+use archive_engine::{ecs::Blueprint, *};
+use image::{Rgba, RgbaImage};
+use wgpu::CommandBuffer;
+
+use crate::*;
+
+// side length of the static layer texture
+const MAP_TEXELS: u32 = 256;
+// world units around the obstacles, so the edge ones aren't cut off
+const MAP_PADDING: f32 = 4.0;
+// world units shown when there are no obstacles to fit
+const EMPTY_MAP_SIZE: f32 = 64.0;
+
+// sizes are fractions of the window height, like the hud
+const SIZE: f32 = 0.25;
+const MARGIN: f32 = 0.03;
+// leaves room for the ammo counter underneath
+const BOTTOM: f32 = 0.1;
+const MARKER_SIZE: f32 = 0.012;
+
+const BACKGROUND: Rgba<u8> = Rgba([32, 32, 32, 192]);
+const OBSTACLE: Rgba<u8> = Rgba([150, 150, 150, 255]);
+// rgba, red in the low byte
+const LOCAL_COLOR: u32 = 0xff40e0ff;
+const TEAMMATE_COLOR: u32 = 0xff40c040;
+
+pub type StaticQ = (
+    &'static ecs::Position,
+    Option<&'static ecs::Scale>,
+    &'static ecs::Replicated,
+);
+pub type LocalQ = (
+    &'static ecs::Camera,
+    &'static ecs::Position,
+    &'static ecs::Player,
+);
+pub type PlayerQ = hecs::Without<ecs::Camera, (&'static ecs::Position, &'static ecs::Player)>;
+
+fn to_f32(num: Num) -> f32 {
+    num.0.to_num::<f32>()
+}
+
+// a square of the world, y up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MapBounds {
+    pub min: [f32; 2],
+    pub size: f32,
+}
+
+impl MapBounds {
+    // the smallest square around every obstacle, centered on them
+    pub fn fit(obstacles: &[Obstacle]) -> Self {
+        if obstacles.is_empty() {
+            let half = EMPTY_MAP_SIZE / 2.0;
+            return MapBounds {
+                min: [-half, -half],
+                size: EMPTY_MAP_SIZE,
+            };
+        }
+        let mut lo = [f32::MAX; 2];
+        let mut hi = [f32::MIN; 2];
+        for obstacle in obstacles {
+            for axis in 0..2 {
+                let half = obstacle.size[axis] / 2.0;
+                lo[axis] = lo[axis].min(obstacle.center[axis] - half);
+                hi[axis] = hi[axis].max(obstacle.center[axis] + half);
+            }
+        }
+        let size = (hi[0] - lo[0]).max(hi[1] - lo[1]) + 2.0 * MAP_PADDING;
+        let center = [(lo[0] + hi[0]) / 2.0, (lo[1] + hi[1]) / 2.0];
+        MapBounds {
+            min: [center[0] - size / 2.0, center[1] - size / 2.0],
+            size,
+        }
+    }
+
+    fn clamp(&self, point: [f32; 2]) -> [f32; 2] {
+        [
+            point[0].clamp(self.min[0], self.min[0] + self.size),
+            point[1].clamp(self.min[1], self.min[1] + self.size),
+        ]
+    }
+}
+
+// rotation is left out, it doesn't show at minimap scale
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Obstacle {
+    pub center: [f32; 2],
+    pub size: [f32; 2],
+}
+
+// the static entities in a snapshot, in a stable order
+pub fn find_obstacles(view: &mut ecs::Snapshot) -> Vec<Obstacle> {
+    let mut obstacles: Vec<_> = view
+        .query_mut::<StaticQ>()
+        .into_iter()
+        .filter(|(_, (_, _, repl))| repl.blueprint() == Some(Blueprint::Static))
+        .map(|(_, (pos, scale, repl))| Obstacle {
+            center: [to_f32(pos.xy.x), to_f32(pos.xy.y)],
+            size: sprite::world_size(repl.blueprint(), scale),
+        })
+        .collect();
+    obstacles.sort_by(|a, b| {
+        (a.center, a.size)
+            .partial_cmp(&(b.center, b.size))
+            .expect("positions are fixed point, never nan")
+    });
+    obstacles
+}
+
+// top row of the image is the top of the map, which is what the sprite
+// shader expects
+pub fn rasterize(obstacles: &[Obstacle], bounds: MapBounds) -> RgbaImage {
+    let mut image = RgbaImage::from_pixel(MAP_TEXELS, MAP_TEXELS, BACKGROUND);
+    let texels_per_unit = MAP_TEXELS as f32 / bounds.size;
+    let top = bounds.min[1] + bounds.size;
+    let to_texel = |n: f32| (n * texels_per_unit).clamp(0.0, MAP_TEXELS as f32) as u32;
+    for obstacle in obstacles {
+        let [cx, cy] = obstacle.center;
+        let [hw, hh] = [obstacle.size[0] / 2.0, obstacle.size[1] / 2.0];
+        let (x0, x1) = (
+            to_texel(cx - hw - bounds.min[0]),
+            to_texel(cx + hw - bounds.min[0]),
+        );
+        let (y0, y1) = (to_texel(top - (cy + hh)), to_texel(top - (cy - hh)));
+        // always at least a texel, so small obstacles don't vanish
+        for y in y0..y1.max(y0 + 1).min(MAP_TEXELS) {
+            for x in x0..x1.max(x0 + 1).min(MAP_TEXELS) {
+                image.put_pixel(x, y, OBSTACLE);
+            }
+        }
+    }
+    image
+}
+
+// maps bounds (world, y up) onto rect (pixels, y down), with the rest of
+// the world spilling over the rest of the screen
+pub fn projection(bounds: MapBounds, rect: [f32; 4], screen_size: [f32; 2]) -> [[f32; 4]; 4] {
+    let [x, y, size, _] = rect;
+    let units_per_pixel = bounds.size / size;
+    let left = bounds.min[0] - x * units_per_pixel;
+    let top = bounds.min[1] + bounds.size + y * units_per_pixel;
+    cgmath::ortho(
+        left,
+        left + screen_size[0] * units_per_pixel,
+        top - screen_size[1] * units_per_pixel,
+        top,
+        -1.0,
+        1.0,
+    )
+    .into()
+}
+
+struct StaticLayer {
+    // Client::statics_version it was drawn from
+    version: u64,
+    bounds: MapBounds,
+    texture: sprite::SpriteTexture,
+    // the whole texture stretched over bounds
+    quad: [sprite::GpuSprite; 1],
+}
+
+pub struct Minimap {
+    // world units, placed so the map lands in the corner
+    global: GlobalBuffer,
+    static_layer: Option<StaticLayer>,
+    markers: Vec<sprite::GpuSprite>,
+}
+
+impl Minimap {
+    pub fn new(ctx: &GraphicsContext) -> Self {
+        Minimap {
+            global: GlobalBuffer::new(&ctx.device),
+            static_layer: None,
+            markers: Vec::new(),
+        }
+    }
+
+    // forget the map, e.g. when there is nothing to show
+    pub fn clear(&mut self) {
+        self.static_layer = None;
+        self.markers.clear();
+    }
+
+    pub fn update(
+        &mut self,
+        ctx: &GraphicsContext,
+        sprite_painter: &sprite::SpritePainter,
+        atlas: &sprite::Atlas,
+        statics_version: u64,
+        view: &mut ecs::Snapshot,
+    ) {
+        // statics never move, but they can take a few deltas to all show
+        // up, so redraw whenever the set changes and not every frame
+        if self.static_layer.as_ref().map(|layer| layer.version) != Some(statics_version) {
+            let obstacles = find_obstacles(view);
+            self.static_layer = Some(Self::build_static_layer(
+                ctx,
+                sprite_painter,
+                statics_version,
+                &obstacles,
+            ));
+        }
+        let bounds = self.static_layer.as_ref().unwrap().bounds;
+
+        let [width, height] = [ctx.config.width as f32, ctx.config.height as f32];
+        let size = SIZE * height;
+        let rect = [
+            width - MARGIN * height - size,
+            height - BOTTOM * height - size,
+            size,
+            size,
+        ];
+        let global_data = Global {
+            mvp: projection(bounds, rect, [width, height]),
+        };
+        self.global.update(&ctx.queue, global_data);
+
+        // TODO the safe zone goes here once there is one
+        let white = atlas.texel(sprite::WHITE_TEXTURE);
+        let marker_size = MARKER_SIZE * height * bounds.size / size;
+        let marker = |pos: &ecs::Position, color: u32| {
+            let center = bounds.clamp([to_f32(pos.xy.x), to_f32(pos.xy.y)]);
+            let half = marker_size / 2.0;
+            let min = [center[0] - half, center[1] - half];
+            sprite::GpuSprite::rect(min, [marker_size, marker_size], color, white)
+        };
+        self.markers.clear();
+        let local = view
+            .query_mut::<LocalQ>()
+            .into_iter()
+            .next()
+            .map(|(_, (_, pos, player))| (*pos, player.team()));
+        if let Some((pos, team)) = local {
+            for (_, (pos, player)) in view.query_mut::<PlayerQ>() {
+                if player.team() == team {
+                    self.markers.push(marker(pos, TEAMMATE_COLOR));
+                }
+            }
+            // last, so it draws on top
+            self.markers.push(marker(&pos, LOCAL_COLOR));
+        }
+    }
+
+    fn build_static_layer(
+        ctx: &GraphicsContext,
+        sprite_painter: &sprite::SpritePainter,
+        version: u64,
+        obstacles: &[Obstacle],
+    ) -> StaticLayer {
+        let bounds = MapBounds::fit(obstacles);
+        let image = rasterize(obstacles, bounds);
+        let texture_handle = sprite::TextureHandle::from_layers(&ctx.device, &ctx.queue, &[image]);
+        let texture = sprite::SpriteTexture::init(
+            &ctx.device,
+            &sprite_painter.texture_bind_group_layout,
+            texture_handle,
+        );
+        let whole = sprite::AtlasRegion {
+            uv_rect: [0.0, 0.0, 1.0, 1.0],
+            layer: 0,
+        };
+        let quad = sprite::GpuSprite::rect(bounds.min, [bounds.size; 2], 0xffffffff, whole);
+        StaticLayer {
+            version,
+            bounds,
+            texture,
+            quad: [quad],
+        }
+    }
+
+    pub fn render(
+        &self,
+        ctx: &GraphicsContext,
+        view: &wgpu::TextureView,
+        sprite_painter: &mut sprite::SpritePainter,
+        sprite_texture: &sprite::SpriteTexture,
+    ) -> Vec<CommandBuffer> {
+        let layer = match &self.static_layer {
+            Some(layer) => layer,
+            None => return Vec::new(),
+        };
+        vec![
+            sprite_painter.render_overlay(ctx, view, &self.global, &layer.texture, &layer.quad),
+            sprite_painter.render_overlay(ctx, view, &self.global, sprite_texture, &self.markers),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Matrix4, Vector4};
+
+    #[test]
+    fn test_rasterize() {
+        let obstacles = [
+            Obstacle {
+                center: [0.0, 10.0],
+                size: [2.0, 2.0],
+            },
+            Obstacle {
+                center: [0.0, -10.0],
+                size: [2.0, 2.0],
+            },
+        ];
+        let bounds = MapBounds::fit(&obstacles);
+        assert_eq!(bounds.size, 22.0 + 2.0 * MAP_PADDING);
+        assert_eq!(bounds.min, [-bounds.size / 2.0, -bounds.size / 2.0]);
+
+        let image = rasterize(&obstacles, bounds);
+        let texel = |world: [f32; 2]| {
+            let scale = MAP_TEXELS as f32 / bounds.size;
+            let x = (world[0] - bounds.min[0]) * scale;
+            let y = (bounds.min[1] + bounds.size - world[1]) * scale;
+            *image.get_pixel(x as u32, y as u32)
+        };
+        assert_eq!(texel([0.0, 10.0]), OBSTACLE);
+        assert_eq!(texel([0.0, -10.0]), OBSTACLE);
+        assert_eq!(texel([0.0, 0.0]), BACKGROUND);
+        // north is the top row
+        assert_eq!(texel([0.0, 10.9]), OBSTACLE);
+        assert_eq!(*image.get_pixel(MAP_TEXELS / 2, 0), BACKGROUND);
+    }
+
+    #[test]
+    fn test_projection() {
+        let bounds = MapBounds {
+            min: [-8.0, 2.0],
+            size: 16.0,
+        };
+        let screen_size = [800.0, 600.0];
+        let rect = [600.0, 400.0, 150.0, 150.0];
+        let m: Matrix4<f32> = projection(bounds, rect, screen_size).into();
+        let to_pixels = |world: [f32; 2]| {
+            let clip = m * Vector4::new(world[0], world[1], 0.0, 1.0);
+            [
+                (clip.x + 1.0) / 2.0 * screen_size[0],
+                (1.0 - clip.y) / 2.0 * screen_size[1],
+            ]
+        };
+        let close =
+            |a: [f32; 2], b: [f32; 2]| (a[0] - b[0]).abs() < 1e-2 && (a[1] - b[1]).abs() < 1e-2;
+        // the top left of the map is the top left of the rect
+        assert!(close(to_pixels([-8.0, 18.0]), [600.0, 400.0]));
+        assert!(close(to_pixels([8.0, 2.0]), [750.0, 550.0]));
+    }
+}
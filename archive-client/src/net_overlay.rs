@@ -0,0 +1,236 @@
+use archive_engine::*;
+use wgpu::CommandBuffer;
+use wgpu_glyph::{HorizontalAlign, Section, VerticalAlign};
+
+use crate::client::{History, HISTORY_LEN};
+use crate::hud::label;
+use crate::*;
+
+// sizes are fractions of the window height, like the hud
+const LEFT: f32 = 0.03;
+// below the fps counter
+const TOP: f32 = 0.12;
+const TEXT_SIZE: f32 = 0.025;
+const LINE_SPACING: f32 = 1.2;
+const GRAPH_WIDTH: f32 = 0.35;
+const GRAPH_HEIGHT: f32 = 0.08;
+const GRAPH_GAP: f32 = 0.015;
+const LINE_WIDTH: f32 = 0.003;
+
+// rgba, red in the low byte
+const GRAPH_BACK: u32 = 0xa0202020;
+const RTT_COLOR: u32 = 0xff40c0ff;
+const LOSS_COLOR: u32 = 0xff3030d0;
+const IN_COLOR: u32 = 0xff40c040;
+const OUT_COLOR: u32 = 0xffd08040;
+
+// graphs never zoom in past these, so noise near zero stays flat
+const MIN_RTT_MS: f32 = 50.0;
+const MIN_LOSS: f32 = 0.05;
+const MIN_BYTES: f32 = 1024.0;
+
+pub struct NetOverlay {
+    visible: bool,
+    // pixels, y down
+    global: GlobalBuffer,
+    quads: Vec<sprite::GpuSprite>,
+    // top left corners in pixels, the text sections borrow these
+    labels: Vec<([f32; 2], String)>,
+    text_size: f32,
+}
+
+impl NetOverlay {
+    pub fn new(ctx: &GraphicsContext) -> Self {
+        NetOverlay {
+            visible: false,
+            global: GlobalBuffer::new(&ctx.device),
+            quads: Vec::new(),
+            labels: Vec::new(),
+            text_size: 0.0,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn update(
+        &mut self,
+        ctx: &GraphicsContext,
+        atlas: &sprite::Atlas,
+        client: &client::Client,
+    ) {
+        self.quads.clear();
+        self.labels.clear();
+        if !self.visible {
+            return;
+        }
+
+        let [width, height] = [ctx.config.width as f32, ctx.config.height as f32];
+        let global_data = Global {
+            mvp: cgmath::ortho(0.0, width, height, 0.0, -1.0, 1.0).into(),
+        };
+        self.global.update(&ctx.queue, global_data);
+        self.text_size = TEXT_SIZE * height;
+
+        let stats = client.net_stats();
+        let kb = |history: &History| history.latest().unwrap_or(0.0) / 1024.0;
+        let interp = match client.interp_delay() {
+            Some(ticks) => {
+                let ms = ticks * ecs::TICK_DURATION.as_secs_f64() * 1000.0;
+                format!("interp delay {ticks:.1} ticks ({ms:.0} ms)")
+            }
+            None => "interp delay -".to_string(),
+        };
+        let lines = [
+            match stats.rtt_ms.latest() {
+                Some(rtt) => format!("rtt {rtt:.0} ms"),
+                None => "rtt -".to_string(),
+            },
+            format!("loss {:.1}%", stats.current_loss() * 100.0),
+            format!(
+                "in {:.1} KB/s, out {:.1} KB/s",
+                kb(&stats.bytes_in),
+                kb(&stats.bytes_out)
+            ),
+            format!("snapshots buffered {}", client.buffered_snapshots()),
+            format!("desyncs {}", stats.desyncs),
+            match (client.server_tick(), client.clock().offset()) {
+                (Some(tick), Some(offset)) => {
+                    format!("server tick {tick:.1} (offset {:.0} ms)", offset * 1000.0)
+                }
+                _ => "server tick -".to_string(),
+            },
+            interp,
+            format!("predicting {} inputs ahead", client.pending_inputs()),
+        ];
+
+        let x = LEFT * height;
+        let mut y = TOP * height;
+        let line_height = self.text_size * LINE_SPACING;
+        for line in lines {
+            self.labels.push(([x, y], line));
+            y += line_height;
+        }
+
+        let white = atlas.texel(sprite::WHITE_TEXTURE);
+        // name, the histories it plots with their colors, and the least
+        // its scale goes up to
+        type Graph<'a> = (&'a str, &'a [(&'a History, u32)], f32);
+        let graphs: [Graph; 3] = [
+            ("rtt", &[(&stats.rtt_ms, RTT_COLOR)], MIN_RTT_MS),
+            ("loss", &[(&stats.loss, LOSS_COLOR)], MIN_LOSS),
+            (
+                "bandwidth in/out",
+                &[(&stats.bytes_in, IN_COLOR), (&stats.bytes_out, OUT_COLOR)],
+                MIN_BYTES,
+            ),
+        ];
+        let size = [GRAPH_WIDTH * height, GRAPH_HEIGHT * height];
+        for (title, histories, min_scale) in graphs {
+            y += GRAPH_GAP * height;
+            self.labels.push(([x, y], title.to_string()));
+            y += line_height;
+            graph(
+                &mut self.quads,
+                white,
+                [x, y],
+                size,
+                histories,
+                min_scale,
+                LINE_WIDTH * height,
+            );
+            y += size[1];
+        }
+    }
+
+    pub fn sections(&self) -> Vec<Section<'_>> {
+        let align = (HorizontalAlign::Left, VerticalAlign::Top);
+        self.labels
+            .iter()
+            .map(|(position, text)| label(*position, align, text, self.text_size))
+            .collect()
+    }
+
+    // None while hidden, so there's nothing to submit
+    pub fn render(
+        &self,
+        ctx: &GraphicsContext,
+        view: &wgpu::TextureView,
+        sprite_painter: &mut sprite::SpritePainter,
+        sprite_texture: &sprite::SpriteTexture,
+    ) -> Option<CommandBuffer> {
+        if !self.visible {
+            return None;
+        }
+        Some(sprite_painter.render_overlay(ctx, view, &self.global, sprite_texture, &self.quads))
+    }
+}
+
+// plots histories over a background, oldest on the left. they share a
+// vertical scale that fits the biggest sample
+fn graph(
+    quads: &mut Vec<sprite::GpuSprite>,
+    white: sprite::AtlasRegion,
+    min: [f32; 2],
+    size: [f32; 2],
+    histories: &[(&History, u32)],
+    min_scale: f32,
+    line_width: f32,
+) {
+    quads.push(sprite::GpuSprite::rect(min, size, GRAPH_BACK, white));
+
+    let scale = histories
+        .iter()
+        .map(|(history, _)| history.max())
+        .fold(min_scale, f32::max);
+    let step = size[0] / (HISTORY_LEN - 1) as f32;
+    let bottom = min[1] + size[1];
+    for (history, color) in histories {
+        let points: Vec<[f32; 2]> = history
+            .values()
+            .enumerate()
+            .map(|(i, value)| [min[0] + i as f32 * step, bottom - value / scale * size[1]])
+            .collect();
+        for pair in points.windows(2) {
+            quads.push(sprite::GpuSprite::line(
+                pair[0], pair[1], line_width, *color, white,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graph() {
+        let white = sprite::AtlasRegion {
+            uv_rect: [0.5, 0.5, 0.0, 0.0],
+            layer: 0,
+        };
+        let mut history = History::default();
+        for value in [0.0, 100.0, 50.0] {
+            history.push(value);
+        }
+        let mut quads = Vec::new();
+        graph(
+            &mut quads,
+            white,
+            [10.0, 20.0],
+            [590.0, 100.0],
+            &[(&history, RTT_COLOR)],
+            MIN_RTT_MS,
+            2.0,
+        );
+        // the background and a segment between each pair of samples
+        assert_eq!(quads.len(), 3);
+        let step = 590.0 / (HISTORY_LEN - 1) as f32;
+        // from the bottom left corner up to the top
+        let rise = &quads[1];
+        assert_eq!(rise.position, [10.0 + step / 2.0, 70.0]);
+        assert!((rise.size[0] - step.hypot(100.0)).abs() < 1e-3);
+        assert!(rise.rotation > 0.0);
+    }
+}
@@ -0,0 +1,192 @@
+use super::*;
+
+use std::collections::HashMap;
+
+use image::RgbaImage;
+
+// side length of each atlas layer. webgl2 only guarantees 2048
+pub const ATLAS_SIZE: u32 = 1024;
+// empty pixels around each image so linear filtering doesn't bleed
+// neighbors into each other
+const ATLAS_PADDING: u32 = 1;
+
+pub const MISSING_TEXTURE: &str = "missing";
+// solid white, for flat colored quads
+pub const WHITE_TEXTURE: &str = "white";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub layer: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+// shelf packing: images go left to right along a shelf as tall as the
+// tallest image on it, tallest images first. a new layer starts once a
+// shelf doesn't fit. returns placements in the same order as sizes
+pub fn pack(sizes: &[(u32, u32)], atlas_size: u32, padding: u32) -> (Vec<Placement>, u32) {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].1));
+
+    let mut placements = vec![
+        Placement {
+            layer: 0,
+            x: 0,
+            y: 0
+        };
+        sizes.len()
+    ];
+    let (mut layer, mut x, mut y, mut shelf_height) = (0, 0, 0, 0);
+    for i in order {
+        let (w, h) = (sizes[i].0 + 2 * padding, sizes[i].1 + 2 * padding);
+        assert!(
+            w <= atlas_size && h <= atlas_size,
+            "{}x{} image doesn't fit in the atlas",
+            sizes[i].0,
+            sizes[i].1
+        );
+        if x + w > atlas_size {
+            // next shelf
+            x = 0;
+            y += shelf_height;
+            shelf_height = 0;
+        }
+        if y + h > atlas_size {
+            // next layer
+            layer += 1;
+            x = 0;
+            y = 0;
+            shelf_height = 0;
+        }
+        placements[i] = Placement {
+            layer,
+            x: x + padding,
+            y: y + padding,
+        };
+        x += w;
+        shelf_height = shelf_height.max(h);
+    }
+    let layers = if sizes.is_empty() { 0 } else { layer + 1 };
+    (placements, layers)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRegion {
+    // x, y, width, height in uv space
+    pub uv_rect: [f32; 4],
+    pub layer: u32,
+}
+
+#[derive(Default)]
+pub struct AtlasBuilder {
+    images: Vec<(String, RgbaImage)>,
+}
+
+impl AtlasBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn add(self, name: &str, image_bytes: &[u8]) -> Self {
+        let image = image::load_from_memory(image_bytes).unwrap().to_rgba8();
+        self.add_image(name, image)
+    }
+    pub fn add_image(mut self, name: &str, image: RgbaImage) -> Self {
+        self.images.push((name.into(), image));
+        self
+    }
+    // the texture goes to a SpriteTexture, the Atlas is for looking up
+    // where each image ended up
+    pub fn build(self, device: &wgpu::Device, queue: &wgpu::Queue) -> (Atlas, TextureHandle) {
+        let sizes: Vec<_> = self.images.iter().map(|(_, im)| im.dimensions()).collect();
+        let (placements, layer_count) = pack(&sizes, ATLAS_SIZE, ATLAS_PADDING);
+
+        let mut layers: Vec<_> = (0..layer_count.max(1))
+            .map(|_| RgbaImage::new(ATLAS_SIZE, ATLAS_SIZE))
+            .collect();
+        let mut regions = HashMap::new();
+        for ((name, image), placement) in self.images.into_iter().zip(placements) {
+            let Placement { layer, x, y } = placement;
+            image::imageops::replace(&mut layers[layer as usize], &image, x, y);
+
+            let size = ATLAS_SIZE as f32;
+            let (w, h) = image.dimensions();
+            let region = AtlasRegion {
+                uv_rect: [
+                    x as f32 / size,
+                    y as f32 / size,
+                    w as f32 / size,
+                    h as f32 / size,
+                ],
+                layer,
+            };
+            regions.insert(name, region);
+        }
+
+        let texture_handle = TextureHandle::from_layers(device, queue, &layers);
+        (Atlas { regions }, texture_handle)
+    }
+}
+
+pub struct Atlas {
+    regions: HashMap<String, AtlasRegion>,
+}
+
+impl Atlas {
+    // falls back to the missing texture for unknown names
+    pub fn region(&self, name: &str) -> AtlasRegion {
+        match self.regions.get(name) {
+            Some(&region) => region,
+            None => self.regions[MISSING_TEXTURE],
+        }
+    }
+    // the middle texel of an image, so stretching it over a big quad
+    // doesn't filter in the padding around it
+    pub fn texel(&self, name: &str) -> AtlasRegion {
+        let AtlasRegion { uv_rect, layer } = self.region(name);
+        let [x, y, w, h] = uv_rect;
+        AtlasRegion {
+            uv_rect: [x + w / 2.0, y + h / 2.0, 0.0, 0.0],
+            layer,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(a: (Placement, (u32, u32)), b: (Placement, (u32, u32))) -> bool {
+        let ((pa, (wa, ha)), (pb, (wb, hb))) = (a, b);
+        pa.layer == pb.layer
+            && pa.x < pb.x + wb
+            && pb.x < pa.x + wa
+            && pa.y < pb.y + hb
+            && pb.y < pa.y + ha
+    }
+
+    #[test]
+    fn test_pack() {
+        let sizes = [(32, 32), (8, 8), (64, 16), (40, 40), (16, 64), (30, 10)];
+        let (placements, layers) = pack(&sizes, 100, 1);
+
+        let placed: Vec<_> = placements.iter().copied().zip(sizes).collect();
+        for (i, &(p, (w, h))) in placed.iter().enumerate() {
+            assert!(p.layer < layers);
+            // with a texel of padding on every side
+            assert!(p.x >= 1 && p.x + w < 100, "{:?} is out of bounds", p);
+            assert!(p.y >= 1 && p.y + h < 100, "{:?} is out of bounds", p);
+            for &other in &placed[i + 1..] {
+                assert!(!overlaps(placed[i], other), "{:?} overlaps {:?}", p, other);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pack_overflow() {
+        // only one fits per layer
+        let (placements, layers) = pack(&[(60, 60), (60, 60), (60, 60)], 64, 1);
+        assert_eq!(layers, 3);
+        let layer_ids: Vec<_> = placements.iter().map(|p| p.layer).collect();
+        assert_eq!(layer_ids, vec![0, 1, 2]);
+    }
+}
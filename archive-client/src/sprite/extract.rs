@@ -0,0 +1,82 @@
+use super::*;
+
+use archive_engine::{ecs::Blueprint, *};
+
+struct Appearance {
+    size: [f32; 2],
+    texture: &'static str,
+    // rgba, red in the low byte. tints the texture
+    color: u32,
+}
+
+impl Appearance {
+    fn for_blueprint(blueprint: Option<Blueprint>) -> Self {
+        match blueprint {
+            Some(Blueprint::Player) => Appearance {
+                size: [1.0, 1.0],
+                texture: "player",
+                color: 0xffffffff,
+            },
+            Some(Blueprint::Bullet) => Appearance {
+                size: [0.25, 0.25],
+                texture: "bullet",
+                color: 0xffffffff,
+            },
+            Some(Blueprint::Static) => Appearance {
+                size: [2.0, 2.0],
+                texture: "obstacle",
+                color: 0xffffffff,
+            },
+            // shouldn't be replicated, make it obvious if it is
+            None => Appearance {
+                size: [1.0, 1.0],
+                texture: MISSING_TEXTURE,
+                color: 0xffff00ff,
+            },
+        }
+    }
+}
+
+fn to_f32(num: Num) -> f32 {
+    num.0.to_num::<f32>()
+}
+
+// how big an entity is drawn, in world units
+pub fn world_size(blueprint: Option<Blueprint>, scale: Option<&ecs::Scale>) -> [f32; 2] {
+    let size = Appearance::for_blueprint(blueprint).size;
+    let scale = scale.map_or([1.0, 1.0], |s| [to_f32(s.xy.x), to_f32(s.xy.y)]);
+    [size[0] * scale[0], size[1] * scale[1]]
+}
+
+pub type ExtractQ = (
+    &'static ecs::Position,
+    Option<&'static ecs::Rotation>,
+    Option<&'static ecs::Scale>,
+    &'static ecs::Replicated,
+);
+
+// turns the replicated entities in a snapshot into sprites, in world
+// space. the camera takes it from there
+pub fn extract_sprites(snapshot: &mut ecs::Snapshot, atlas: &Atlas, sprites: &mut Vec<GpuSprite>) {
+    let mut layered = Vec::new();
+    for (_, (pos, rot, scale, repl)) in snapshot.query_mut::<ExtractQ>() {
+        let look = Appearance::for_blueprint(repl.blueprint());
+        let region = atlas.region(look.texture);
+        let sprite = GpuSprite {
+            position: [to_f32(pos.xy.x), to_f32(pos.xy.y)],
+            size: world_size(repl.blueprint(), scale),
+            // the shader rotates clockwise
+            rotation: -rot.map_or(0.0, |r| to_f32(r.rad)),
+            color: look.color,
+            uv_rect: region.uv_rect,
+            layer: region.layer,
+            ..Default::default()
+        };
+        layered.push((pos.zed, sprite));
+    }
+    // higher zed draws on top
+    layered.sort_by_key(|&(zed, _)| zed);
+
+    sprites.clear();
+    sprites.extend(layered.into_iter().map(|(_, sprite)| sprite));
+}
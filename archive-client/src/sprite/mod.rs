@@ -1,5 +1,9 @@
+mod atlas;
+mod extract;
 mod sprite_painter;
 mod texture;
 
+pub use atlas::*;
+pub use extract::*;
 pub use sprite_painter::*;
 pub use texture::*;
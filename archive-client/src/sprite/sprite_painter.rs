@@ -1,18 +1,37 @@
 use super::*;
 use crate::*;
-use std::{borrow::Cow, mem};
+use std::{borrow::Cow, mem, num::NonZeroU64};
 
 use bytemuck::{Pod, Zeroable};
-use wgpu::CommandBuffer;
+use futures::{executor::LocalPool, task::SpawnExt};
+use wgpu::{util::StagingBelt, CommandBuffer};
 
 pub struct SpritePainter {
     instance_buffer: wgpu::Buffer,
+    // in sprites, grows to fit whatever we're asked to draw
+    instance_capacity: usize,
+    // reuses its mapped chunks across frames instead of allocating a new
+    // upload buffer every time. chunks come back in post_frame
+    staging_belt: StagingBelt,
+    recall_pool: LocalPool,
 
     render_pipeline: wgpu::RenderPipeline,
     pub texture_bind_group_layout: wgpu::BindGroupLayout,
 }
 
-const MAX_SPRITES: usize = 512;
+const INITIAL_SPRITES: usize = 512;
+// 1024 sprites per staging chunk, bigger uploads get a chunk of their own
+const STAGING_CHUNK_SIZE: wgpu::BufferAddress = 1024 * mem::size_of::<GpuSprite>() as u64;
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: (capacity * mem::size_of::<GpuSprite>()) as _,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::VERTEX,
+        mapped_at_creation: false,
+    })
+}
+
 // 256 bit minimum alignment imposed by nvidia or something. there is also
 // min_uniform_buffer_offset_alignment which basically means the GPU could
 // in theory do better, but I don't want to mess with that at runtime
@@ -23,7 +42,10 @@ pub struct GpuSprite {
     pub size: [f32; 2],
     pub rotation: f32,
     pub color: u32,
-    pub _pad: [u32; 2],
+    // where in the atlas to sample, see AtlasRegion
+    pub uv_rect: [f32; 4],
+    pub layer: u32,
+    pub _pad: [u32; 5],
 }
 
 impl GpuSprite {
@@ -58,9 +80,50 @@ impl GpuSprite {
                     shader_location: 3,
                     format: wgpu::VertexFormat::Uint32,
                 },
+                // uv_rect
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // layer
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 10]>() as wgpu::BufferAddress,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Uint32,
+                },
             ],
         }
     }
+
+    // an axis aligned rectangle from its corner, for ui quads. region
+    // should usually be a texel so the color comes through as is
+    pub fn rect(min: [f32; 2], size: [f32; 2], color: u32, region: AtlasRegion) -> Self {
+        GpuSprite {
+            // sprites are positioned by their center
+            position: [min[0] + size[0] / 2.0, min[1] + size[1] / 2.0],
+            size,
+            color,
+            uv_rect: region.uv_rect,
+            layer: region.layer,
+            ..Default::default()
+        }
+    }
+
+    // a segment width thick, for graphs
+    pub fn line(from: [f32; 2], to: [f32; 2], width: f32, color: u32, region: AtlasRegion) -> Self {
+        let [dx, dy] = [to[0] - from[0], to[1] - from[1]];
+        GpuSprite {
+            position: [(from[0] + to[0]) / 2.0, (from[1] + to[1]) / 2.0],
+            size: [dx.hypot(dy), width],
+            // the shader rotates the other way
+            rotation: -dy.atan2(dx),
+            color,
+            uv_rect: region.uv_rect,
+            layer: region.layer,
+            ..Default::default()
+        }
+    }
 }
 
 impl SpritePainter {
@@ -74,13 +137,7 @@ impl SpritePainter {
             source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("sprite_shader.wgsl"))),
         });
 
-        let local_size = MAX_SPRITES * mem::size_of::<GpuSprite>();
-        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: None,
-            size: local_size as _,
-            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::VERTEX,
-            mapped_at_creation: false,
-        });
+        let instance_buffer = create_instance_buffer(device, INITIAL_SPRITES);
 
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -90,7 +147,7 @@ impl SpritePainter {
                         visibility: wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Texture {
                             multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
                             sample_type: wgpu::TextureSampleType::Float { filterable: true },
                         },
                         count: None,
@@ -122,7 +179,11 @@ impl SpritePainter {
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
                 entry_point: "fs_main",
-                targets: &[config.format.into()],
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
             }),
             primitive: wgpu::PrimitiveState {
                 topology: wgpu::PrimitiveTopology::TriangleStrip,
@@ -136,11 +197,15 @@ impl SpritePainter {
 
         SpritePainter {
             instance_buffer,
+            instance_capacity: INITIAL_SPRITES,
+            staging_belt: StagingBelt::new(STAGING_CHUNK_SIZE),
+            recall_pool: LocalPool::new(),
             render_pipeline,
             texture_bind_group_layout,
         }
     }
 
+    // clears the frame and draws the world
     pub fn render(
         &mut self,
         ctx: &GraphicsContext,
@@ -148,25 +213,59 @@ impl SpritePainter {
         sprite_texture: &SpriteTexture,
         sprites: &[GpuSprite],
     ) -> CommandBuffer {
-        let GraphicsContext { queue, device, .. } = ctx;
-        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(sprites));
+        let load = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        self.render_impl(ctx, view, &ctx.global, sprite_texture, sprites, load)
+    }
+
+    // draws on top of whatever is there, with a different projection
+    pub fn render_overlay(
+        &mut self,
+        ctx: &GraphicsContext,
+        view: &wgpu::TextureView,
+        global: &GlobalBuffer,
+        sprite_texture: &SpriteTexture,
+        sprites: &[GpuSprite],
+    ) -> CommandBuffer {
+        let load = wgpu::LoadOp::Load;
+        self.render_impl(ctx, view, global, sprite_texture, sprites, load)
+    }
+
+    fn render_impl(
+        &mut self,
+        ctx: &GraphicsContext,
+        view: &wgpu::TextureView,
+        global: &GlobalBuffer,
+        sprite_texture: &SpriteTexture,
+        sprites: &[GpuSprite],
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) -> CommandBuffer {
+        let GraphicsContext { device, .. } = ctx;
+        if sprites.len() > self.instance_capacity {
+            self.instance_capacity = sprites.len().next_power_of_two();
+            self.instance_buffer = create_instance_buffer(device, self.instance_capacity);
+        }
+
         let mut encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        let bytes: &[u8] = bytemuck::cast_slice(sprites);
+        if let Some(size) = NonZeroU64::new(bytes.len() as u64) {
+            self.staging_belt
+                .write_buffer(&mut encoder, &self.instance_buffer, 0, size, device)
+                .copy_from_slice(bytes);
+        }
+        self.staging_belt.finish();
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
                 color_attachments: &[wgpu::RenderPassColorAttachment {
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: true,
-                    },
+                    ops: wgpu::Operations { load, store: true },
                     ..launch_config::color_attachment(ctx, view)
                 }],
                 depth_stencil_attachment: None,
             });
             render_pass.set_pipeline(&self.render_pipeline);
 
-            render_pass.set_bind_group(0, &ctx.global.global_group, &[]);
+            render_pass.set_bind_group(0, &global.global_group, &[]);
             render_pass.set_bind_group(1, &sprite_texture.bind_group, &[]);
 
             render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
@@ -175,4 +274,72 @@ impl SpritePainter {
 
         encoder.finish()
     }
+
+    // call once the command buffer from render has been submitted
+    pub fn post_frame(&mut self, ctx: &GraphicsContext) {
+        let recall = self.staging_belt.recall();
+        self.recall_pool
+            .spawner()
+            .spawn(recall)
+            .expect("recall pool is never shut down");
+        // on native, buffers only finish mapping when the device is polled
+        ctx.device.poll(wgpu::Maintain::Poll);
+        self.recall_pool.run_until_stalled();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    const SIZE: u32 = 256;
+
+    // cargo test -- --ignored on a machine with a gpu (or a software adapter)
+    #[test]
+    #[ignore = "needs a gpu adapter"]
+    fn test_many_sprites() {
+        let ctx = headless::test_context(SIZE, SIZE).expect("no gpu adapter");
+        let global_data = Global {
+            mvp: cgmath::ortho(0.0, SIZE as f32, SIZE as f32, 0.0, -1.0, 1.0).into(),
+        };
+        GlobalBuffer::write(&ctx, global_data);
+
+        let mut painter = SpritePainter::init(&ctx, &ctx.global.global_bind_group_layout);
+        let white = RgbaImage::from_pixel(1, 1, image::Rgba([255; 4]));
+        let texture_handle = TextureHandle::from_layers(&ctx.device, &ctx.queue, &[white]);
+        let sprite_texture = SpriteTexture::init(
+            &ctx.device,
+            &painter.texture_bind_group_layout,
+            texture_handle,
+        );
+
+        let target = headless::OffscreenTarget::new(&ctx);
+
+        // grow twice, and only the last sprite lands at (200, 200), so it
+        // only shows up if every instance made it to the gpu
+        for count in [10_000, 20_000] {
+            let mut sprites = vec![
+                GpuSprite {
+                    position: [64.0, 64.0],
+                    size: [8.0, 8.0],
+                    color: 0xffffffff,
+                    uv_rect: [0.0, 0.0, 1.0, 1.0],
+                    ..Default::default()
+                };
+                count
+            ];
+            sprites.last_mut().unwrap().position = [200.0, 200.0];
+
+            let commands = painter.render(&ctx, target.view(), &sprite_texture, &sprites);
+            ctx.queue.submit(Some(commands));
+            painter.post_frame(&ctx);
+            assert!(painter.instance_capacity >= count);
+
+            let frame = target.capture(&ctx);
+            assert_eq!(frame.get_pixel(200, 200).0, [255; 4]);
+            assert_eq!(frame.get_pixel(64, 64).0, [255; 4]);
+            assert_eq!(frame.get_pixel(0, 0).0, [0, 0, 0, 255]);
+        }
+    }
 }
@@ -1,20 +1,20 @@
+use image::RgbaImage;
+
 pub struct TextureHandle {
     pub(super) view: wgpu::TextureView,
     pub(super) sampler: wgpu::Sampler,
 }
 
 impl TextureHandle {
-    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, image_bytes: &[u8]) -> Self {
-        let image = image::load_from_memory(image_bytes).unwrap();
-        let image_rgba = image.as_rgba8().unwrap();
-
-        use image::GenericImageView;
-        let dimensions = image.dimensions();
+    // a texture array with one layer per image, all the same size
+    pub fn from_layers(device: &wgpu::Device, queue: &wgpu::Queue, layers: &[RgbaImage]) -> Self {
+        let dimensions = layers[0].dimensions();
+        assert!(layers.iter().all(|layer| layer.dimensions() == dimensions));
 
         let texture_size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
-            depth_or_array_layers: 1,
+            depth_or_array_layers: layers.len() as u32,
         };
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             size: texture_size,
@@ -26,26 +26,39 @@ impl TextureHandle {
             label: None,
         });
 
-        queue.write_texture(
-            // Tells wgpu where to copy the pixel data
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            // The actual pixel data
-            image_rgba,
-            // The layout of the texture
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * dimensions.0),
-                rows_per_image: std::num::NonZeroU32::new(dimensions.1),
-            },
-            texture_size,
-        );
+        for (z, layer) in layers.iter().enumerate() {
+            queue.write_texture(
+                // Tells wgpu where to copy the pixel data
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: z as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                // The actual pixel data
+                layer,
+                // The layout of the texture
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(4 * dimensions.0),
+                    rows_per_image: std::num::NonZeroU32::new(dimensions.1),
+                },
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..texture_size
+                },
+            );
+        }
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // has to be explicit, a single layer would default to a plain D2 view
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
@@ -35,7 +35,8 @@ impl TextPainter {
             glyph_brush.queue(section);
         }
 
-        let transform: [f32; 16] = bytemuck::cast(ctx.global.get_data().mvp);
+        // text stays in screen pixels whatever the camera is doing
+        let transform = wgpu_glyph::orthographic_projection(ctx.config.width, ctx.config.height);
         glyph_brush
             .draw_queued_with_transform(&device, queue, &mut encoder, view, transform)
             .unwrap();
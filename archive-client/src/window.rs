@@ -15,6 +15,70 @@ pub struct GraphicsContext {
     pub multisampled_fb: Option<wgpu::TextureView>,
 }
 
+// what offscreen frames are rendered as, so they read back as rgba
+pub const HEADLESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+impl GraphicsContext {
+    // everything but the surface, which the caller configures with config
+    pub async fn new(adapter: wgpu::Adapter, config: wgpu::SurfaceConfiguration) -> Self {
+        // Create the logical device and command queue
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features: wgpu::Features::empty(),
+                    // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the swapchain.
+                    limits: wgpu::Limits::downlevel_webgl2_defaults()
+                        .using_resolution(adapter.limits()),
+                },
+                None,
+            )
+            .await
+            .expect("Failed to create device");
+
+        let global = GlobalBuffer::new(&device);
+
+        let multisampled_fb = launch_config::multisampled_framebuffer(&device, &config);
+
+        GraphicsContext {
+            config,
+            adapter,
+            device,
+            queue,
+            global,
+            multisampled_fb,
+        }
+    }
+
+    // renders into offscreen textures instead of a window, see
+    // headless::OffscreenTarget. prefers a software adapter so frames come
+    // out the same on every machine, None if there's no adapter at all
+    pub async fn headless(width: u32, height: u32) -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let mut adapter = None;
+        for force_fallback_adapter in [true, false] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    force_fallback_adapter,
+                    compatible_surface: None,
+                })
+                .await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: HEADLESS_FORMAT,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        Some(Self::new(adapter?, config).await)
+    }
+}
+
 // this function is mainly playing along with the winit event loop and
 // setting up wgpu constructs. But it also passes through client_rx which
 // is critical for setting up the client e.g. with connections to the server
@@ -36,21 +100,6 @@ pub async fn run_init(
         .await
         .expect("Failed to find an appropriate adapter");
 
-    // Create the logical device and command queue
-    let (device, queue) = adapter
-        .request_device(
-            &wgpu::DeviceDescriptor {
-                label: None,
-                features: wgpu::Features::empty(),
-                // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the swapchain.
-                limits: wgpu::Limits::downlevel_webgl2_defaults()
-                    .using_resolution(adapter.limits()),
-            },
-            None,
-        )
-        .await
-        .expect("Failed to create device");
-
     let swapchain_format = surface.get_preferred_format(&adapter).unwrap();
 
     let config = wgpu::SurfaceConfiguration {
@@ -61,23 +110,9 @@ pub async fn run_init(
         present_mode: wgpu::PresentMode::Fifo,
     };
 
-    surface.configure(&device, &config);
-
-    let global = GlobalBuffer::new(&device);
-
-    let multisampled_fb = launch_config::multisampled_framebuffer(&device, &config);
-
-    let mut ctx = GraphicsContext {
-        config,
-        adapter,
-        device,
-        queue,
-        global,
-        multisampled_fb,
-    };
+    let mut ctx = GraphicsContext::new(adapter, config).await;
+    surface.configure(&ctx.device, &ctx.config);
 
-    // TODO I don't like this, we should have a separate function that returns ctx,
-    // pass that into App::init, and then another function which does the event loop
     let mut app = App::init(&ctx, client_rx);
 
     // return a FnOnce so we can "escape" wasm-bindgen-futures and run this
@@ -120,6 +155,7 @@ pub async fn run_init(
                     event: WindowEvent::CloseRequested,
                     ..
                 } => *control_flow = ControlFlow::Exit,
+                Event::WindowEvent { event, .. } => app.update(&event),
                 Event::RedrawEventsCleared => {
                     // RedrawRequested will only trigger once, unless we manually
                     // request it.
@@ -0,0 +1,65 @@
+use archive_engine::{ecs::*, *};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const ENTITY_COUNTS: [u32; 3] = [100, 1_000, 10_000];
+
+// a realm with count moving entities over a few archetypes, and the
+// server and client snapshots of it once everything has been sent
+fn synced(count: u32) -> (Realm, ServerSnapshot, Snapshot) {
+    let mut realm = Realm::new();
+    for i in 0..count {
+        let pos = Position {
+            xy: V2::new(i % 100, i / 100),
+            zed: mk_zed(0),
+        };
+        let vel = Velocity {
+            xy: mk_v2!(0.5, -0.25),
+        };
+        match i % 3 {
+            0 => realm.spawn((pos, vel, Health::new(100), Replicated::default())),
+            1 => realm.spawn((pos, vel, Bullet {}, Replicated::default())),
+            _ => realm.spawn((pos, Replicated::default())),
+        };
+    }
+
+    let mut server = ServerSnapshot::new();
+    let mut client = Snapshot::new();
+    // deltas are capped, so this takes a while at 10k
+    loop {
+        let delta = ServerDelta::diff(&mut server, &mut realm);
+        if delta.action_count() == 0 {
+            break;
+        }
+        server = delta.apply_server(&mut server);
+        client = delta.into_delta().apply(&mut client);
+    }
+    (realm, server, client)
+}
+
+fn bench_snapshots(c: &mut Criterion) {
+    let mut group = c.benchmark_group("snapshot");
+    group.sample_size(20);
+    for count in ENTITY_COUNTS {
+        let (mut realm, mut server, mut client) = synced(count);
+
+        group.bench_function(BenchmarkId::new("clone_mut", count), |b| {
+            b.iter(|| client.clone_mut())
+        });
+
+        // everything that moves has changed since the last delta
+        realm.run_systems();
+        group.bench_function(BenchmarkId::new("diff", count), |b| {
+            b.iter(|| ServerDelta::diff(&mut server, &mut realm))
+        });
+
+        let delta = ServerDelta::diff(&mut server, &mut realm);
+        group.bench_function(BenchmarkId::new("apply_server", count), |b| {
+            b.iter(|| delta.apply_server(&mut server))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_snapshots);
+criterion_main!(benches);
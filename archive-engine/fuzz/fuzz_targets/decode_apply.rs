@@ -0,0 +1,19 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use archive_engine::*;
+
+// anything the server could send has to decode and apply without
+// panicking, however broken it is. run with `cargo fuzz run decode_apply`
+fuzz_target!(|data: &[u8]| {
+    let delta = match rtc::decode_message::<rtc::ServerMessage>(data) {
+        Ok(rtc::ServerMessage::Delta { delta, .. }) => delta,
+        _ => return,
+    };
+    let mut base = match delta.try_apply(&mut ecs::Snapshot::new()) {
+        Ok(base) => base,
+        Err(_) => return,
+    };
+    // again onto its own result, so updates and despawns find entities
+    let _ = delta.try_apply(&mut base);
+});
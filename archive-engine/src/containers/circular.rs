@@ -138,6 +138,16 @@ impl<T, const CAP: usize, const VCAP: usize> RollingBuf<T, CAP, VCAP> {
             Err(RollingBufError::OutOfBounds)
         }
     }
+    pub fn index_mut(&mut self, rolling_index: usize) -> Result<Option<&mut T>, RollingBufError> {
+        let start = self.backing.start;
+        let true_index = self.calc_true_index(rolling_index)?;
+        let data = self.backing.get_mut(true_index - start);
+        if let Some(data) = data {
+            Ok(data.as_mut())
+        } else {
+            Err(RollingBufError::OutOfBounds)
+        }
+    }
     pub fn new() -> Self {
         Self::check_caps();
         RollingBuf {
@@ -208,6 +218,21 @@ mod tests {
         assert_eq!(buf.index(3), Err(RollingBufError::OutOfBounds));
     }
 
+    #[test]
+    fn test_rollover_index_mut() {
+        let mut buf = RollingBuf::<i32, 2, 16>::new();
+
+        buf.add(0, 0).unwrap();
+        buf.add(1, 1).unwrap();
+
+        *buf.index_mut(1).unwrap().unwrap() += 10;
+        assert_eq!(*buf.index(1).unwrap().unwrap(), 11);
+
+        buf.add(2, 2).unwrap();
+        assert_eq!(buf.index_mut(0), Err(RollingBufError::TooOld));
+        assert_eq!(buf.index_mut(3), Err(RollingBufError::OutOfBounds));
+    }
+
     #[test]
     fn test_rollover_wraparound() {
         let mut buf = RollingBuf::<i32, 2, 16>::new();
@@ -64,6 +64,13 @@ impl TokenPool {
 
         self.freed.push(Reverse(key));
     }
+
+    // true if the token is still live, i.e. it hasn't been freed (and possibly
+    // handed out again under a newer generation) since it was allocated
+    pub fn contains(&self, entry: PoolToken) -> bool {
+        let PoolToken { key, generation } = entry;
+        self.generations.get(key as usize) == Some(&generation)
+    }
 }
 
 #[cfg(test)]
@@ -101,6 +108,22 @@ mod tests {
         assert_eq!(d.key, 0);
     }
 
+    #[test]
+    fn test_stale_token() {
+        let mut pool = TokenPool::new();
+        let a = pool.alloc();
+        assert!(pool.contains(a));
+
+        pool.free(a);
+        assert!(!pool.contains(a));
+
+        // same slot, newer generation
+        let b = pool.alloc();
+        assert_eq!(a.key, b.key);
+        assert!(pool.contains(b));
+        assert!(!pool.contains(a));
+    }
+
     #[test]
     #[should_panic(expected = "double freed you dummy")]
     fn test_double_free() {
@@ -2,14 +2,79 @@ use super::*;
 use crate::*;
 
 use std::collections::{BTreeMap, BinaryHeap};
+use std::fmt;
+use std::marker::PhantomData;
 
 use hecs::*;
+use serde::{de, ser::SerializeTuple, Deserialize, Deserializer, Serialize, Serializer};
+
+// a component diff that only sends the fields that changed, behind a
+// mask of which ones those are. the rest are zero, so adding it works
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaskedDiff<T> {
+    mask: FieldBits,
+    diff: T,
+}
+
+impl<T: FieldMask> MaskedDiff<T> {
+    pub fn new(diff: T) -> Self {
+        MaskedDiff {
+            mask: diff.nonzero_fields(),
+            diff,
+        }
+    }
+    pub fn mask(&self) -> FieldBits {
+        self.mask
+    }
+    pub fn diff(&self) -> T {
+        self.diff
+    }
+}
+
+impl<T: FieldMask + Quantize> Serialize for MaskedDiff<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = 1 + self.mask.count_ones() as usize;
+        let mut tuple = serializer.serialize_tuple(len)?;
+        tuple.serialize_element(&self.mask)?;
+        self.diff
+            .write_fields(self.mask, T::FRAC_BITS, &mut tuple)?;
+        tuple.end()
+    }
+}
+
+impl<'de, T: FieldMask + Quantize> Deserialize<'de> for MaskedDiff<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MaskedVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: FieldMask + Quantize> de::Visitor<'de> for MaskedVisitor<T> {
+            type Value = MaskedDiff<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a field mask followed by the fields it names")
+            }
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mask: FieldBits = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                if mask & !T::all_fields() != 0 {
+                    return Err(de::Error::custom("mask names fields that don't exist"));
+                }
+                let diff = T::read_fields(mask, T::FRAC_BITS, &mut seq)?;
+                Ok(MaskedDiff { mask, diff })
+            }
+        }
+
+        // the most it could be, the mask says how many are really there
+        let len = 1 + T::FIELDS as usize;
+        deserializer.deserialize_tuple(len, MaskedVisitor(PhantomData))
+    }
+}
 
 macro_rules! make_delta_diff {
     ($($kinds:tt),*) => {
         derive_delta! {
             enum DeltaDiff {
-                $($kinds($kinds)),*
+                $($kinds(MaskedDiff<$kinds>)),*
             }
         }
     }
@@ -34,9 +99,11 @@ macro_rules! make_delta_remove {
     }
 }
 
-make_delta_diff!(Position, Rotation, Velocity, Health);
-make_delta_replace!(Camera, Player, Bullet);
-make_delta_remove!(Position, Rotation, Velocity, Camera, Player, Bullet, Health);
+make_delta_diff!(Position, Rotation, Velocity, Scale, Health);
+make_delta_replace!(Camera, Player, Bullet, Weapon, Replicated);
+make_delta_remove!(
+    Position, Rotation, Velocity, Scale, Camera, Player, Bullet, Health, Weapon, Replicated
+);
 
 macro_rules! match_delta_helper {
     ($enum:ident, $diff:ident, |$c:ident| $expr:expr, $($kinds:tt),*) => {
@@ -57,13 +124,23 @@ macro_rules! match_delta_diff {
             Position,
             Rotation,
             Velocity,
+            Scale,
             Health
         )
     };
 }
 macro_rules! match_delta_replace {
     ($diff:ident, |$c:ident| $expr:expr) => {
-        match_delta_helper!(DeltaReplace, $diff, |$c| $expr, Camera, Player, Bullet)
+        match_delta_helper!(
+            DeltaReplace,
+            $diff,
+            |$c| $expr,
+            Camera,
+            Player,
+            Bullet,
+            Weapon,
+            Replicated
+        )
     };
 }
 
@@ -87,10 +164,13 @@ macro_rules! match_delta_remove {
             Position,
             Rotation,
             Velocity,
+            Scale,
             Camera,
             Player,
             Bullet,
-            Health
+            Health,
+            Weapon,
+            Replicated
         )
     };
 }
@@ -149,6 +229,7 @@ impl SingleDiff {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Delta {
     actions: Vec<DeltaAction>,
 }
@@ -224,11 +305,14 @@ impl ServerDelta {
             ($($kinds:tt),*) => {
                 $(
                     let (old, new) = query_both!($kinds);
+                    // old came out of a snapshot, so it's already quantized
+                    let new = new.map(Quantize::quantize);
 
                     if old != new {
                         if let Some(new) = new {
                             let old = old.unwrap_or_default();
-                            patches.push(DeltaComponentPatch::DiffComponent(DeltaDiff::$kinds(new - old)));
+                            let diff = MaskedDiff::new(new - old);
+                            patches.push(DeltaComponentPatch::DiffComponent(DeltaDiff::$kinds(diff)));
                         } else {
                             // old must not be None
                             patches.push(DeltaComponentPatch::RemoveComponent(DeltaRemove::$kinds));
@@ -238,7 +322,7 @@ impl ServerDelta {
             };
         }
         // TODO replace this with some kind of Diff trait with associated items?
-        standard_diff!(Position, Rotation, Velocity, Health);
+        standard_diff!(Position, Rotation, Velocity, Scale, Health);
 
         macro_rules! standard_replace {
             ($($kinds:tt),*) => {
@@ -256,7 +340,7 @@ impl ServerDelta {
                 )*
             };
         }
-        standard_replace!(Camera, Player, Bullet);
+        standard_replace!(Camera, Player, Bullet, Weapon, Replicated);
 
         patches
     }
@@ -347,7 +431,7 @@ impl ServerDelta {
         (result, token_map)
     }
 
-    pub(super) fn diff(base: &mut ServerSnapshot, realm: &mut Realm) -> Self {
+    pub fn diff(base: &mut ServerSnapshot, realm: &mut Realm) -> Self {
         let (total_diff, token_map) = ServerDelta::prioritized_total_diff(base, realm).into();
         let mut queue: BinaryHeap<_> = total_diff.into();
 
@@ -384,63 +468,132 @@ impl ServerDelta {
     }
 
     // &mut is only used for an exclusive reference to the World for performance
-    pub(super) fn apply_server(&self, to: &mut ServerSnapshot) -> ServerSnapshot {
+    pub fn apply_server(&self, to: &mut ServerSnapshot) -> ServerSnapshot {
         let new_snapshot = self.inner.apply(&mut to.inner);
 
         new_snapshot.server_augment(self.server_meta.clone())
     }
+
+    // the part that actually goes over the wire
+    pub fn into_delta(self) -> Delta {
+        self.inner
+    }
+    pub fn action_count(&self) -> usize {
+        self.inner.actions.len()
+    }
+    // how many entities had changes that didn't fit in this delta
+    pub fn priority_backlog(&self) -> usize {
+        self.server_meta.priority_map.len()
+    }
+}
+
+// why a delta doesn't fit the snapshot it was applied to. deltas come off
+// the wire, so these mean a corrupt packet or a bug, and shouldn't crash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaError {
+    // an update or despawn for an entity the snapshot doesn't have
+    MissingEntity(ReplKey),
+    // a remove for a component the entity doesn't have
+    MissingComponent(ReplKey),
+    // spawns start from nothing, so there's nothing to remove
+    RemoveInSpawn(ReplKey),
 }
 
+impl std::fmt::Display for DeltaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeltaError::MissingEntity(key) => write!(f, "no entity for repl key {key}"),
+            DeltaError::MissingComponent(key) => {
+                write!(f, "removing a missing component from repl key {key}")
+            }
+            DeltaError::RemoveInSpawn(key) => write!(f, "remove in the spawn of repl key {key}"),
+        }
+    }
+}
+
+impl std::error::Error for DeltaError {}
+
 impl Delta {
-    // &mut is only used for an exclusive reference to the World for performance
-    pub(super) fn apply(&self, onto: &mut Snapshot) -> Snapshot {
+    // for deltas we made ourselves, which always fit their base
+    pub fn apply(&self, onto: &mut Snapshot) -> Snapshot {
+        self.try_apply(onto).expect("delta doesn't fit its base")
+    }
+
+    // checks every patch against the snapshot as it goes. onto is left
+    // alone either way, &mut is only used for an exclusive reference to
+    // the World for performance
+    pub fn try_apply(&self, onto: &mut Snapshot) -> Result<Snapshot, DeltaError> {
         let mut result = onto.clone_mut();
         for action in &self.actions {
             let DeltaAction {
                 repl_key,
                 ent_patch,
             } = action;
+            let repl_key = *repl_key;
             let ent = result.ent_map.get(&repl_key).copied();
             match ent_patch {
                 DeltaEntityPatch::SpawnEntity(patches) => {
+                    let mut builder = Self::build_spawn(repl_key, patches)?;
                     if let Some(ent) = ent {
-                        result.world.despawn(ent).unwrap();
+                        result
+                            .world
+                            .despawn(ent)
+                            .map_err(|_| DeltaError::MissingEntity(repl_key))?;
                     }
-                    let mut builder = Self::build_spawn(patches);
                     let ent = result.world.spawn(builder.build());
-                    result.ent_map.insert(*repl_key, ent);
+                    result.ent_map.insert(repl_key, ent);
                 }
                 DeltaEntityPatch::UpdateEntity(patches) => {
-                    Self::apply_component_patches(&mut result, ent.unwrap(), patches);
+                    let ent = ent.ok_or(DeltaError::MissingEntity(repl_key))?;
+                    Self::apply_component_patches(&mut result, repl_key, ent, patches)?;
                 }
                 DeltaEntityPatch::DespawnEntity => {
-                    result.world.despawn(ent.unwrap()).unwrap();
-                    result.ent_map.remove(repl_key).unwrap();
+                    let ent = ent.ok_or(DeltaError::MissingEntity(repl_key))?;
+                    result
+                        .world
+                        .despawn(ent)
+                        .map_err(|_| DeltaError::MissingEntity(repl_key))?;
+                    result.ent_map.remove(&repl_key);
                 }
             }
         }
-        result
+        Ok(result)
+    }
+
+    // whether applying this to before (giving after) spawned, changed or
+    // despawned anything of the blueprint, so e.g. the client can skip
+    // redoing work for entities that never change
+    pub fn touches(&self, blueprint: Blueprint, before: &Snapshot, after: &Snapshot) -> bool {
+        self.actions.iter().any(|action| {
+            before.blueprint_of(action.repl_key) == Some(blueprint)
+                || after.blueprint_of(action.repl_key) == Some(blueprint)
+        })
     }
 
-    fn build_spawn(patches: &Vec<DeltaComponentPatch>) -> EntityBuilder {
+    fn build_spawn(
+        repl_key: ReplKey,
+        patches: &Vec<DeltaComponentPatch>,
+    ) -> Result<EntityBuilder, DeltaError> {
         let mut builder = EntityBuilder::new();
 
         for &patch in patches {
             use DeltaComponentPatch::*;
             match patch {
-                DiffComponent(diff) => match_delta_diff!(diff, |c| builder.add(c)),
+                DiffComponent(diff) => match_delta_diff!(diff, |c| builder.add(c.diff)),
                 ReplaceComponent(replace) => match_delta_replace!(replace, |c| builder.add(c)),
-                RemoveComponent(_) => unreachable!(),
+                RemoveComponent(_) => return Err(DeltaError::RemoveInSpawn(repl_key)),
             };
         }
 
-        builder
+        Ok(builder)
     }
     fn apply_component_patches(
         target: &mut Snapshot,
+        repl_key: ReplKey,
         ent: Entity,
         patches: &Vec<DeltaComponentPatch>,
-    ) {
+    ) -> Result<(), DeltaError> {
+        let missing_entity = |_: NoSuchEntity| DeltaError::MissingEntity(repl_key);
         for &patch in patches {
             use DeltaComponentPatch::*;
 
@@ -448,10 +601,14 @@ impl Delta {
                 DiffComponent(diff) => match_delta_diff!(diff, |c| {
                     let dest = target.get_mut::<&mut _Component>(ent);
                     if let Some(dest) = dest {
-                        *dest += c;
+                        // fields outside the mask are zero, so they stay put
+                        *dest += c.diff;
                     } else {
                         // add component if it isn't there already
-                        target.world.insert_one(ent, c).unwrap();
+                        target
+                            .world
+                            .insert_one(ent, c.diff)
+                            .map_err(missing_entity)?;
                     }
                 }),
                 ReplaceComponent(replace) => match_delta_replace!(replace, |c| {
@@ -460,14 +617,18 @@ impl Delta {
                         *dest = c;
                     } else {
                         // add component if it isn't there already
-                        target.world.insert_one(ent, c).unwrap();
+                        target.world.insert_one(ent, c).map_err(missing_entity)?;
                     }
                 }),
                 RemoveComponent(remove) => match_delta_remove!(remove, || {
-                    target.world.remove_one::<Struct>(ent).unwrap();
+                    target
+                        .world
+                        .remove_one::<Struct>(ent)
+                        .map_err(|_| DeltaError::MissingComponent(repl_key))?;
                 }),
             };
         }
+        Ok(())
     }
 }
 
@@ -510,9 +671,12 @@ mod tests {
             let ent_patch = &diff.inner.actions[0].ent_patch;
             assert_eq!(
                 *ent_patch,
-                DeltaEntityPatch::SpawnEntity(vec![DeltaComponentPatch::DiffComponent(
-                    DeltaDiff::Position(pos_a)
-                )]),
+                DeltaEntityPatch::SpawnEntity(vec![
+                    DeltaComponentPatch::DiffComponent(DeltaDiff::Position(MaskedDiff::new(
+                        pos_a.quantize()
+                    ))),
+                    DeltaComponentPatch::ReplaceComponent(DeltaReplace::Replicated(R_PLAYER)),
+                ]),
                 "spawns in a position entity"
             );
 
@@ -579,11 +743,199 @@ mod tests {
             let ent_patch = &diff.inner.actions[0].ent_patch;
             assert_eq!(
                 *ent_patch,
-                DeltaEntityPatch::SpawnEntity(vec![DeltaComponentPatch::DiffComponent(
-                    DeltaDiff::Position(pos_b)
-                )]),
+                DeltaEntityPatch::SpawnEntity(vec![
+                    DeltaComponentPatch::DiffComponent(DeltaDiff::Position(MaskedDiff::new(
+                        pos_b.quantize()
+                    ))),
+                    DeltaComponentPatch::ReplaceComponent(DeltaReplace::Replicated(R_PLAYER)),
+                ]),
                 "spawns in the new position without subtracting pos_a from pos_b"
             );
         }
     }
+
+    #[test]
+    fn test_masked_diff() {
+        let only_x = Position {
+            xy: mk_v2!(0.5, 0),
+            zed: mk_zed(0),
+        };
+        let masked = MaskedDiff::new(only_x);
+        assert_eq!(masked.mask(), 0b001);
+        let bytes = bincode::serialize(&masked).unwrap();
+        assert_eq!(bytes.len(), 1 + 1, "the mask and x alone");
+        assert_eq!(
+            bincode::deserialize::<MaskedDiff<Position>>(&bytes).unwrap(),
+            masked
+        );
+
+        let only_zed = MaskedDiff::new(Position {
+            xy: V2::default(),
+            zed: mk_zed(-1),
+        });
+        assert_eq!(only_zed.mask(), 0b100);
+        let bytes = bincode::serialize(&only_zed).unwrap();
+        assert_eq!(bytes.len(), 1 + 1);
+        assert_eq!(
+            bincode::deserialize::<MaskedDiff<Position>>(&bytes).unwrap(),
+            only_zed
+        );
+
+        let health = MaskedDiff::new(Health::new(3) - Health::new(10));
+        assert_eq!(bincode::serialize(&health).unwrap().len(), 1 + 2);
+
+        // position only has three fields
+        assert!(bincode::deserialize::<MaskedDiff<Position>>(&[0b1000]).is_err());
+        // and the mask promised a field that isn't there
+        assert!(bincode::deserialize::<MaskedDiff<Position>>(&[0b010]).is_err());
+    }
+
+    #[test]
+    fn test_diff_size() {
+        // a tick of running diagonally, which is most of what goes out
+        let from = Position {
+            xy: V_A,
+            zed: mk_zed(0),
+        }
+        .quantize();
+        let run = V2 {
+            x: PLAYER_SPEED,
+            y: PLAYER_SPEED,
+        };
+        let to = Position {
+            xy: V_A + run,
+            ..from
+        }
+        .quantize();
+        let diff = MaskedDiff::new(to - from);
+        let bytes = bincode::serialize(&diff).unwrap();
+        // the mask and a byte of steps each, instead of 4 bytes of I12F20
+        assert_eq!(bytes.len(), 1 + 1 + 1);
+        assert_eq!(
+            bincode::deserialize::<MaskedDiff<Position>>(&bytes).unwrap(),
+            diff
+        );
+
+        // far moves still come back exactly, e.g. spawns
+        let spawn = MaskedDiff::new(to);
+        let bytes = bincode::serialize(&spawn).unwrap();
+        assert_eq!(bytes.len(), 1 + 2 + 2);
+        assert_eq!(
+            bincode::deserialize::<MaskedDiff<Position>>(&bytes).unwrap(),
+            spawn
+        );
+
+        let turn = Rotation { rad: mk_num!(0.3) }.quantize() - Rotation::default();
+        assert_eq!(
+            bincode::serialize(&MaskedDiff::new(turn)).unwrap().len(),
+            1 + 1
+        );
+    }
+
+    #[test]
+    fn test_try_apply_rejects_bad_deltas() {
+        let pos_a = Position {
+            xy: V_A,
+            zed: mk_zed(2),
+        };
+        let spawn = DeltaAction {
+            repl_key: 3,
+            ent_patch: DeltaEntityPatch::SpawnEntity(vec![DeltaComponentPatch::DiffComponent(
+                DeltaDiff::Position(MaskedDiff::new(pos_a)),
+            )]),
+        };
+        let mut base = Delta {
+            actions: vec![spawn],
+        }
+        .try_apply(&mut Snapshot::new())
+        .unwrap();
+
+        let bad = [
+            (
+                DeltaAction {
+                    repl_key: 4,
+                    ent_patch: DeltaEntityPatch::UpdateEntity(vec![]),
+                },
+                DeltaError::MissingEntity(4),
+            ),
+            (
+                DeltaAction {
+                    repl_key: 4,
+                    ent_patch: DeltaEntityPatch::DespawnEntity,
+                },
+                DeltaError::MissingEntity(4),
+            ),
+            (
+                DeltaAction {
+                    repl_key: 3,
+                    ent_patch: DeltaEntityPatch::UpdateEntity(vec![
+                        DeltaComponentPatch::RemoveComponent(DeltaRemove::Health),
+                    ]),
+                },
+                DeltaError::MissingComponent(3),
+            ),
+            (
+                DeltaAction {
+                    repl_key: 5,
+                    ent_patch: DeltaEntityPatch::SpawnEntity(vec![
+                        DeltaComponentPatch::RemoveComponent(DeltaRemove::Position),
+                    ]),
+                },
+                DeltaError::RemoveInSpawn(5),
+            ),
+        ];
+        for (action, error) in bad {
+            let delta = Delta {
+                actions: vec![action],
+            };
+            assert_eq!(delta.try_apply(&mut base).err(), Some(error));
+        }
+
+        // the base is untouched by the failures
+        let ent = base.ent_map[&3];
+        assert_eq!(*base.world.get::<Position>(ent).unwrap(), pos_a);
+        let despawn = Delta {
+            actions: vec![DeltaAction {
+                repl_key: 3,
+                ent_patch: DeltaEntityPatch::DespawnEntity,
+            }],
+        };
+        assert!(despawn.try_apply(&mut base).unwrap().ent_map.is_empty());
+    }
+
+    #[test]
+    fn test_touches() {
+        let r_static = Replicated {
+            blueprint: Some(Blueprint::Static),
+        };
+        let mut realm = Realm::new();
+        let wall = realm.spawn((Position::default(), r_static));
+        let player = realm.spawn((Position::default(), R_PLAYER));
+
+        let mut empty = ServerSnapshot::new();
+        let spawned = ServerDelta::diff(&mut empty, &mut realm);
+        let mut first = spawned.apply_server(&mut empty);
+        assert!(spawned
+            .inner
+            .touches(Blueprint::Static, &empty.inner, &first.inner));
+
+        // moving the player leaves the statics alone
+        realm.get_mut::<&mut Position>(player).unwrap().xy = V_A;
+        let moved = ServerDelta::diff(&mut first, &mut realm);
+        let mut second = moved.apply_server(&mut first);
+        assert_eq!(moved.inner.actions.len(), 1);
+        assert!(!moved
+            .inner
+            .touches(Blueprint::Static, &first.inner, &second.inner));
+        assert!(moved
+            .inner
+            .touches(Blueprint::Player, &first.inner, &second.inner));
+
+        realm.despawn(wall);
+        let despawned = ServerDelta::diff(&mut second, &mut realm);
+        let third = despawned.apply_server(&mut second);
+        assert!(despawned
+            .inner
+            .touches(Blueprint::Static, &second.inner, &third.inner));
+    }
 }
@@ -0,0 +1,10 @@
+use crate::*;
+
+use serde::{Deserialize, Serialize};
+
+// one-off things that happen during a tick. unlike components they aren't
+// part of snapshots, so a client that misses one never hears about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameEvent {
+    PlayerDied { client_id: rtc::ClientId },
+}
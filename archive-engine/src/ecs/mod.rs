@@ -1,12 +1,18 @@
 mod delta;
+mod events;
+mod quantize;
 mod realm;
+mod replay;
 mod replication;
 mod snapshot;
 mod systems;
 mod utils;
 
 pub use delta::*;
+pub use events::*;
+pub use quantize::*;
 pub use realm::*;
+pub use replay::*;
 pub use replication::*;
 pub use snapshot::*;
 pub use systems::*;
@@ -0,0 +1,144 @@
+use super::*;
+use crate::*;
+
+use fixed::types::I12F20;
+
+// how finely each replicated number goes over the wire. the realm keeps
+// full precision, only what gets diffed into snapshots is rounded. all
+// powers of two, so diffs are whole steps and go out as step counts
+pub const POSITION_FRAC_BITS: u32 = 6; // 1/64 unit
+pub const VELOCITY_FRAC_BITS: u32 = 10; // 1/1024 unit per tick
+pub const ROTATION_FRAC_BITS: u32 = 6; // 1/64 radian, a bit under a degree
+
+// rounds a component to what clients get to see. has to be idempotent,
+// or a snapshot never catches up to the realm
+pub trait Quantize: Sized {
+    // the fraction bits of each Num that survive quantize
+    const FRAC_BITS: u32 = Num::FRAC_NBITS;
+
+    fn quantize(self) -> Self {
+        self
+    }
+}
+
+impl Quantize for Position {
+    const FRAC_BITS: u32 = POSITION_FRAC_BITS;
+
+    fn quantize(self) -> Self {
+        Position {
+            xy: round_v2(self.xy, POSITION_FRAC_BITS),
+            zed: self.zed,
+        }
+    }
+}
+impl Quantize for Velocity {
+    const FRAC_BITS: u32 = VELOCITY_FRAC_BITS;
+
+    fn quantize(self) -> Self {
+        Velocity {
+            xy: round_v2(self.xy, VELOCITY_FRAC_BITS),
+        }
+    }
+}
+impl Quantize for Rotation {
+    const FRAC_BITS: u32 = ROTATION_FRAC_BITS;
+
+    fn quantize(self) -> Self {
+        Rotation {
+            rad: round_to_bits(self.rad, ROTATION_FRAC_BITS),
+        }
+    }
+}
+impl Quantize for Scale {}
+impl Quantize for Health {}
+
+// to the nearest multiple of 1 / 2^frac_bits, halves round up
+fn round_to_bits(num: Num, frac_bits: u32) -> Num {
+    let shift = Num::FRAC_NBITS - frac_bits;
+    if shift == 0 {
+        return num;
+    }
+    let half = 1i32 << (shift - 1);
+    let bits = (num.0.to_bits().wrapping_add(half) >> shift) << shift;
+    fixed::Wrapping(I12F20::from_bits(bits))
+}
+fn round_v2(v: V2, frac_bits: u32) -> V2 {
+    V2 {
+        x: round_to_bits(v.x, frac_bits),
+        y: round_to_bits(v.y, frac_bits),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantize() {
+        let pos = Position {
+            xy: mk_v2!(1.2, -1.6),
+            zed: mk_zed(2),
+        };
+        let quantized = pos.quantize();
+        assert_eq!(quantized.xy, V2::new(1.203125, -1.59375));
+        assert_eq!(quantized.zed, pos.zed);
+        assert_eq!(quantized.quantize(), quantized);
+
+        let step = Num::from_num(1) / Num::from_num(1 << ROTATION_FRAC_BITS);
+        for rad in [mk_num!(0), mk_num!(1), mk_num!(-3.1), mk_num!(100)] {
+            let quantized = Rotation { rad }.quantize();
+            let error = quantized.rad - rad;
+            let half_step = step / Num::from_num(2);
+            assert!(-half_step <= error && error <= half_step);
+            assert_eq!(quantized.quantize(), quantized);
+        }
+    }
+
+    #[test]
+    fn test_client_server_agree() {
+        let mut realm = Realm::new();
+        let pos = Position {
+            xy: mk_v2!(1.2, -1.6),
+            zed: mk_zed(0),
+        };
+        let rot = Rotation { rad: mk_num!(0.3) };
+        // a bit under two position steps a tick, so every tick has an update
+        let vel = Velocity {
+            xy: mk_v2!(0.0301, 0),
+        };
+        let ent = realm.spawn((pos, rot, vel, Replicated::default()));
+
+        let mut server = ServerSnapshot::new();
+        let mut client = Snapshot::new();
+        let sync = |realm: &mut Realm, server: &mut ServerSnapshot, client: &mut Snapshot| {
+            let delta = ServerDelta::diff(server, realm);
+            let count = delta.action_count();
+            *server = delta.apply_server(server);
+            *client = delta.into_delta().apply(client);
+            count
+        };
+
+        for _ in 0..3 {
+            assert_eq!(sync(&mut realm, &mut server, &mut client), 1);
+            let client_ent = client.ent_map[&0];
+            let real = *realm.world.get::<Position>(ent).unwrap();
+            let seen = *client.world.get::<Position>(client_ent).unwrap();
+            assert_eq!(seen, real.quantize());
+            let real = *realm.world.get::<Rotation>(ent).unwrap();
+            let seen = *client.world.get::<Rotation>(client_ent).unwrap();
+            assert_eq!(seen, real.quantize());
+            assert_eq!(server.checksum(), client.checksum());
+            realm.run_systems();
+        }
+
+        // the simulation still moves at full precision
+        let real = *realm.world.get::<Position>(ent).unwrap();
+        assert_eq!(real.xy, pos.xy + vel.xy * Num::from_num(3));
+
+        // moving by less than a step doesn't send anything
+        realm.world.get_mut::<Velocity>(ent).unwrap().xy = V2::default();
+        sync(&mut realm, &mut server, &mut client);
+        realm.world.get_mut::<Position>(ent).unwrap().xy.x += mk_num!(0.001);
+        assert_eq!(sync(&mut realm, &mut server, &mut client), 0);
+    }
+}
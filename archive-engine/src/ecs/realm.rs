@@ -4,6 +4,7 @@ use super::*;
 use crate::*;
 
 use bimap::BiBTreeMap;
+use log::warn;
 
 use hecs::*;
 
@@ -12,6 +13,9 @@ pub const TICK_RATE: Num = mk_num!(0.016666666666666666);
 pub const TICK_DURATION: Duration =
     Duration::from_micros(conversions::num_to_umicros_cast(TICK_RATE));
 
+pub const PLAYER_HEALTH: u16 = 100;
+pub const PLAYER_MAGAZINE: u16 = 30;
+
 #[derive(Default)]
 pub struct Realm {
     // only ecs can access world because it has invariants to uphold
@@ -25,17 +29,26 @@ pub struct Realm {
     pub(crate) repl_token_pool: ReplPool,
     pub(crate) ent_map: BiBTreeMap<ReplToken, Entity>,
     pub(crate) player_map: BTreeMap<rtc::ClientId, Entity>,
+    // what happened since the last take_events
+    pub(super) events: Vec<GameEvent>,
+    // everything done to the realm since start_recording
+    replay: Option<Replay>,
 }
 
 impl Realm {
     pub fn new() -> Self {
         Realm::default()
     }
+    pub fn entity_count(&self) -> u32 {
+        self.world.len()
+    }
     pub fn run_systems(&mut self) {
+        self.record(ReplayEvent::RunSystems);
         input_system(self);
         movement_system(self);
         health_system(self);
         // death_system(self);
+        self.tick += 1;
     }
     // TODO add the player and make this relative
     pub(super) fn calc_priority_inc(&mut self, ent: Entity) -> Priority {
@@ -75,6 +88,129 @@ impl Realm {
         }
         self.world.despawn(ent).unwrap();
     }
+    // spawns the entity a client controls. it outlives the client's session,
+    // so it stays put until despawn_player is called
+    pub fn spawn_player(&mut self, client_id: rtc::ClientId) -> Entity {
+        self.record(ReplayEvent::SpawnPlayer(client_id));
+        let ent = self.spawn((
+            Position::default(),
+            Rotation::default(),
+            Velocity::default(),
+            Input::default(),
+            Health::new(PLAYER_HEALTH),
+            Weapon::new(PLAYER_MAGAZINE),
+            Player::new(client_id),
+            Replicated {
+                blueprint: Some(Blueprint::Player),
+            },
+        ));
+        if let Some(old) = self.player_map.insert(client_id, ent) {
+            warn!("client #{client_id} already had a player, despawning it");
+            self.despawn(old);
+        }
+        ent
+    }
+    pub fn despawn_player(&mut self, client_id: rtc::ClientId) {
+        self.record(ReplayEvent::DespawnPlayer(client_id));
+        if let Some(ent) = self.player_map.remove(&client_id) {
+            // systems can despawn players on their own, e.g. when they die
+            if self.world.contains(ent) {
+                self.despawn(ent);
+            }
+        }
+    }
+    // players still in the world, i.e. the ones who haven't died or left
+    pub fn player_count(&self) -> usize {
+        self.player_map
+            .values()
+            .filter(|&&ent| self.world.contains(ent))
+            .count()
+    }
+    pub fn take_events(&mut self) -> Vec<GameEvent> {
+        std::mem::take(&mut self.events)
+    }
+    pub fn player_for_client(&self, client_id: rtc::ClientId) -> Option<Entity> {
+        self.player_map.get(&client_id).copied()
+    }
+    pub fn set_team(&mut self, client_id: rtc::ClientId, team: Team) {
+        self.record(ReplayEvent::SetTeam(client_id, team));
+        let ent = match self.player_for_client(client_id) {
+            Some(ent) if self.world.contains(ent) => ent,
+            _ => return,
+        };
+        if let Some(player) = self.get_mut::<&mut Player>(ent) {
+            player.team = team;
+        }
+    }
+    // the latest input from a client, picked up by input_system next tick
+    pub fn set_input(&mut self, client_id: rtc::ClientId, input: Input) {
+        self.record(ReplayEvent::SetInput(client_id, input));
+        let ent = match self.player_for_client(client_id) {
+            Some(ent) if self.world.contains(ent) => ent,
+            _ => return,
+        };
+        if let Some(current) = self.get_mut::<&mut Input>(ent) {
+            // deserializing skips the clamping in new
+            *current = Input::new(input.movement(), input.aim(), input.buttons());
+        }
+    }
+    // runs f with a Camera on the client's player, so a delta diffed inside
+    // tells that client (and only that client) which entity is theirs
+    pub fn with_camera_for<T>(
+        &mut self,
+        client_id: rtc::ClientId,
+        f: impl FnOnce(&mut Realm) -> T,
+    ) -> T {
+        let ent = match self.player_for_client(client_id) {
+            Some(ent) if self.world.contains(ent) => ent,
+            _ => return f(self),
+        };
+        self.world.insert_one(ent, Camera {}).unwrap();
+        let result = f(self);
+        // f may have despawned it
+        let _ = self.world.remove_one::<Camera>(ent);
+        result
+    }
+    // keeps everything needed to rerun the match from here on. playback
+    // starts from an empty realm, so this one has to be empty too
+    pub fn start_recording(&mut self, record_sent: bool) -> Result<(), RecordError> {
+        if !self.world.is_empty() {
+            return Err(RecordError::NotEmpty(self.world.len()));
+        }
+        self.replay = Some(Replay::new(self.tick, record_sent));
+        Ok(())
+    }
+    // the recording so far, with only the events since the last
+    // take_replay_events
+    pub fn replay(&self) -> Option<&Replay> {
+        self.replay.as_ref()
+    }
+    // the events since the last call, so they can be written out as the
+    // match goes instead of piling up. recording carries on
+    pub fn take_replay_events(&mut self) -> Vec<ReplayEvent> {
+        match &mut self.replay {
+            Some(replay) => std::mem::take(&mut replay.events),
+            None => Vec::new(),
+        }
+    }
+    // stops recording
+    pub fn take_replay(&mut self) -> Option<Replay> {
+        self.replay.take()
+    }
+    // a message the server sent a client on its nth send, kept if the
+    // recording wants them
+    pub fn record_sent(&mut self, send: u64, client_id: rtc::ClientId, message: &[u8]) {
+        if let Some(replay) = self.replay.as_mut().filter(|replay| replay.record_sent) {
+            replay
+                .events
+                .push(ReplayEvent::Sent(send, client_id, message.to_vec()));
+        }
+    }
+    fn record(&mut self, event: ReplayEvent) {
+        if let Some(replay) = &mut self.replay {
+            replay.events.push(event);
+        }
+    }
     pub fn get_mut<Q: Query>(&mut self, ent: Entity) -> Option<QueryItem<Q>> {
         utils::world_get_mut::<Q>(&mut self.world, ent)
     }
@@ -82,3 +218,89 @@ impl Realm {
         self.world.query_mut()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn player_lifecycle() {
+        let mut realm = Realm::new();
+        let ent = realm.spawn_player(3);
+        assert_eq!(realm.player_for_client(3), Some(ent));
+        assert_eq!(realm.ent_map.len(), 1, "players are replicated");
+        assert_eq!(realm.get_mut::<&Player>(ent).unwrap().team(), 3);
+        realm.set_team(3, 1);
+        assert_eq!(realm.get_mut::<&Player>(ent).unwrap().team(), 1);
+
+        realm.despawn_player(3);
+        assert_eq!(realm.player_for_client(3), None);
+        assert!(!realm.world.contains(ent));
+        assert!(realm.ent_map.is_empty());
+
+        // despawning twice is harmless
+        realm.despawn_player(3);
+    }
+
+    #[test]
+    fn input_moves_player() {
+        let mut realm = Realm::new();
+        let ent = realm.spawn_player(3);
+        // way out of range, gets clamped
+        let input = Input::new(V2::new(5, -1), mk_num!(1.5), Input::FIRE);
+        realm.set_input(3, input);
+        realm.run_systems();
+
+        let pos = *realm.get_mut::<&Position>(ent).unwrap();
+        assert_eq!(
+            pos.xy,
+            V2 {
+                x: PLAYER_SPEED,
+                y: -PLAYER_SPEED
+            }
+        );
+        let rot = *realm.get_mut::<&Rotation>(ent).unwrap();
+        assert_eq!(rot.rad, mk_num!(1.5));
+        assert!(realm.get_mut::<&Input>(ent).unwrap().pressed(Input::FIRE));
+    }
+
+    #[test]
+    fn player_death_event() {
+        let mut realm = Realm::new();
+        let ent = realm.spawn_player(3);
+        realm.spawn_player(4);
+        realm.run_systems();
+        assert!(realm.take_events().is_empty());
+
+        *realm.get_mut::<&mut Health>(ent).unwrap() = Health::new(0);
+        realm.run_systems();
+        assert_eq!(
+            realm.take_events(),
+            vec![GameEvent::PlayerDied { client_id: 3 }]
+        );
+        assert!(realm.take_events().is_empty());
+    }
+
+    #[test]
+    fn camera_for_client() {
+        let mut realm = Realm::new();
+        let ent = realm.spawn_player(3);
+        let other = realm.spawn_player(4);
+
+        let seen = realm.with_camera_for(3, |realm| {
+            let mut seen = Vec::new();
+            for (ent, _) in realm.query_mut::<&Camera>() {
+                seen.push(ent);
+            }
+            seen
+        });
+        assert_eq!(seen, vec![ent]);
+        assert!(realm.get_mut::<&Camera>(ent).is_none());
+        assert!(realm.get_mut::<&Camera>(other).is_none());
+
+        // no player, no camera
+        realm.with_camera_for(5, |realm| {
+            assert_eq!(realm.query_mut::<&Camera>().into_iter().count(), 0)
+        });
+    }
+}
@@ -0,0 +1,186 @@
+use super::*;
+use crate::*;
+
+use serde::{Deserialize, Serialize};
+
+// everything done to a realm from outside its systems, in order. the
+// systems are deterministic, so these are enough to rerun a match
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplayEvent {
+    SpawnPlayer(rtc::ClientId),
+    DespawnPlayer(rtc::ClientId),
+    SetTeam(rtc::ClientId, Team),
+    SetInput(rtc::ClientId, Input),
+    RunSystems,
+    // an encoded ServerMessage as it went out on the arena's nth send,
+    // only kept when recording sent messages. playback skips these.
+    // arenas send every tick, even before the realm starts ticking
+    Sent(u64, rtc::ClientId, Vec<u8>),
+}
+
+// a recorded match. the realm has no map or rng yet, so an empty realm
+// at start_tick plus the events is all it takes to play one back. when
+// it gets either they'll need to go in here too
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Replay {
+    // replays only play back on the build that recorded them
+    pub protocol: rtc::ProtocolInfo,
+    pub start_tick: u64,
+    pub record_sent: bool,
+    pub events: Vec<ReplayEvent>,
+}
+
+impl Replay {
+    pub fn new(start_tick: u64, record_sent: bool) -> Self {
+        Replay {
+            protocol: rtc::ProtocolInfo::current(),
+            start_tick,
+            record_sent,
+            events: Vec::new(),
+        }
+    }
+
+    // a replay file is the header, then the events in however many chunks
+    // they were flushed in, so it can be written as the match goes
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = self.encode_header();
+        bytes.extend(Self::encode_events(&self.events));
+        bytes
+    }
+    // everything but the events
+    pub fn encode_header(&self) -> Vec<u8> {
+        let header = Replay {
+            events: Vec::new(),
+            ..*self
+        };
+        bincode::serialize(&header).expect("replays always serialize")
+    }
+    pub fn encode_events(events: &[ReplayEvent]) -> Vec<u8> {
+        bincode::serialize(events).expect("replays always serialize")
+    }
+    pub fn decode(mut bytes: &[u8]) -> bincode::Result<Self> {
+        let mut replay: Replay = bincode::deserialize_from(&mut bytes)?;
+        while !bytes.is_empty() {
+            let events: Vec<ReplayEvent> = bincode::deserialize_from(&mut bytes)?;
+            replay.events.extend(events);
+        }
+        Ok(replay)
+    }
+
+    // reruns the match from scratch, calling on_tick after every tick
+    pub fn play(&self, mut on_tick: impl FnMut(&mut Realm)) -> Realm {
+        let mut realm = Realm::new();
+        realm.tick = self.start_tick;
+        for event in &self.events {
+            match *event {
+                ReplayEvent::SpawnPlayer(client_id) => {
+                    realm.spawn_player(client_id);
+                }
+                ReplayEvent::DespawnPlayer(client_id) => realm.despawn_player(client_id),
+                ReplayEvent::SetTeam(client_id, team) => realm.set_team(client_id, team),
+                ReplayEvent::SetInput(client_id, input) => realm.set_input(client_id, input),
+                ReplayEvent::RunSystems => {
+                    realm.run_systems();
+                    on_tick(&mut realm);
+                }
+                ReplayEvent::Sent(..) => (),
+            }
+        }
+        realm
+    }
+
+    // what went out to a client and the send it went out on, for playing
+    // a client back without a server
+    pub fn sent_to(&self, client_id: rtc::ClientId) -> Vec<(u64, &[u8])> {
+        self.events
+            .iter()
+            .filter_map(|event| match event {
+                ReplayEvent::Sent(send, to, message) if *to == client_id => {
+                    Some((*send, message.as_slice()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// why a realm can't start recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordError {
+    // playback starts from an empty realm, so whatever is already in this
+    // one would be missing and the replay would drift. has the entity count
+    NotEmpty(u32),
+}
+
+impl std::fmt::Display for RecordError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordError::NotEmpty(count) => {
+                write!(f, "can't record a realm that already has {count} entities")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_not_empty() {
+        let mut realm = Realm::new();
+        realm.spawn_player(1);
+        assert_eq!(realm.start_recording(false), Err(RecordError::NotEmpty(1)));
+        assert!(realm.take_replay().is_none());
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut realm = Realm::new();
+        realm.start_recording(true).unwrap();
+        realm.spawn_player(3);
+        realm.spawn_player(4);
+        realm.set_team(4, 3);
+        for i in 0..20 {
+            let movement = V2::new(i % 3, -1);
+            realm.set_input(3, Input::new(movement, mk_num!(0.5), 0));
+            realm.run_systems();
+            realm.record_sent(i as u64 + 5, 3, &[i as u8]);
+            if i == 10 {
+                realm.despawn_player(4);
+            }
+        }
+        let replay = realm.take_replay().unwrap();
+        assert!(realm.take_replay().is_none());
+
+        // the same as it would be streamed
+        let (first, rest) = replay.events.split_at(7);
+        let mut streamed = replay.encode_header();
+        streamed.extend(Replay::encode_events(first));
+        streamed.extend(Replay::encode_events(rest));
+        assert_eq!(Replay::decode(&streamed).unwrap(), replay);
+
+        let replay = Replay::decode(&replay.encode()).unwrap();
+        let mut ticks = 0;
+        let mut played = replay.play(|_| ticks += 1);
+        assert_eq!(ticks, 20);
+        assert_eq!(played.tick, realm.tick);
+        assert_eq!(played.player_count(), 1);
+
+        // the same moves land the player in the same spot
+        let ent = realm.player_for_client(3).unwrap();
+        let played_ent = played.player_for_client(3).unwrap();
+        assert_eq!(
+            *played.get_mut::<&Position>(played_ent).unwrap(),
+            *realm.get_mut::<&Position>(ent).unwrap()
+        );
+
+        let sent = replay.sent_to(3);
+        assert_eq!(sent.len(), 20);
+        assert_eq!(sent[0], (5, &[0u8][..]));
+        assert_eq!(sent[19], (24, &[19u8][..]));
+        assert!(replay.sent_to(4).is_empty());
+    }
+}
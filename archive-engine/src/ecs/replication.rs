@@ -15,6 +15,12 @@ derive_components! {
     }
 }
 
+impl Replicated {
+    pub fn blueprint(&self) -> Option<Blueprint> {
+        self.blueprint
+    }
+}
+
 pub(super) trait ReplicationMap {
     fn entity_for_token(&self, token: &ReplToken) -> Entity;
 }
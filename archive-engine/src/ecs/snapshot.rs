@@ -1,11 +1,15 @@
 use super::*;
 use crate::*;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
 use hecs::*;
 
 pub type SnapshotId = usize;
+pub type SnapshotChecksum = u64;
+
+const PI: Num = mk_num!(3.14159265358979);
+pub(super) const TAU: Num = mk_num!(6.28318530717959);
 
 pub struct Snapshot {
     pub(super) world: World,
@@ -64,40 +68,154 @@ impl Snapshot {
         }
     }
 
-    fn clone_builder(world: &mut World, ent: Entity) -> EntityBuilder {
+    fn clone_builder_ref(world: &World, ent: Entity) -> EntityBuilder {
         let mut builder = EntityBuilder::new();
         map_all_components!(|Struct| {
-            if let Ok(comp) = world.get_mut::<Struct>(ent) {
+            if let Ok(comp) = world.get::<Struct>(ent) {
                 builder.add::<Struct>(*comp);
             }
         });
         builder
     }
 
+    // copies whole archetypes at a time, column by column, instead of
+    // rebuilding every entity one component at a time
     pub fn clone_mut(&mut self) -> Self {
         let mut new_world = World::new();
-        // TODO I think there is a faster way using archetypes() to clone this
+        let mut ent_map = BTreeMap::new();
 
-        let mut entities = Vec::new();
+        // the copies get new entities, so remember which key each id had
+        let keys: HashMap<u32, ReplKey> = self
+            .ent_map
+            .iter()
+            .map(|(&repl_key, ent)| (ent.id(), repl_key))
+            .collect();
 
-        for (ent, ()) in self.world.query_mut::<()>() {
-            entities.push(ent);
-        }
+        for archetype in self.world.archetypes() {
+            if archetype.is_empty() {
+                continue;
+            }
+            let mut batch_type = ColumnBatchType::new();
+            map_all_components!(|Struct| {
+                if archetype.has::<Struct>() {
+                    batch_type.add::<Struct>();
+                }
+            });
+            let mut batch = batch_type.into_batch(archetype.len());
+            map_all_components!(|Struct| {
+                if let (Some(column), Some(mut writer)) =
+                    (archetype.get::<Struct>(), batch.writer::<Struct>())
+                {
+                    for &comp in column.iter() {
+                        let _ = writer.push(comp);
+                    }
+                }
+            });
+            let batch = batch.build().expect("every column was filled");
 
-        for ent in entities {
-            let mut builder = Snapshot::clone_builder(&mut self.world, ent);
-            new_world.spawn(builder.build());
+            // spawned in the same order as the archetype's rows
+            let spawned = new_world.spawn_column_batch(batch);
+            for (id, ent) in archetype.ids().iter().zip(spawned) {
+                if let Some(&repl_key) = keys.get(id) {
+                    ent_map.insert(repl_key, ent);
+                }
+            }
         }
 
         Snapshot {
             world: new_world,
-            ent_map: self.ent_map.clone(),
+            ent_map,
+        }
+    }
+
+    // comes out the same for the same replicated state on the server and
+    // the client, so it goes by ReplKey and not hecs' entities or layout
+    pub fn checksum(&self) -> SnapshotChecksum {
+        let mut hash = FNV_OFFSET;
+        for (&repl_key, &ent) in &self.ent_map {
+            hash = fnv1a(hash, &repl_key.to_le_bytes());
+            map_all_components!(|Struct| {
+                match self.world.get::<Struct>(ent) {
+                    Ok(comp) => {
+                        let bytes = bincode::serialize(&*comp).expect("components serialize");
+                        hash = fnv1a(hash, &[1]);
+                        hash = fnv1a(hash, &bytes);
+                    }
+                    Err(_) => hash = fnv1a(hash, &[0]),
+                }
+            });
         }
+        hash
+    }
+
+    pub(super) fn blueprint_of(&self, repl_key: ReplKey) -> Option<Blueprint> {
+        let ent = *self.ent_map.get(&repl_key)?;
+        self.world.get::<Replicated>(ent).ok()?.blueprint()
     }
 
     pub fn get_mut<Q: Query>(&mut self, ent: Entity) -> Option<QueryItem<Q>> {
         utils::world_get_mut::<Q>(&mut self.world, ent)
     }
+    pub fn query_mut<Q: Query>(&mut self) -> QueryMut<'_, Q> {
+        self.world.query_mut::<Q>()
+    }
+
+    // blends `from` towards `to` by t in [0, 1], for rendering between two
+    // received snapshots. entities only in `to` show up as they are, and
+    // entities only in `from` are left out
+    pub fn interpolate(from: &Snapshot, to: &Snapshot, t: Num) -> Snapshot {
+        let mut result = Snapshot::new();
+        for (&repl_key, &to_ent) in &to.ent_map {
+            let mut builder = Snapshot::clone_builder_ref(&to.world, to_ent);
+            let ent = result.world.spawn(builder.build());
+            result.ent_map.insert(repl_key, ent);
+
+            let from_ent = match from.ent_map.get(&repl_key) {
+                Some(&from_ent) => from_ent,
+                None => continue,
+            };
+            if let (Ok(old), Ok(mut new)) = (
+                from.world.get::<Position>(from_ent),
+                result.world.get_mut::<Position>(ent),
+            ) {
+                new.xy = lerp_v2(old.xy, new.xy, t);
+            }
+            if let (Ok(old), Ok(mut new)) = (
+                from.world.get::<Rotation>(from_ent),
+                result.world.get_mut::<Rotation>(ent),
+            ) {
+                new.rad = lerp_angle(old.rad, new.rad, t);
+            }
+            if let (Ok(old), Ok(mut new)) = (
+                from.world.get::<Scale>(from_ent),
+                result.world.get_mut::<Scale>(ent),
+            ) {
+                new.xy = lerp_v2(old.xy, new.xy, t);
+            }
+        }
+        result
+    }
+}
+
+fn lerp(a: Num, b: Num, t: Num) -> Num {
+    a + (b - a) * t
+}
+fn lerp_v2(a: V2, b: V2, t: Num) -> V2 {
+    V2 {
+        x: lerp(a.x, b.x, t),
+        y: lerp(a.y, b.y, t),
+    }
+}
+// takes the short way around
+fn lerp_angle(a: R, b: R, t: Num) -> R {
+    let mut diff = b - a;
+    while diff > PI {
+        diff -= TAU;
+    }
+    while diff < -PI {
+        diff += TAU;
+    }
+    a + diff * t
 }
 
 pub(super) type ReplTokenMap = BTreeMap<ReplKey, ReplToken>;
@@ -136,6 +254,10 @@ impl ServerSnapshot {
     pub(super) fn priority_accum_for_token(&self, token: &ReplToken) -> Option<Priority> {
         self.meta.priority_map.get(&token.key()).copied()
     }
+    // what the client's copy should checksum to
+    pub fn checksum(&self) -> SnapshotChecksum {
+        self.inner.checksum()
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -157,4 +279,121 @@ mod tests {
         // expect to see it come back in the clone
         assert_eq!(matches, vec![orig]);
     }
+
+    #[test]
+    fn clone_mut_archetypes() {
+        let pos = Position {
+            xy: mk_v2!(1, 2),
+            zed: mk_zed(0),
+        };
+        let mut snapshot = Snapshot::new();
+        for i in 0..20u16 {
+            // spread over a few archetypes, with gaps in the entity ids
+            let ent = match i % 3 {
+                0 => snapshot.world.spawn((pos, Health::new(i))),
+                1 => snapshot.world.spawn((Bullet {},)),
+                _ => snapshot.world.spawn((pos, Velocity::default(), Bullet {})),
+            };
+            if i % 4 != 0 {
+                snapshot.ent_map.insert(i as ReplKey, ent);
+            }
+        }
+        let ent = snapshot.ent_map[&5];
+        snapshot.world.despawn(ent).unwrap();
+        snapshot.ent_map.remove(&5);
+
+        let mut clone = snapshot.clone_mut();
+        assert_eq!(clone.world.len(), snapshot.world.len());
+        assert_eq!(
+            clone.ent_map.keys().collect::<Vec<_>>(),
+            snapshot.ent_map.keys().collect::<Vec<_>>()
+        );
+        // the same components ended up behind the same keys
+        assert_eq!(clone.checksum(), snapshot.checksum());
+
+        // and the clone is its own world
+        let ent = clone.ent_map[&1];
+        clone.world.despawn(ent).unwrap();
+        assert_eq!(clone.world.len() + 1, snapshot.world.len());
+    }
+
+    #[test]
+    fn interpolate() {
+        let mut from = Snapshot::new();
+        let mut to = Snapshot::new();
+
+        let ent = from.world.spawn((
+            Position {
+                xy: mk_v2!(0, 0),
+                zed: mk_zed(0),
+            },
+            Rotation { rad: mk_num!(3) },
+        ));
+        from.ent_map.insert(0, ent);
+
+        let ent = to.world.spawn((
+            Position {
+                xy: mk_v2!(2, -4),
+                zed: mk_zed(1),
+            },
+            Rotation { rad: mk_num!(-3) },
+        ));
+        to.ent_map.insert(0, ent);
+        // only in the newer snapshot
+        let ent = to.world.spawn((Health::new(5),));
+        to.ent_map.insert(1, ent);
+
+        let mut mid = Snapshot::interpolate(&from, &to, mk_num!(0.5));
+
+        let ent = mid.ent_map[&0];
+        let pos = *mid.get_mut::<&Position>(ent).unwrap();
+        assert_eq!(pos.xy, mk_v2!(1, -2), "halfway between the positions");
+        assert_eq!(pos.zed, mk_zed(1), "zed comes from the newer snapshot");
+
+        let rot = mid.get_mut::<&Rotation>(ent).unwrap().rad;
+        assert!(
+            rot > mk_num!(3.1) && rot < mk_num!(3.2),
+            "rotates through pi instead of through 0, got {rot}"
+        );
+
+        let ent = mid.ent_map[&1];
+        assert_eq!(*mid.get_mut::<&Health>(ent).unwrap(), Health::new(5));
+    }
+
+    #[test]
+    fn checksum() {
+        let pos = Position {
+            xy: mk_v2!(1, 2),
+            zed: mk_zed(0),
+        };
+        let mut a = Snapshot::new();
+        let ent = a.world.spawn((pos, Health::new(5)));
+        a.ent_map.insert(3, ent);
+        let ent = a.world.spawn((Bullet {},));
+        a.ent_map.insert(7, ent);
+
+        // same state, but spawned in the other order and through a
+        // different archetype
+        let mut b = Snapshot::new();
+        let ent = b.world.spawn((Bullet {}, Health::new(1)));
+        b.world.remove_one::<Health>(ent).unwrap();
+        b.ent_map.insert(7, ent);
+        let ent = b.world.spawn((Health::new(5),));
+        b.world.insert_one(ent, pos).unwrap();
+        b.ent_map.insert(3, ent);
+        assert_eq!(a.checksum(), b.checksum());
+
+        let ent = b.ent_map[&3];
+        *b.get_mut::<&mut Health>(ent).unwrap() = Health::new(4);
+        assert_ne!(a.checksum(), b.checksum());
+
+        // a missing component isn't the same as a default one
+        let mut c = Snapshot::new();
+        let ent = c.world.spawn((Bullet {},));
+        c.ent_map.insert(7, ent);
+        let mut d = Snapshot::new();
+        let ent = d.world.spawn((Bullet {}, Camera {}));
+        d.ent_map.insert(7, ent);
+        assert_ne!(c.checksum(), d.checksum());
+    }
 }
@@ -38,11 +38,65 @@ derive_components! {
 
     pub struct Player {
         id: rtc::ClientId,
+        pub(super) team: Team,
     }
     // replicated player inputs
     pub struct Input {
         movement: V2,
         aim: R,
+        buttons: Buttons,
+    }
+}
+impl Player {
+    // everyone plays solo until they're put on a team
+    pub fn new(id: rtc::ClientId) -> Self {
+        Player { id, team: id }
+    }
+    pub fn id(&self) -> rtc::ClientId {
+        self.id
+    }
+    pub fn team(&self) -> Team {
+        self.team
+    }
+}
+
+pub type Team = u8;
+
+// bitmask of held buttons, see the consts on Input
+pub type Buttons = u8;
+
+// world units per tick at full tilt
+pub const PLAYER_SPEED: Num = mk_num!(0.1);
+
+impl Input {
+    pub const FIRE: Buttons = 1 << 0;
+    pub const RELOAD: Buttons = 1 << 1;
+    pub const INTERACT: Buttons = 1 << 2;
+
+    // movement is a direction, each axis gets clamped to [-1, 1] so
+    // clients can't ask to go faster
+    pub fn new(movement: V2, aim: R, buttons: Buttons) -> Self {
+        let clamp = |n: Num| n.clamp(Num::from_num(-1), Num::from_num(1));
+        Input {
+            movement: V2 {
+                x: clamp(movement.x),
+                y: clamp(movement.y),
+            },
+            aim,
+            buttons,
+        }
+    }
+    pub fn movement(&self) -> V2 {
+        self.movement
+    }
+    pub fn aim(&self) -> R {
+        self.aim
+    }
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+    pub fn pressed(&self, button: Buttons) -> bool {
+        self.buttons & button != 0
     }
 }
 
@@ -50,7 +104,7 @@ pub type InputQ = (&'static mut Velocity, &'static mut Rotation, &'static Input)
 
 pub fn input_system(realm: &mut Realm) {
     for (_id, (vel, rot, input)) in realm.query_mut::<InputQ>() {
-        vel.xy += input.movement;
+        vel.xy = input.movement * PLAYER_SPEED;
         rot.rad = input.aim;
     }
 }
@@ -58,6 +112,20 @@ pub fn input_system(realm: &mut Realm) {
 derive_components! {
     pub struct Bullet {}
     pub struct Dead {}
+
+    // nothing fires yet, for now this is only shown on the hud
+    pub struct Weapon {
+        pub ammo: u16,
+        pub magazine: u16,
+    }
+}
+impl Weapon {
+    pub fn new(magazine: u16) -> Self {
+        Weapon {
+            ammo: magazine,
+            magazine,
+        }
+    }
 }
 pub(super) type HealthVal = std::num::Wrapping<u16>;
 derive_math_components! {
@@ -71,19 +139,28 @@ impl Health {
             value: std::num::Wrapping(value),
         }
     }
+    pub fn value(&self) -> u16 {
+        self.value.0
+    }
 }
 
-pub type HealthQ = &'static Health;
+pub type HealthQ = (&'static Health, Option<&'static Player>);
 
 pub fn health_system(realm: &mut Realm) {
     let mut to_despawn = Vec::new();
-    for (id, health) in realm.query_mut::<HealthQ>() {
+    for (id, (health, player)) in realm.query_mut::<HealthQ>() {
         if health.value.0 == 0 {
-            to_despawn.push(id);
+            to_despawn.push((id, player.copied()));
         }
     }
-    for id in to_despawn {
-        realm.world.despawn(id).unwrap();
+    for (id, player) in to_despawn {
+        if let Some(player) = player {
+            realm.events.push(GameEvent::PlayerDied {
+                client_id: player.id,
+            });
+        }
+        // through the realm so the replication token gets freed too
+        realm.despawn(id);
     }
 }
 
@@ -0,0 +1,97 @@
+use super::*;
+use crate::*;
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+// counts up by one for every delta sent to a client, starting over with
+// each new session
+pub type DeltaSeq = u64;
+// counts up by one for every input a client sends, so the server can
+// ignore inputs that show up after newer ones
+pub type InputSeq = u64;
+// picked by the client to match pongs up with pings
+pub type PingId = u32;
+
+// where a DeltaSeq lives in a SnapshotBuf
+pub fn snapshot_index(seq: DeltaSeq) -> usize {
+    (seq % SNAPSHOT_VCAP as DeltaSeq) as usize
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum ServerMessage {
+    // brings the snapshot at base (or an empty one, if None) up to the
+    // realm's state at tick. the result becomes the snapshot for seq
+    Delta {
+        seq: DeltaSeq,
+        base: Option<DeltaSeq>,
+        tick: u64,
+        delta: ecs::Delta,
+        // of the resulting snapshot, every so often, to catch desyncs
+        checksum: Option<ecs::SnapshotChecksum>,
+    },
+    // what happened during tick. sent once, so it can get lost
+    Events {
+        tick: u64,
+        events: Vec<ecs::GameEvent>,
+    },
+    // answers a ping with the next delta. tick is the realm's as it went
+    // out, for syncing the client's clock
+    Pong {
+        id: PingId,
+        tick: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum ClientMessage {
+    // the client has the snapshot for seq, so it can be used as a base
+    Ack { seq: DeltaSeq },
+    // what the player is doing this tick
+    Input { seq: InputSeq, input: ecs::Input },
+    // for measuring round trip time
+    Ping { id: PingId },
+    // a checksum didn't match, so the client wants a delta from scratch
+    Resync,
+}
+
+// way more than a delta ever needs. a length prefix in a bad message can
+// claim anything, and this keeps decoding from believing it
+pub const MAX_MESSAGE_SIZE: u64 = 64 * 1024;
+
+pub fn encode_message<T: Serialize>(message: &T) -> Vec<u8> {
+    bincode::serialize(message).expect("messages always serialize")
+}
+pub fn decode_message<T: DeserializeOwned>(bytes: &[u8]) -> bincode::Result<T> {
+    // the same encoding as bincode::serialize, plus the limit
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_MESSAGE_SIZE)
+        .deserialize(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_message() {
+        let ping = ClientMessage::Ping { id: 7 };
+        let bytes = encode_message(&ping);
+        assert!(matches!(
+            decode_message::<ClientMessage>(&bytes),
+            Ok(ClientMessage::Ping { id: 7 })
+        ));
+
+        // a delta claiming way more actions than there are bytes: the
+        // variant, seq, no base, tick and then the action count
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(decode_message::<ServerMessage>(&bytes).is_err());
+    }
+}
@@ -1,3 +1,7 @@
+mod messages;
+mod protocol;
 mod rtc_types;
 
+pub use messages::*;
+pub use protocol::*;
 pub use rtc_types::*;
@@ -0,0 +1,149 @@
+use std::fmt;
+
+use crate::ecs::*;
+use crate::*;
+
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+// the real guard. bump this whenever messages or deltas change, the schema
+// hash only catches components changing without a bump. it can't see
+// inside field types (V2, Team, ...) or a field changing meaning
+pub const PROTOCOL_VERSION: u32 = 5;
+
+// u32 and not u64 so it survives JS numbers, like ResumeToken
+pub type SchemaHash = u32;
+
+// covers the name, fields (by name and type, as written) and encoded size
+// of every replicated component, so a stale client built against
+// different components gets turned away instead of misreading deltas
+pub fn schema_hash() -> SchemaHash {
+    let mut hash = FNV_OFFSET;
+    map_all_components!(|Struct| {
+        // just the type, so moving it between modules doesn't count
+        let name = std::any::type_name::<Struct>();
+        let name = name.rsplit("::").next().unwrap_or(name);
+        hash = fnv1a(hash, name.as_bytes());
+        for (field, ty) in <Struct as ComponentSchema>::SCHEMA {
+            // separated, so fields can't run together into the same bytes
+            hash = fnv1a(hash, &[0]);
+            hash = fnv1a(hash, field.as_bytes());
+            hash = fnv1a(hash, &[1]);
+            hash = fnv1a(hash, ty.as_bytes());
+        }
+        let size = bincode::serialized_size(&Struct::default()).unwrap_or(0);
+        hash = fnv1a(hash, &size.to_le_bytes());
+    });
+    (hash ^ (hash >> 32)) as SchemaHash
+}
+
+// what both ends of a join have to agree on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolInfo {
+    pub version: u32,
+    pub schema_hash: SchemaHash,
+}
+
+impl ProtocolInfo {
+    pub fn current() -> Self {
+        ProtocolInfo {
+            version: PROTOCOL_VERSION,
+            schema_hash: schema_hash(),
+        }
+    }
+
+    // called by the server with what the client sent
+    pub fn check(client: ProtocolInfo) -> Result<(), JoinError> {
+        let server = ProtocolInfo::current();
+        if client == server {
+            Ok(())
+        } else {
+            Err(JoinError::ProtocolMismatch { server, client })
+        }
+    }
+}
+
+// the first websocket frame. protocol goes first so the server can read
+// it even when the rest is laid out differently
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JoinRequest {
+    pub protocol: ProtocolInfo,
+    pub ticket: ArenaTicket,
+}
+
+// why the server turned down a join, sent back as the body of a 409 on
+// /signal or as the websocket's first frame
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JoinError {
+    // the client is a stale build, usually a cached wasm bundle
+    ProtocolMismatch {
+        server: ProtocolInfo,
+        client: ProtocolInfo,
+    },
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::ProtocolMismatch { server, client } => write!(
+                f,
+                "the game was updated, please refresh (server {}/{:08x}, client {}/{:08x})",
+                server.version, server.schema_hash, client.version, client.schema_hash
+            ),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+// the server's answer frame on the websocket
+pub type JoinResult = Result<JoinAnswer, JoinError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_check() {
+        let current = ProtocolInfo::current();
+        assert_eq!(current.schema_hash, schema_hash());
+        assert!(ProtocolInfo::check(current).is_ok());
+
+        let stale = ProtocolInfo {
+            version: PROTOCOL_VERSION - 1,
+            ..current
+        };
+        assert_eq!(
+            ProtocolInfo::check(stale),
+            Err(JoinError::ProtocolMismatch {
+                server: current,
+                client: stale
+            })
+        );
+        // what a client from before versioning deserializes as
+        assert!(ProtocolInfo::check(ProtocolInfo::default()).is_err());
+    }
+
+    #[test]
+    fn test_component_schema() {
+        assert_eq!(Position::SCHEMA, &[("xy", "V2"), ("zed", "Zed")]);
+        assert_eq!(Replicated::SCHEMA, &[("blueprint", "Option<Blueprint>")]);
+        assert!(Camera::SCHEMA.is_empty());
+    }
+
+    #[test]
+    fn test_join_request_prefix() {
+        let request = JoinRequest {
+            protocol: ProtocolInfo::current(),
+            ticket: ArenaTicket {
+                arena_ukey: 7,
+                resume: None,
+            },
+        };
+        let bytes = bincode::serialize(&request).unwrap();
+        // the server reads the protocol alone before the whole request
+        let protocol: ProtocolInfo = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(protocol, request.protocol);
+    }
+}
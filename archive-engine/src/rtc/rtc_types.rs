@@ -1,25 +1,44 @@
 use std::sync::mpsc;
 
+use super::*;
 use crate::*;
 
 use serde::{Deserialize, Serialize};
 
-const SNAPSHOT_CAP: usize = 256;
+pub const SNAPSHOT_CAP: usize = 256;
 const SNAPSHOT_VCAP_BITS: u32 = 12;
-const SNAPSHOT_VCAP: usize = 2usize.pow(SNAPSHOT_VCAP_BITS);
+pub const SNAPSHOT_VCAP: usize = 2usize.pow(SNAPSHOT_VCAP_BITS);
 
 pub type SnapshotBuf<T> = containers::RollingBuf<T, SNAPSHOT_CAP, SNAPSHOT_VCAP>;
 
 pub type ArenaUkey = u64;
 pub type ClientId = u8;
+// ClientIds get recycled, so the server tracks clients with pool tokens
+// to tell a live client apart from a stale one in the same slot
+pub type ClientToken = containers::PoolToken;
 
-#[derive(Copy, Clone, Serialize, Deserialize)]
+// handed out at join time so a client can reclaim its slot after a dropped
+// connection. a string and not a u64 because it passes through JS numbers
+pub type ResumeToken = String;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ResumeTicket {
+    pub client_id: ClientId,
+    pub resume_token: ResumeToken,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ArenaTicket {
     pub arena_ukey: ArenaUkey,
+    // Some if this is a reconnect into an existing slot
+    pub resume: Option<ResumeTicket>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ClientOffer {
+    // missing from clients older than versioning, which then fail the check
+    #[serde(default)]
+    pub protocol: ProtocolInfo,
     pub ticket: ArenaTicket,
     pub sdp: String,
 }
@@ -27,7 +46,68 @@ pub struct ClientOffer {
 #[derive(Serialize, Deserialize)]
 pub struct ServerAnswer {
     pub client_id: ClientId,
+    pub resume_token: ResumeToken,
     pub sdp: String,
+    // for the IceOffers that follow
+    pub signal_id: SignalId,
+}
+impl ServerAnswer {
+    pub fn join_answer(&self) -> JoinAnswer {
+        JoinAnswer {
+            client_id: self.client_id,
+            resume_token: self.resume_token.clone(),
+        }
+    }
+}
+
+// names a handshake between /signal and the /signal/ice calls after it
+pub type SignalId = String;
+
+// the same fields and json names as the browser's RTCIceCandidateInit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IceCandidate {
+    pub candidate: String,
+    pub sdp_mid: Option<String>,
+    #[serde(rename = "sdpMLineIndex")]
+    pub sdp_mline_index: Option<u16>,
+}
+
+// trickle ice: SDPs go out without candidates, then the client keeps
+// POSTing the candidates it has found to /signal/ice and gets back the
+// ones the server has found since the last call
+#[derive(Serialize, Deserialize)]
+pub struct IceOffer {
+    pub signal_id: SignalId,
+    pub candidates: Vec<IceCandidate>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IceAnswer {
+    pub candidates: Vec<IceCandidate>,
+    // the server has found all its candidates, so there's no point in
+    // asking again
+    pub done: bool,
+}
+
+// websocket equivalent of ServerAnswer, sent in the first frame as a
+// JoinResult
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JoinAnswer {
+    pub client_id: ClientId,
+    pub resume_token: ResumeToken,
+}
+impl JoinAnswer {
+    // gets this client back into the same slot after a dropped connection
+    pub fn resume_ticket(&self, arena_ukey: ArenaUkey) -> ArenaTicket {
+        ArenaTicket {
+            arena_ukey,
+            resume: Some(ResumeTicket {
+                client_id: self.client_id,
+                resume_token: self.resume_token.clone(),
+            }),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -56,5 +136,29 @@ pub type BoxedRtcSession = Box<dyn RtcSession>;
 
 pub trait RtcServerDescriptor {
     type Error;
-    fn rtc_connect(&self) -> SharedFuture<Result<BoxedRtcSession, Self::Error>>;
+    // the answer is what the client needs to resume with a later ticket
+    fn rtc_connect(
+        &self,
+        ticket: ArenaTicket,
+    ) -> SharedFuture<Result<(BoxedRtcSession, JoinAnswer), Self::Error>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ice_candidate_json() {
+        // has to match the browser's RTCIceCandidateInit
+        let candidate = IceCandidate {
+            candidate: "candidate:1 1 udp 1 10.0.0.1 9 typ host".into(),
+            sdp_mid: None,
+            sdp_mline_index: Some(0),
+        };
+        let json = serde_json::to_value(&candidate).unwrap();
+        assert_eq!(json["sdpMid"], serde_json::Value::Null);
+        assert_eq!(json["sdpMLineIndex"], 0);
+        let parsed: IceCandidate = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, candidate);
+    }
 }
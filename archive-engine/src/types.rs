@@ -98,6 +98,238 @@ pub type R = Num;
 // be used in the single threaded browser environment
 pub type SharedFuture<T> = Pin<Box<dyn Future<Output = T>>>;
 
+// FNV-1a, for hashes that have to come out the same on every build and
+// platform, which std's hashers don't promise
+pub(crate) const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+pub(crate) fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+// one bit per number in a component, first field lowest
+pub type FieldBits = u8;
+
+// splits a value into the numbers it's made of, so a diff can carry just
+// the ones that changed. derived for every math component.
+// frac_bits is how many fraction bits of each Num can be nonzero, the
+// rest are left off the wire
+pub trait FieldMask: Copy + Default + PartialEq {
+    const FIELDS: u32;
+
+    // the fields that aren't zero, which in a diff are the changed ones
+    fn nonzero_fields(&self) -> FieldBits;
+    fn write_fields<S: serde::ser::SerializeTuple>(
+        &self,
+        mask: FieldBits,
+        frac_bits: u32,
+        out: &mut S,
+    ) -> Result<(), S::Error>;
+    // fields left out of the mask come back as zero
+    fn read_fields<'de, A: serde::de::SeqAccess<'de>>(
+        mask: FieldBits,
+        frac_bits: u32,
+        seq: &mut A,
+    ) -> Result<Self, A::Error>;
+
+    fn all_fields() -> FieldBits {
+        ((1u32 << Self::FIELDS) - 1) as FieldBits
+    }
+}
+
+macro_rules! impl_field_mask_leaf {
+    ($($t:ty),*) => {
+        $(
+            impl FieldMask for $t {
+                const FIELDS: u32 = 1;
+
+                fn nonzero_fields(&self) -> FieldBits {
+                    (*self != Self::default()) as FieldBits
+                }
+                fn write_fields<S: serde::ser::SerializeTuple>(
+                    &self,
+                    mask: FieldBits,
+                    _frac_bits: u32,
+                    out: &mut S,
+                ) -> Result<(), S::Error> {
+                    if mask & 1 != 0 {
+                        out.serialize_element(self)?;
+                    }
+                    Ok(())
+                }
+                fn read_fields<'de, A: serde::de::SeqAccess<'de>>(
+                    mask: FieldBits,
+                    _frac_bits: u32,
+                    seq: &mut A,
+                ) -> Result<Self, A::Error> {
+                    if mask & 1 == 0 {
+                        return Ok(Self::default());
+                    }
+                    seq.next_element()?
+                        .ok_or_else(|| serde::de::Error::custom("missing masked field"))
+                }
+            }
+        )*
+    };
+}
+
+impl_field_mask_leaf!(Zed, std::num::Wrapping<u16>);
+
+// a diff of quantized numbers is a whole number of steps, and usually a
+// small one, so only the step count goes out
+impl FieldMask for Num {
+    const FIELDS: u32 = 1;
+
+    fn nonzero_fields(&self) -> FieldBits {
+        (*self != Self::default()) as FieldBits
+    }
+    fn write_fields<S: serde::ser::SerializeTuple>(
+        &self,
+        mask: FieldBits,
+        frac_bits: u32,
+        out: &mut S,
+    ) -> Result<(), S::Error> {
+        if mask & 1 != 0 {
+            let shift = Num::FRAC_NBITS - frac_bits;
+            let bits = self.0.to_bits();
+            debug_assert_eq!(bits & ((1 << shift) - 1), 0, "not quantized");
+            out.serialize_element(&Varint(bits >> shift))?;
+        }
+        Ok(())
+    }
+    fn read_fields<'de, A: serde::de::SeqAccess<'de>>(
+        mask: FieldBits,
+        frac_bits: u32,
+        seq: &mut A,
+    ) -> Result<Self, A::Error> {
+        if mask & 1 == 0 {
+            return Ok(Self::default());
+        }
+        let Varint(steps) = seq
+            .next_element()?
+            .ok_or_else(|| serde::de::Error::custom("missing masked field"))?;
+        let shift = Num::FRAC_NBITS - frac_bits;
+        Ok(fixed::Wrapping(I12F20::from_bits(steps << shift)))
+    }
+}
+
+const MAX_VARINT_BYTES: usize = 5;
+
+// zigzag LEB128, seven bits a byte, so numbers near zero either way take
+// a single byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Varint(pub i32);
+
+impl Serialize for Varint {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+
+        let mut zigzag = ((self.0 << 1) ^ (self.0 >> 31)) as u32;
+        let mut bytes = Vec::with_capacity(MAX_VARINT_BYTES);
+        while zigzag >= 0x80 {
+            bytes.push(zigzag as u8 | 0x80);
+            zigzag >>= 7;
+        }
+        bytes.push(zigzag as u8);
+
+        let mut tuple = serializer.serialize_tuple(bytes.len())?;
+        for byte in &bytes {
+            tuple.serialize_element(byte)?;
+        }
+        tuple.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Varint {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct VarintVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for VarintVisitor {
+            type Value = Varint;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "a zigzag LEB128 varint")
+            }
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Varint, A::Error> {
+                let mut zigzag = 0u32;
+                for i in 0..MAX_VARINT_BYTES {
+                    let byte: u8 = seq
+                        .next_element()?
+                        .ok_or_else(|| serde::de::Error::invalid_length(i, &self))?;
+                    zigzag |= ((byte & 0x7f) as u32) << (7 * i);
+                    if byte & 0x80 == 0 {
+                        return Ok(Varint((zigzag >> 1) as i32 ^ -((zigzag & 1) as i32)));
+                    }
+                }
+                Err(serde::de::Error::custom("varint runs past 5 bytes"))
+            }
+        }
+
+        // the most it could be, the high bits say where it really ends
+        deserializer.deserialize_tuple(MAX_VARINT_BYTES, VarintVisitor)
+    }
+}
+
+// structs are their fields' fields, in order
+macro_rules! impl_field_mask {
+    ($name:ident { $($field:ident: $fty:ty),* }) => {
+        impl crate::FieldMask for $name {
+            const FIELDS: u32 = 0 $(+ <$fty as crate::FieldMask>::FIELDS)*;
+
+            fn nonzero_fields(&self) -> crate::FieldBits {
+                let mut mask = 0;
+                let mut _shift = 0;
+                $(
+                    mask |= crate::FieldMask::nonzero_fields(&self.$field) << _shift;
+                    _shift += <$fty as crate::FieldMask>::FIELDS;
+                )*
+                mask
+            }
+            fn write_fields<S: serde::ser::SerializeTuple>(
+                &self,
+                _mask: crate::FieldBits,
+                _frac_bits: u32,
+                _out: &mut S,
+            ) -> Result<(), S::Error> {
+                let mut _shift = 0;
+                $(
+                    crate::FieldMask::write_fields(&self.$field, _mask >> _shift, _frac_bits, _out)?;
+                    _shift += <$fty as crate::FieldMask>::FIELDS;
+                )*
+                Ok(())
+            }
+            fn read_fields<'de, A: serde::de::SeqAccess<'de>>(
+                _mask: crate::FieldBits,
+                _frac_bits: u32,
+                _seq: &mut A,
+            ) -> Result<Self, A::Error> {
+                let mut _shift = 0;
+                Ok($name {
+                    $($field: {
+                        let field = <$fty as crate::FieldMask>::read_fields(_mask >> _shift, _frac_bits, _seq)?;
+                        _shift += <$fty as crate::FieldMask>::FIELDS;
+                        field
+                    }),*
+                })
+            }
+        }
+        // one bit each in the mask, so more would silently shift off the end
+        const _: () = assert!(
+            <$name as crate::FieldMask>::FIELDS <= crate::FieldBits::BITS,
+            "too many fields for FieldBits"
+        );
+    };
+}
+
+impl_field_mask!(V2 { x: Num, y: Num });
+
 macro_rules! map_types {
     (|$param:ident| $body:block, ($($kinds:ty),*)) => {
         ($({
@@ -111,7 +343,10 @@ macro_rules! map_all_components {
     (|$param:ident| $body:block) => {
         map_types!(
             |$param| $body,
-            (Position, Rotation, Velocity, Camera, Player, Input, Bullet, Health)
+            (
+                Position, Rotation, Velocity, Scale, Camera, Player, Input, Bullet, Health, Weapon,
+                Replicated
+            )
         )
     };
 }
@@ -119,24 +354,36 @@ macro_rules! map_all_components {
 pub(crate) use map_all_components;
 pub(crate) use map_types;
 
+// field names and types of a component as written, for the schema hash.
+// derived for every component
+pub trait ComponentSchema {
+    const SCHEMA: &'static [(&'static str, &'static str)];
+}
+
 macro_rules! derive_components {
-    ($($i:item)*) => {
+    ($($(#[$meta:meta])* $vis:vis struct $name:ident { $($fvis:vis $field:ident: $fty:ty),* $(,)? })*) => {
         $(
             #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
-            $i
+            $(#[$meta])*
+            $vis struct $name { $($fvis $field: $fty),* }
+
+            impl crate::ComponentSchema for $name {
+                const SCHEMA: &'static [(&'static str, &'static str)] =
+                    &[$((stringify!($field), stringify!($fty))),*];
+            }
         )*
     }
 }
 
 macro_rules! derive_math_components {
-
-    ($($i:item)*) => {
+    ($($vis:vis struct $name:ident { $($fvis:vis $field:ident: $fty:ty),* $(,)? })*) => {
         derive_components! {
             $(
                 #[derive(derive_more::Add, derive_more::AddAssign, derive_more::Sub, derive_more::SubAssign, derive_more::Neg)]
-                $i
+                $vis struct $name { $($fvis $field: $fty),* }
             )*
         }
+        $(impl_field_mask!($name { $($field: $fty),* });)*
     }
 }
 
@@ -153,11 +400,38 @@ macro_rules! derive_delta {
 pub(crate) use derive_components;
 pub(crate) use derive_delta;
 pub(crate) use derive_math_components;
+pub(crate) use impl_field_mask;
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_varint() {
+        for (n, len) in [
+            (0, 1),
+            (-1, 1),
+            (63, 1),
+            (-64, 1),
+            (64, 2),
+            (1000, 2),
+            (i32::MIN, 5),
+        ] {
+            let bytes = bincode::serialize(&Varint(n)).unwrap();
+            assert_eq!(bytes.len(), len, "{n}");
+            assert_eq!(bincode::deserialize::<Varint>(&bytes).unwrap(), Varint(n));
+        }
+        assert_eq!(
+            bincode::deserialize::<Varint>(&bincode::serialize(&Varint(i32::MAX)).unwrap())
+                .unwrap(),
+            Varint(i32::MAX)
+        );
+        // never ends
+        assert!(bincode::deserialize::<Varint>(&[0x80; 6]).is_err());
+        // ends early
+        assert!(bincode::deserialize::<Varint>(&[0x80]).is_err());
+    }
+
     #[test]
     fn num_micros() {
         let num = mk_num!(0.5);
@@ -1,17 +1,89 @@
+mod native_client_rtc;
+mod native_gamepad;
 mod native_random;
 mod tungstenite_client_rtc;
 
 use std::sync::mpsc;
 
 use archive_client::*;
-use archive_engine::{rtc::RtcServerDescriptor, *};
-use log::error;
+use archive_engine::*;
+use archive_server::session;
+use log::{error, warn};
+use native_gamepad::NativeGamepadBuilder;
 use native_random::NativeRandomBuilder;
 
+const BINDINGS_PATH: &str = "bindings.json";
+const RTC_HOSTNAME: &str = "http://localhost:3030";
+const WS_HOSTNAME: &str = "ws://localhost:8080";
+
+// rebinds keys from bindings.json in the working directory, if there is one
+fn load_bindings() -> input::Bindings {
+    let json = match std::fs::read_to_string(BINDINGS_PATH) {
+        Ok(json) => json,
+        Err(_) => return Default::default(),
+    };
+    input::Bindings::from_json(&json).unwrap_or_else(|e| {
+        error!("ignoring {BINDINGS_PATH}: {e}");
+        Default::default()
+    })
+}
+
+// `archive-native REPLAY [CLIENT_ID]` plays back what a server recorded
+// with --record-sent for that client instead of connecting
+fn replay_session() -> Option<anyhow::Result<rtc::BoxedRtcSession>> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next()?;
+    Some((|| -> anyhow::Result<rtc::BoxedRtcSession> {
+        let client_id = match args.next() {
+            Some(id) => id.parse()?,
+            None => 0,
+        };
+        let replay = ecs::Replay::decode(&std::fs::read(&path)?)?;
+        Ok(Box::new(client::ReplaySession::new(&replay, client_id)?))
+    })())
+}
+
+// webrtc first, then the websocket for networks that block udp
+async fn connect(
+    ticket: rtc::ArenaTicket,
+) -> anyhow::Result<(session::EnumRtcSession, rtc::JoinAnswer)> {
+    let rtc_handle = native_client_rtc::NativeServerHandle {
+        hostname: RTC_HOSTNAME.into(),
+    };
+    match rtc_handle.rtc_connect_raw(ticket.clone()).await {
+        Ok((session, answer)) => return Ok((session.into(), answer)),
+        // the server turned us down, it would say the same over the websocket
+        Err(e) if e.downcast_ref::<rtc::JoinError>().is_some() => return Err(e),
+        Err(e) => warn!("webrtc failed, falling back to websocket: {e}"),
+    }
+    let ws_handle = tungstenite_client_rtc::TungsteniteServerHandle {
+        hostname: WS_HOSTNAME.into(),
+    };
+    let (session, answer) = ws_handle.rtc_connect_raw(ticket).await?;
+    Ok((session.into(), answer))
+}
+
+// the client only polls this once a frame, so the connect itself runs on
+// the tokio workers
+fn reconnect(ticket: rtc::ArenaTicket) -> SharedFuture<Result<rtc::BoxedRtcSession, String>> {
+    let task = tokio::spawn(connect(ticket));
+    Box::pin(async move {
+        match task.await {
+            Ok(Ok((session, _))) => Ok(Box::new(session) as rtc::BoxedRtcSession),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(e) => Err(e.to_string()),
+        }
+    })
+}
+
 #[tokio::main]
 async fn main() {
     random::register(NativeRandomBuilder {});
-    launch_config::register(launch_config::LaunchConfig { sample_count: 1 });
+    gamepad::register(NativeGamepadBuilder {});
+    launch_config::register(launch_config::LaunchConfig {
+        sample_count: 1,
+        bindings: load_bindings(),
+    });
 
     let event_loop = winit::event_loop::EventLoop::new();
     let window = winit::window::Window::new(&event_loop).unwrap();
@@ -19,12 +91,31 @@ async fn main() {
     env_logger::init();
     let (tx, rx) = mpsc::channel();
 
-    let server_handle = tungstenite_client_rtc::TungsteniteServerHandle {
-        hostname: "ws://localhost:8080".into(),
+    if let Some(session) = replay_session() {
+        match session {
+            Ok(session) => tx
+                .send(client::ClientMessageFromApp::Connected(session))
+                .unwrap(),
+            Err(e) => error!("failed to load replay: {e}"),
+        }
+        let run = run_init(event_loop, window, rx).await;
+        run();
+        return;
+    }
+
+    let arena_ukey = 0;
+    let ticket = rtc::ArenaTicket {
+        arena_ukey,
+        resume: None,
     };
-    match server_handle.rtc_connect().await {
-        Ok(session) => {
-            tx.send(client::ClientMessageFromApp::Connected(session))
+    match connect(ticket).await {
+        Ok((session, answer)) => {
+            let rejoin = client::Rejoin {
+                ticket: answer.resume_ticket(arena_ukey),
+                connect: Box::new(reconnect),
+            };
+            let session = Box::new(session);
+            tx.send(client::ClientMessageFromApp::Joined { session, rejoin })
                 .unwrap();
         }
         Err(e) => {
@@ -1,115 +1,165 @@
-// NOTE: this whole file just doesn't work.
-// I independently ran into the same problem as https://github.com/webrtc-rs/webrtc/issues/115
-// which seems unsolvable without using trickle ICE which I have no interest in right now.
-// Going to create a websocket fallback instead.
+use std::sync::Arc;
+
 use archive_engine::{rtc::*, *};
 use archive_server::*;
 
 use anyhow::{bail, Result};
 
-use hyper::{Body, Client, Method, Request};
+use hyper::{Body, Client, Method, Request, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
 
-use log::info;
+use log::{info, warn};
+use tokio::time::Duration;
 use webrtc::{
     data_channel::data_channel_init::RTCDataChannelInit,
     peer_connection::sdp::{sdp_type::RTCSdpType, session_description::RTCSessionDescription},
 };
 
+// between /signal/ice calls while either side is still gathering
+const TRICKLE_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone)]
 pub struct NativeServerHandle {
     pub hostname: String,
 }
 
 impl NativeServerHandle {
-    async fn rtc_signal(
-        hostname: String,
-        client_offer: rtc::ClientOffer,
-    ) -> Result<rtc::ServerAnswer> {
-        let offer_serialized = serde_json::to_string(&client_offer)?;
+    async fn post_json<T: Serialize, R: DeserializeOwned>(url: String, body: &T) -> Result<R> {
+        let body_serialized = serde_json::to_string(body)?;
 
-        info!("offering '{offer_serialized}'");
+        info!("posting '{body_serialized}' to {url}");
 
         let client = Client::new();
 
         let req = Request::builder()
             .method(Method::POST)
-            .uri(&format!("{hostname}/signal"))
+            .uri(&url)
             .header("Content-Type", "application/json")
             .header("Accept", "application/json")
-            .body(Body::from(offer_serialized))?;
+            .body(Body::from(body_serialized))?;
 
         let resp = client.request(req).await?;
+        if resp.status() == StatusCode::CONFLICT {
+            // the server turned down the join itself
+            let bytes = hyper::body::to_bytes(resp.into_body()).await?;
+            let error: rtc::JoinError = serde_json::from_slice(&bytes)?;
+            return Err(error.into());
+        }
         if !resp.status().is_success() {
             bail!("bad signal status: {}", resp.status());
         }
         let bytes = hyper::body::to_bytes(resp.into_body()).await?;
-        let server_answer: rtc::ServerAnswer = serde_json::from_slice(&bytes)?;
-        Ok(server_answer)
+        Ok(serde_json::from_slice(&bytes)?)
     }
-    async fn rtc_connect_raw(hostname: String) -> Result<session::NativeRtcSession> {
+
+    // swaps candidates with the server until both sides are done gathering
+    async fn trickle_loop(
+        hostname: String,
+        signal_id: rtc::SignalId,
+        trickle: Arc<session::Trickle>,
+    ) -> Result<()> {
+        loop {
+            let (candidates, local_done) = trickle.take_local();
+            let ice_offer = rtc::IceOffer {
+                signal_id: signal_id.clone(),
+                candidates,
+            };
+            let ice_answer: rtc::IceAnswer =
+                Self::post_json(format!("{hostname}/signal/ice"), &ice_offer).await?;
+            trickle.add_remote(ice_answer.candidates).await?;
+            if local_done && ice_answer.done {
+                return Ok(());
+            }
+            tokio::time::sleep(TRICKLE_INTERVAL).await;
+        }
+    }
+
+    pub async fn rtc_connect_raw(
+        &self,
+        ticket: rtc::ArenaTicket,
+    ) -> Result<(session::NativeRtcSession, rtc::JoinAnswer)> {
         let peer_connection = session::create_peer_connection().await?;
 
+        // has to exist before the offer so the SDP includes it
+        let data_channel = peer_connection
+            .create_data_channel(
+                "udp",
+                Some(RTCDataChannelInit {
+                    ordered: Some(false),
+                    max_packet_life_time: None,
+                    max_retransmits: Some(0),
+                    protocol: None,
+                    negotiated: None,
+                    id: None,
+                }),
+            )
+            .await?;
+
         let offer = peer_connection.create_offer(None).await?;
 
-        // Create channel that is blocked until ICE Gathering is complete
-        let mut gather_complete = peer_connection.gathering_complete_promise().await;
+        // has to be listening before gathering starts
+        let trickle = session::Trickle::new(peer_connection.clone()).await;
 
-        // Sets the LocalDescription, and starts our UDP listeners
+        // Sets the LocalDescription, and starts our UDP listeners. candidates
+        // go out through /signal/ice as they're found
         peer_connection.set_local_description(offer).await?;
 
-        // Block until ICE Gathering is complete, disabling trickle ICE
-        // we do this because we only can exchange one signaling message
-        // in a production application you should exchange ICE Candidates via OnICECandidate
-        let _ = gather_complete.recv().await;
-
-        // Output the answer in base64 so we can paste it in browser
         let sdp = if let Some(local_desc) = peer_connection.local_description().await {
             local_desc.sdp
         } else {
             bail!("failed to generate offer local description");
         };
 
-        let server_answer = Self::rtc_signal(
-            hostname,
-            rtc::ClientOffer {
-                ticket: ArenaTicket { arena_ukey: 0 },
-                sdp,
-            },
-        )
-        .await?;
+        let client_offer = rtc::ClientOffer {
+            protocol: rtc::ProtocolInfo::current(),
+            ticket,
+            sdp,
+        };
+        let server_answer: rtc::ServerAnswer =
+            Self::post_json(format!("{}/signal", self.hostname), &client_offer).await?;
+        info!("joined as client #{}", server_answer.client_id);
+        let join_answer = server_answer.join_answer();
 
         let mut answer = RTCSessionDescription::default();
         answer.sdp_type = RTCSdpType::Answer;
         answer.sdp = server_answer.sdp;
         peer_connection.set_remote_description(answer).await?;
 
-        peer_connection
-            .create_data_channel(
-                "udp",
-                Some(RTCDataChannelInit {
-                    ordered: Some(false),
-                    max_packet_life_time: None,
-                    max_retransmits: Some(0),
-                    protocol: None,
-                    negotiated: None,
-                    id: None,
-                }),
-            )
-            .await?;
-        let session = session::NativeRtcSession::new(peer_connection).await?;
-        Ok(session)
+        let session = session::NativeRtcSession::new_with_channel(peer_connection, data_channel);
+        tokio::pin!(session);
+        let trickle_loop =
+            Self::trickle_loop(self.hostname.clone(), server_answer.signal_id, trickle);
+        tokio::pin!(trickle_loop);
+        let session = tokio::select! {
+            // the session registers its handlers on the first poll
+            biased;
+            session = &mut session => session,
+            result = &mut trickle_loop => {
+                // the server forgets the signal id once it's connected, so
+                // this can fail just before the channel opens
+                if let Err(e) = result {
+                    warn!("stopped trickling: {e}");
+                }
+                session.await
+            }
+        }?;
+        Ok((session, join_answer))
     }
 }
 
 impl rtc::RtcServerDescriptor for NativeServerHandle {
     type Error = anyhow::Error;
 
-    fn rtc_connect(&self) -> SharedFuture<Result<rtc::BoxedRtcSession, Self::Error>> {
-        let hostname = self.hostname.clone();
+    fn rtc_connect(
+        &self,
+        ticket: rtc::ArenaTicket,
+    ) -> SharedFuture<Result<(rtc::BoxedRtcSession, rtc::JoinAnswer), Self::Error>> {
+        let handle = self.clone();
 
         Box::pin(async move {
-            let session = Self::rtc_connect_raw(hostname).await?;
+            let (session, answer) = handle.rtc_connect_raw(ticket).await?;
             let boxed: Box<dyn RtcSession> = Box::new(session);
-            Ok(boxed)
+            Ok((boxed, answer))
         })
     }
 }
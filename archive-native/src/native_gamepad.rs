@@ -0,0 +1,42 @@
+use archive_client::gamepad::{Gamepad, GamepadBuilderImpl, GamepadImpl, GamepadState};
+use gilrs::{Axis, Button, GamepadId, Gilrs};
+use log::error;
+
+pub struct NativeGamepadBuilder {}
+struct NativeGamepad {
+    // None if gilrs couldn't start, e.g. no permission to read the devices
+    gilrs: Option<Gilrs>,
+    // the pad that sent the latest event
+    active: Option<GamepadId>,
+}
+
+impl GamepadBuilderImpl for NativeGamepadBuilder {
+    fn create(&self) -> Gamepad {
+        let gilrs = Gilrs::new()
+            .map_err(|e| error!("gamepads unavailable: {e}"))
+            .ok();
+        Box::new(NativeGamepad {
+            gilrs,
+            active: None,
+        })
+    }
+}
+impl GamepadImpl for NativeGamepad {
+    fn poll(&mut self) -> Option<GamepadState> {
+        let gilrs = self.gilrs.as_mut()?;
+        // gilrs only updates its cached state as events are drained
+        while let Some(event) = gilrs.next_event() {
+            self.active = Some(event.id);
+        }
+        let pad = gilrs.connected_gamepad(self.active?)?;
+
+        let pressed = |buttons: &[Button]| buttons.iter().any(|&b| pad.is_pressed(b));
+        Some(GamepadState {
+            left_stick: [pad.value(Axis::LeftStickX), pad.value(Axis::LeftStickY)],
+            right_stick: [pad.value(Axis::RightStickX), pad.value(Axis::RightStickY)],
+            fire: pressed(&[Button::RightTrigger2, Button::RightTrigger]),
+            reload: pressed(&[Button::West]),
+            interact: pressed(&[Button::South]),
+        })
+    }
+}
@@ -4,33 +4,59 @@ use archive_engine::{
     *,
 };
 use archive_server::session;
-use futures::SinkExt;
+use futures::{SinkExt, StreamExt};
+use log::info;
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
 
+#[derive(Clone)]
 pub struct TungsteniteServerHandle {
     pub hostname: String,
 }
 
 impl TungsteniteServerHandle {
-    async fn rtc_connect_raw(hostname: String) -> Result<session::MpscRtcSession> {
-        let (mut ws_stream, _) = connect_async(hostname).await.context("Failed to connect")?;
-        let ticket = ArenaTicket { arena_ukey: 0 };
-        let ticket_bin = bincode::serialize(&ticket)?;
-        ws_stream.send(Message::Binary(ticket_bin)).await?;
-        session::MpscRtcSession::new_from_tungstenite(ws_stream).await
+    pub async fn rtc_connect_raw(
+        &self,
+        ticket: ArenaTicket,
+    ) -> Result<(session::MpscRtcSession, rtc::JoinAnswer)> {
+        let (mut ws_stream, _) = connect_async(&self.hostname)
+            .await
+            .context("Failed to connect")?;
+        let request = rtc::JoinRequest {
+            protocol: rtc::ProtocolInfo::current(),
+            ticket,
+        };
+        let request_bin = bincode::serialize(&request)?;
+        ws_stream.send(Message::Binary(request_bin)).await?;
+
+        // the server answers the ticket before anything else
+        let answer = ws_stream
+            .next()
+            .await
+            .context("ws closed before join answer")??
+            .into_data();
+        let answer: rtc::JoinResult =
+            bincode::deserialize(&answer[..]).context("failed to parse join answer")?;
+        let answer = answer?;
+        info!("joined as client #{}", answer.client_id);
+
+        let session = session::MpscRtcSession::new_from_tungstenite(ws_stream).await?;
+        Ok((session, answer))
     }
 }
 
 impl rtc::RtcServerDescriptor for TungsteniteServerHandle {
     type Error = anyhow::Error;
 
-    fn rtc_connect(&self) -> SharedFuture<Result<rtc::BoxedRtcSession, Self::Error>> {
-        let hostname = self.hostname.clone();
+    fn rtc_connect(
+        &self,
+        ticket: ArenaTicket,
+    ) -> SharedFuture<Result<(rtc::BoxedRtcSession, rtc::JoinAnswer), Self::Error>> {
+        let handle = self.clone();
 
         Box::pin(async move {
-            let session = Self::rtc_connect_raw(hostname).await?;
+            let (session, answer) = handle.rtc_connect_raw(ticket).await?;
             let boxed: Box<dyn RtcSession> = Box::new(session);
-            Ok(boxed)
+            Ok((boxed, answer))
         })
     }
 }
@@ -1,79 +1,672 @@
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
+use super::*;
 use crate::*;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use archive_engine::{
-    rtc::{ClientId, RtcSession},
+    rtc::{ClientId, ClientToken, RtcSession},
     *,
 };
 
-use log::{error, info};
+use log::{debug, error, info, warn};
+use serde::Serialize;
 use webrtc::peer_connection::math_rand_alpha;
 
+const RESUME_TOKEN_LEN: usize = 32;
+// deltas between snapshot checksums, twice a second at 60 ticks
+const CHECKSUM_INTERVAL: rtc::DeltaSeq = 30;
+// pongs owed to a client at once, clients ping a few times a second so
+// anything past this is a flood
+const MAX_PENDING_PONGS: usize = 4;
+// sends between replay flushes, once a second at 60 ticks
+const REPLAY_FLUSH_SENDS: u64 = 60;
+
 #[derive(Default)]
 pub struct Arena {
+    arena_ukey: rtc::ArenaUkey,
     pub(super) realm: ecs::Realm,
     pub(super) clients: BTreeMap<rtc::ClientId, ClientHandle>,
+    client_pool: containers::TokenPool,
+    config: Arc<config::ServerConfig>,
+    phase: ArenaPhase,
+    // when the last client left, None while anyone is around
+    empty_since: Option<Instant>,
+    // tick_asyncs so far. unlike the realm's tick this keeps counting
+    // outside Running, so replays can pace what was sent
+    sends: u64,
+    // where the replay goes, if this server keeps them
+    replay_writer: Option<ReplayWriter>,
 }
 impl Arena {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(arena_ukey: rtc::ArenaUkey, config: Arc<config::ServerConfig>) -> Self {
+        let mut realm = ecs::Realm::new();
+        let replay_writer = config.replay_dir.as_ref().map(|dir| {
+            realm
+                .start_recording(config.record_sent)
+                .expect("new realms are empty");
+            let secs = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let path = dir.join(format!("arena-{arena_ukey}-{secs}.replay"));
+            ReplayWriter::start(path, realm.replay().unwrap().encode_header())
+        });
+        Arena {
+            arena_ukey,
+            realm,
+            config,
+            replay_writer,
+            empty_since: Some(Instant::now()),
+            ..Default::default()
+        }
+    }
+
+    pub fn phase(&self) -> ArenaPhase {
+        self.phase
+    }
+    pub fn is_joinable(&self) -> bool {
+        self.phase.is_joinable() && self.clients.len() < self.config.arena_capacity
     }
+    // finished arenas and ones nobody has been in for a while can be torn down
+    pub fn is_done(&self, now: Instant) -> bool {
+        let abandoned = match self.empty_since {
+            Some(since) => now.duration_since(since) > self.config.reconnect_grace,
+            None => false,
+        };
+        self.phase == ArenaPhase::Finished || abandoned
+    }
+    // kicks everyone, used right before the arena is dropped
+    pub fn shutdown(&mut self) {
+        for handle in self.clients.values() {
+            if let Some(session) = &handle.session {
+                session.close();
+            }
+        }
+        self.phase = ArenaPhase::Finished;
+        metrics::ARENA_DELTA_BYTES.remove(self.arena_ukey);
+        self.flush_replay();
+        // closing the channel lets the writer finish what's queued
+        self.replay_writer = None;
+        self.realm.take_replay();
+    }
+    // hands what was recorded since the last flush to the writer
+    fn flush_replay(&mut self) {
+        let writer = match &self.replay_writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        let events = self.realm.take_replay_events();
+        if !events.is_empty() {
+            writer.write(ecs::Replay::encode_events(&events));
+        }
+    }
+
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let connected = self
+            .clients
+            .values()
+            .filter(|handle| handle.session.is_some())
+            .count();
+        let alive = self.realm.player_count();
+        self.phase = self.phase.next(now, connected, alive, &self.config);
+
+        if self.phase == ArenaPhase::Running {
+            self.realm.run_systems();
+        }
 
-    pub fn tick(&mut self) {}
+        if !self.clients.is_empty() {
+            self.empty_since = None;
+        } else if self.empty_since.is_none() {
+            self.empty_since = Some(now);
+        }
+    }
     pub async fn tick_async(&mut self) {
+        let now = Instant::now();
+        let tick = self.realm.tick;
+        let send = self.sends;
+        self.sends += 1;
+        // everyone gets the same events, so only encode them once
+        let events = self.realm.take_events();
+        let events = if events.is_empty() {
+            None
+        } else {
+            Some(rtc::encode_message(&rtc::ServerMessage::Events {
+                tick,
+                events,
+            }))
+        };
         let mut to_drop = Vec::<ClientId>::new();
+        let mut delta_bytes = 0;
         for (client_id, handle) in self.clients.iter_mut() {
             if handle.session.is_none() {
+                // either still handshaking or waiting on a reconnect
+                if handle.grace_expired(now, &self.config) {
+                    to_drop.push(*client_id);
+                }
                 continue;
             }
             let session = handle.session.as_mut().unwrap();
 
             match session.get_state() {
-                // just kick them for now
-                rtc::SessionState::Disconnected => session.close(),
+                rtc::SessionState::Disconnected => {
+                    // wait for it to report Closed before detaching
+                    session.close();
+                    continue;
+                }
                 rtc::SessionState::Closed => {
-                    to_drop.push(*client_id);
+                    info!("client #{client_id} disconnected, holding their slot");
+                    handle.detach(now);
+                    // don't leave them running into a wall
+                    self.realm.set_input(*client_id, Default::default());
                     continue;
                 }
-                // TODO add some grace period/kick them here
                 rtc::SessionState::Connecting => continue,
                 rtc::SessionState::Connected => (),
             };
-            let message = math_rand_alpha(15);
-            let send_ok = session.send_impl(Vec::from(message.as_bytes())).await;
+            handle.recv_messages(*client_id, &mut self.realm);
+
+            let message = self
+                .realm
+                .with_camera_for(*client_id, |realm| handle.make_delta(realm));
+            self.realm.record_sent(send, *client_id, &message);
+            delta_bytes += message.len() as u64;
+            let session = handle.session.as_ref().unwrap();
+            let mut send_ok = session.send_impl(message).await;
+            if let Some(events) = &events {
+                self.realm.record_sent(send, *client_id, events);
+                send_ok &= session.send_impl(events.clone()).await;
+            }
+            for id in handle.pongs.drain(..) {
+                let pong = rtc::encode_message(&rtc::ServerMessage::Pong { id, tick });
+                send_ok &= session.send_impl(pong).await;
+            }
             if !send_ok {
+                metrics::SEND_FAILURES.inc();
                 error!("failed to send to client #{client_id}");
             }
         }
-        // drop session handles that have been closed
+        if delta_bytes > 0 {
+            metrics::ARENA_DELTA_BYTES.add(self.arena_ukey, delta_bytes);
+        }
+        // drop clients that didn't come back in time
         for client_id in to_drop {
             info!("dropping disconnected client #{client_id}");
-            self.clients.remove(&client_id);
+            self.drop_client(client_id);
+        }
+        if self.sends % REPLAY_FLUSH_SENDS == 0 {
+            self.flush_replay();
+        }
+    }
+
+    // frees the client's slot and player, closing their session if they
+    // still have one. returns false if there was no such client
+    pub fn drop_client(&mut self, client_id: ClientId) -> bool {
+        let handle = match self.clients.remove(&client_id) {
+            Some(handle) => handle,
+            None => return false,
+        };
+        if let Some(session) = &handle.session {
+            session.close();
+        }
+        self.client_pool.free(handle.token);
+        self.realm.despawn_player(client_id);
+        true
+    }
+
+    pub fn summary(&self, arena_ukey: rtc::ArenaUkey) -> ArenaSummary {
+        let now = Instant::now();
+        let clients = self
+            .clients
+            .iter()
+            .map(|(&client_id, handle)| ClientSummary {
+                client_id,
+                transport: handle.session.as_ref().map(|s| s.transport_name()),
+                state: handle
+                    .session
+                    .as_ref()
+                    .map(|s| format!("{:?}", s.get_state()).to_lowercase()),
+                detached_secs: handle
+                    .detached_since
+                    .map(|since| now.duration_since(since).as_secs_f64()),
+            })
+            .collect();
+        ArenaSummary {
+            arena_ukey,
+            phase: self.phase.name(),
+            tick: self.realm.tick,
+            entities: self.realm.entity_count(),
+            clients,
         }
     }
 
-    pub fn alloc_client(&mut self, client_id: rtc::ClientId) {
-        self.clients.insert(client_id, ClientHandle::default());
+    // hands out the lowest free ClientId. the token is what the caller should
+    // hold onto, since the id alone will be reused after the client leaves
+    pub fn alloc_client(&mut self) -> Result<ClientJoin> {
+        if !self.phase.is_joinable() {
+            bail!("arena is not accepting players ({:?})", self.phase);
+        }
+        if self.clients.len() >= self.config.arena_capacity {
+            bail!("arena is full");
+        }
+        let token = self.client_pool.alloc();
+        let client_id = match ClientId::try_from(token.key()) {
+            Ok(client_id) => client_id,
+            Err(_) => {
+                // the pool always hands out the lowest key, so this only
+                // happens when every ClientId is taken
+                self.client_pool.free(token);
+                bail!("max clients reached");
+            }
+        };
+        let handle = ClientHandle::new(token);
+        let join = handle.join();
+        self.clients.insert(client_id, handle);
+        self.realm.spawn_player(client_id);
+        Ok(join)
+    }
+    // lets a client back into the slot it was given at join time. the
+    // player entity is untouched, the new session just takes it over
+    pub fn resume_client(&mut self, resume: rtc::ResumeTicket) -> Result<ClientJoin> {
+        let client_id = resume.client_id;
+        if self.phase == ArenaPhase::Finished {
+            bail!("arena to resume has finished");
+        }
+        let handle = self
+            .clients
+            .get(&client_id)
+            .context("client to resume is gone")?;
+        if !auth::tokens_match(&resume.resume_token, &handle.resume_token) {
+            bail!("bad resume token for client #{client_id}");
+        }
+        info!("client #{client_id} is resuming");
+        Ok(ClientJoin {
+            resumed: true,
+            ..handle.join()
+        })
+    }
+    // undoes a join whose handshake failed. fresh slots are freed right
+    // away, resumed ones keep waiting out their grace period
+    pub fn abandon_join(&mut self, join: &ClientJoin) {
+        if join.resumed || !self.client_pool.contains(join.token) {
+            return;
+        }
+        let client_id = join.client_id();
+        let attached = match self.clients.get(&client_id) {
+            Some(handle) => handle.session.is_some(),
+            None => return,
+        };
+        if !attached {
+            info!("freeing client #{client_id} after a failed handshake");
+            self.drop_client(client_id);
+        }
     }
     pub fn process_client_session(
         &mut self,
-        client_id: rtc::ClientId,
+        token: ClientToken,
         session: session::EnumRtcSession,
     ) -> Result<()> {
+        // the slot may have been freed and handed to someone else while this
+        // session was still handshaking
+        if !self.client_pool.contains(token) {
+            session.close();
+            bail!("stale client token for client #{}", token.key());
+        }
+        let client_id = client_id_for_token(token);
         let handle = self
             .clients
             .get_mut(&client_id)
             .context("unreachable: missing client")?;
-        // TODO check if a session already exists?
-        handle.session = Some(session);
+        handle.attach(session);
         Ok(())
     }
 }
 
-#[derive(Default)]
+// only valid for tokens handed out by Arena::alloc_client, which
+// guarantees the key fits
+pub fn client_id_for_token(token: ClientToken) -> ClientId {
+    token.key() as ClientId
+}
+
+// what a successful join/resume hands back to the transport layer
+#[derive(Clone)]
+pub struct ClientJoin {
+    pub token: ClientToken,
+    pub resume_token: rtc::ResumeToken,
+    // whether this took over an existing slot rather than a fresh one
+    pub resumed: bool,
+}
+impl ClientJoin {
+    pub fn client_id(&self) -> ClientId {
+        client_id_for_token(self.token)
+    }
+    pub fn answer(&self) -> rtc::JoinAnswer {
+        rtc::JoinAnswer {
+            client_id: self.client_id(),
+            resume_token: self.resume_token.clone(),
+        }
+    }
+}
+
+// what the admin api reports about an arena
+#[derive(Serialize)]
+pub struct ArenaSummary {
+    pub arena_ukey: rtc::ArenaUkey,
+    pub phase: &'static str,
+    pub tick: u64,
+    pub entities: u32,
+    pub clients: Vec<ClientSummary>,
+}
+#[derive(Serialize)]
+pub struct ClientSummary {
+    pub client_id: ClientId,
+    // None while handshaking or waiting on a reconnect
+    pub transport: Option<&'static str>,
+    pub state: Option<String>,
+    pub detached_secs: Option<f64>,
+}
+
 pub(super) struct ClientHandle {
+    token: ClientToken,
+    resume_token: rtc::ResumeToken,
     // session == None if they are not connected
     session: Option<session::EnumRtcSession>,
+    // when the client last lost its session. new clients count as
+    // detached until their first handshake finishes
+    detached_since: Option<Instant>,
+    // what we've sent this session, by DeltaSeq
     snapshots: rtc::SnapshotBuf<ecs::ServerSnapshot>,
+    next_seq: rtc::DeltaSeq,
+    // newest snapshot the client says it has
+    acked_seq: Option<rtc::DeltaSeq>,
+    // newest input applied, older ones that arrive late are dropped
+    input_seq: Option<rtc::InputSeq>,
+    // pings heard since the last tick, answered along with the next delta
+    pongs: Vec<rtc::PingId>,
+}
+impl ClientHandle {
+    fn new(token: ClientToken) -> Self {
+        ClientHandle {
+            token,
+            resume_token: math_rand_alpha(RESUME_TOKEN_LEN),
+            session: None,
+            detached_since: Some(Instant::now()),
+            snapshots: Default::default(),
+            next_seq: 0,
+            acked_seq: None,
+            input_seq: None,
+            pongs: Vec::new(),
+        }
+    }
+    fn join(&self) -> ClientJoin {
+        ClientJoin {
+            token: self.token,
+            resume_token: self.resume_token.clone(),
+            resumed: false,
+        }
+    }
+    fn attach(&mut self, session: session::EnumRtcSession) {
+        if let Some(old) = self.session.replace(session) {
+            old.close();
+        }
+        self.detached_since = None;
+        // the client may have missed any number of deltas, so throw away
+        // the baselines and resync from an empty snapshot
+        self.snapshots = Default::default();
+        self.next_seq = 0;
+        self.acked_seq = None;
+        self.input_seq = None;
+        self.pongs.clear();
+    }
+    fn detach(&mut self, now: Instant) {
+        self.session = None;
+        self.detached_since = Some(now);
+    }
+    fn recv_messages(&mut self, client_id: ClientId, realm: &mut ecs::Realm) {
+        // drained up front, handling them needs the rest of self
+        let mut received = Vec::new();
+        if let Some(session) = self.session.as_mut() {
+            while let Ok(bytes) = session.try_recv() {
+                received.push(bytes);
+            }
+        }
+        for bytes in received {
+            let message = match rtc::decode_message::<rtc::ClientMessage>(&bytes) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("bad message from client #{client_id}: {e}");
+                    continue;
+                }
+            };
+            match message {
+                rtc::ClientMessage::Ack { seq } => {
+                    // acks arrive out of order, and can't be for the future
+                    if seq < self.next_seq && self.acked_seq.map_or(true, |acked| seq > acked) {
+                        self.acked_seq = Some(seq);
+                    }
+                }
+                rtc::ClientMessage::Input { seq, input } => {
+                    if self.input_seq.map_or(true, |newest| seq > newest) {
+                        self.input_seq = Some(seq);
+                        realm.set_input(client_id, input);
+                    }
+                }
+                rtc::ClientMessage::Ping { id } => self.queue_pong(client_id, id),
+                rtc::ClientMessage::Resync => self.resync(client_id),
+            }
+        }
+    }
+    fn resync(&mut self, client_id: ClientId) {
+        // deltas are already going out from scratch until one is acked, so
+        // repeats can't make anything better, only keep the client from
+        // ever getting a base
+        if self.base_seq().is_none() {
+            debug!("ignoring resync from client #{client_id}, already resyncing");
+            return;
+        }
+        metrics::RESYNC_REQUESTS.inc();
+        warn!("client #{client_id} desynced, resending from scratch");
+        // forget every base, so acks still in flight for snapshots built
+        // on the bad one can't bring it back
+        self.snapshots = Default::default();
+        self.acked_seq = None;
+    }
+    fn queue_pong(&mut self, client_id: ClientId, id: rtc::PingId) {
+        if self.pongs.len() >= MAX_PENDING_PONGS {
+            debug!("dropping ping {id} from client #{client_id}");
+            return;
+        }
+        self.pongs.push(id);
+    }
+    // the acked snapshot, if it's recent enough to still be buffered
+    fn base_seq(&self) -> Option<rtc::DeltaSeq> {
+        let seq = self.acked_seq?;
+        if self.next_seq - seq > rtc::SNAPSHOT_CAP as rtc::DeltaSeq {
+            return None;
+        }
+        match self.snapshots.index(rtc::snapshot_index(seq)) {
+            Ok(Some(_)) => Some(seq),
+            _ => None,
+        }
+    }
+    // diffs the realm against the newest snapshot the client has, and
+    // remembers the result as the snapshot for the next seq
+    fn make_delta(&mut self, realm: &mut ecs::Realm) -> Vec<u8> {
+        let base_seq = self.base_seq();
+        let mut empty = ecs::ServerSnapshot::new();
+        let base = match base_seq {
+            Some(seq) => self
+                .snapshots
+                .index_mut(rtc::snapshot_index(seq))
+                .unwrap()
+                .unwrap(),
+            None => &mut empty,
+        };
+        let delta = ecs::ServerDelta::diff(base, realm);
+        let snapshot = delta.apply_server(base);
+
+        metrics::DELTA_ACTIONS.observe(delta.action_count() as f64);
+        metrics::PRIORITY_BACKLOG.observe(delta.priority_backlog() as f64);
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let checksum = if seq % CHECKSUM_INTERVAL == 0 {
+            Some(snapshot.checksum())
+        } else {
+            None
+        };
+        if let Err(e) = self.snapshots.add(rtc::snapshot_index(seq), snapshot) {
+            error!("failed to buffer snapshot {seq}: {e:?}");
+        }
+
+        let message = rtc::encode_message(&rtc::ServerMessage::Delta {
+            seq,
+            base: base_seq,
+            tick: realm.tick,
+            delta: delta.into_delta(),
+            checksum,
+        });
+        metrics::DELTA_BYTES.observe(message.len() as f64);
+        message
+    }
+    fn grace_expired(&self, now: Instant, config: &config::ServerConfig) -> bool {
+        match self.detached_since {
+            Some(since) => now.duration_since(since) > config.reconnect_grace,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn arena_with_grace(reconnect_grace: Duration) -> Arena {
+        let config = config::ServerConfig {
+            reconnect_grace,
+            ..Default::default()
+        };
+        Arena::new(0, Arc::new(config))
+    }
+    fn resume_ticket(join: &ClientJoin) -> rtc::ResumeTicket {
+        rtc::ResumeTicket {
+            client_id: join.client_id(),
+            resume_token: join.resume_token.clone(),
+        }
+    }
+
+    #[test]
+    fn test_grace_expiry() {
+        let grace = Duration::from_secs(10);
+        let mut arena = arena_with_grace(grace);
+        let join = arena.alloc_client().unwrap();
+        let handle = arena.clients.get_mut(&join.client_id()).unwrap();
+
+        let now = Instant::now();
+        handle.detach(now);
+        assert!(!handle.grace_expired(now, &arena.config));
+        assert!(!handle.grace_expired(now + grace, &arena.config));
+        assert!(handle.grace_expired(now + grace * 2, &arena.config));
+    }
+
+    #[test]
+    fn test_resume_client() {
+        let mut arena = arena_with_grace(Duration::from_secs(10));
+        let join = arena.alloc_client().unwrap();
+        let other = arena.alloc_client().unwrap();
+
+        let resumed = arena.resume_client(resume_ticket(&join)).unwrap();
+        assert!(resumed.resumed);
+        assert_eq!(resumed.token, join.token);
+        assert_eq!(resumed.resume_token, join.resume_token);
+
+        let wrong = rtc::ResumeTicket {
+            resume_token: "x".repeat(RESUME_TOKEN_LEN),
+            ..resume_ticket(&join)
+        };
+        assert!(arena.resume_client(wrong).is_err());
+        // one client's token doesn't open another's slot
+        let stolen = rtc::ResumeTicket {
+            client_id: other.client_id(),
+            ..resume_ticket(&join)
+        };
+        assert!(arena.resume_client(stolen).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resume_expired() {
+        let mut arena = arena_with_grace(Duration::ZERO);
+        let join = arena.alloc_client().unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        arena.tick_async().await;
+        assert!(arena.clients.is_empty());
+        assert!(arena.resume_client(resume_ticket(&join)).is_err());
+
+        // the id gets reused, but not the token
+        let next = arena.alloc_client().unwrap();
+        assert_eq!(next.client_id(), join.client_id());
+        assert!(arena.resume_client(resume_ticket(&join)).is_err());
+    }
+
+    #[test]
+    fn test_pong_cap() {
+        let mut arena = arena_with_grace(Duration::from_secs(10));
+        let join = arena.alloc_client().unwrap();
+        let handle = arena.clients.get_mut(&join.client_id()).unwrap();
+        for id in 0..100 {
+            handle.queue_pong(join.client_id(), id);
+        }
+        assert_eq!(
+            handle.pongs,
+            (0..MAX_PENDING_PONGS as rtc::PingId).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_resync_throttle() {
+        let mut arena = arena_with_grace(Duration::from_secs(10));
+        let join = arena.alloc_client().unwrap();
+        let client_id = join.client_id();
+        let handle = arena.clients.get_mut(&client_id).unwrap();
+
+        // nothing acked yet, so it's sending from scratch already
+        handle.make_delta(&mut arena.realm);
+        handle.resync(client_id);
+        assert!(handle
+            .snapshots
+            .index(rtc::snapshot_index(0))
+            .unwrap()
+            .is_some());
+
+        handle.acked_seq = Some(0);
+        handle.resync(client_id);
+        assert_eq!(handle.acked_seq, None);
+        assert_eq!(handle.base_seq(), None);
+
+        // an ack for a snapshot sent before the resync can't bring it back
+        handle.make_delta(&mut arena.realm);
+        handle.acked_seq = Some(0);
+        assert_eq!(handle.base_seq(), None);
+        handle.resync(client_id);
+        assert_eq!(handle.acked_seq, Some(0));
+    }
+
+    #[test]
+    fn test_abandon_join() {
+        let mut arena = arena_with_grace(Duration::from_secs(10));
+        let join = arena.alloc_client().unwrap();
+
+        // a failed resume leaves the slot waiting out its grace period
+        let resumed = arena.resume_client(resume_ticket(&join)).unwrap();
+        arena.abandon_join(&resumed);
+        assert!(arena.clients.contains_key(&join.client_id()));
+
+        arena.abandon_join(&join);
+        assert!(arena.clients.is_empty());
+    }
 }
@@ -1,30 +1,77 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 
 use super::*;
+use crate::*;
 use archive_engine::*;
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use log::*;
 use tokio::sync::RwLock;
 
 #[derive(Default)]
 pub struct ArenaMap {
     arena_map: BTreeMap<rtc::ArenaUkey, ArenaLock>,
+    config: Arc<config::ServerConfig>,
 }
 pub type ArenaMapLock = Arc<RwLock<ArenaMap>>;
 
 pub type ArenaLock = Arc<RwLock<Arena>>;
+
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
 impl ArenaMap {
-    pub fn get_or_insert_default(&mut self, arena_ukey: rtc::ArenaUkey) -> ArenaLock {
+    pub fn new(config: Arc<config::ServerConfig>) -> Self {
+        ArenaMap {
+            arena_map: BTreeMap::new(),
+            config,
+        }
+    }
+    pub fn get(&self, arena_ukey: rtc::ArenaUkey) -> Option<ArenaLock> {
+        self.arena_map.get(&arena_ukey).cloned()
+    }
+    pub fn get_or_insert_default(&mut self, arena_ukey: rtc::ArenaUkey) -> Result<ArenaLock> {
         if let Some(arena) = self.arena_map.get(&arena_ukey) {
-            arena.clone()
+            Ok(arena.clone())
         } else {
-            let arena = ArenaLock::default();
+            if self.arena_map.len() >= self.config.max_arenas {
+                bail!("max arenas reached");
+            }
+            let arena = Arc::new(RwLock::new(Arena::new(arena_ukey, self.config.clone())));
             self.arena_map.insert(arena_ukey, arena.clone());
             self.start_poll_task(arena.clone());
-            arena
+            info!("created arena {arena_ukey}");
+            Ok(arena)
+        }
+    }
+    pub fn config(&self) -> Arc<config::ServerConfig> {
+        self.config.clone()
+    }
+    // a copy of the arena handles, so callers can lock arenas one at a
+    // time without holding up the whole map
+    pub fn arenas(&self) -> Vec<(rtc::ArenaUkey, ArenaLock)> {
+        self.arena_map
+            .iter()
+            .map(|(&ukey, arena)| (ukey, arena.clone()))
+            .collect()
+    }
+    // forgets the arena at ukey, unless it has been replaced since
+    fn remove_if_same(&mut self, arena_ukey: rtc::ArenaUkey, arena: &ArenaLock) -> bool {
+        match self.arena_map.get(&arena_ukey) {
+            Some(current) if Arc::ptr_eq(current, arena) => {
+                self.arena_map.remove(&arena_ukey);
+                true
+            }
+            _ => false,
         }
     }
+    // lowest ukey without an arena, for arenas the matchmaker creates
+    fn free_ukey(&self) -> rtc::ArenaUkey {
+        let mut ukey = 0;
+        while self.arena_map.contains_key(&ukey) {
+            ukey += 1;
+        }
+        ukey
+    }
     fn start_poll_task(&self, arena_strong: ArenaLock) {
         let arena_weak = Arc::downgrade(&arena_strong);
         std::mem::drop(arena_strong);
@@ -33,12 +80,14 @@ impl ArenaMap {
             loop {
                 if let Some(arena_strong) = arena_weak.upgrade() {
                     let mut arena = arena_strong.write().await;
+                    let tick_start = std::time::Instant::now();
                     arena.tick();
 
                     // use tokio/async when sending because https://github.com/quinn-rs/quinn/issues/867
                     // FIXME this introduces error into the tickrate
                     // calculate a duration for sleep instead
                     arena.tick_async().await;
+                    metrics::TICK_DURATION.observe(tick_start.elapsed().as_secs_f64());
                 } else {
                     break;
                 }
@@ -58,28 +107,102 @@ impl ArenaMap {
 pub async fn process_client_ticket(
     arena_ticket: rtc::ArenaTicket,
     arena_map: ArenaMapLock,
-) -> Result<(rtc::ClientId, ArenaLock)> {
+) -> Result<(ClientJoin, ArenaLock)> {
     // TODO process their ticket using diesel
     let arena_ukey: rtc::ArenaUkey = arena_ticket.arena_ukey;
 
     // first lock arena_map briefly to get access to the corresponding arena
     let arena_lock = {
         let mut arena_map = arena_map.write().await;
-        arena_map.get_or_insert_default(arena_ukey)
+        if arena_ticket.resume.is_some() {
+            // no point spinning up a fresh arena for someone reconnecting
+            arena_map
+                .get(arena_ukey)
+                .context("arena to resume no longer exists")?
+        } else {
+            arena_map.get_or_insert_default(arena_ukey)?
+        }
     };
 
-    let client_id = {
+    let join = {
         // then lock the arena itself to add the client
         let mut arena = arena_lock.write().await;
+        match arena_ticket.resume {
+            Some(resume) => arena.resume_client(resume)?,
+            None => arena.alloc_client()?,
+        }
+    };
 
-        // TODO actual tickets/client_ids
-        if arena.clients.len() == rtc::ClientId::MAX as usize {
-            bail!("max clients reached");
+    Ok((join, arena_lock))
+}
+
+pub async fn summaries(arena_map: ArenaMapLock) -> Vec<ArenaSummary> {
+    let arenas = arena_map.read().await.arenas();
+    let mut summaries = Vec::new();
+    for (ukey, arena) in arenas {
+        summaries.push(arena.read().await.summary(ukey));
+    }
+    summaries
+}
+
+// kicks everyone and forgets the arena
+pub async fn close_arena(arena_map: ArenaMapLock, arena_ukey: rtc::ArenaUkey) -> bool {
+    let arena = match arena_map.write().await.arena_map.remove(&arena_ukey) {
+        Some(arena) => arena,
+        None => return false,
+    };
+    arena.write().await.shutdown();
+    info!("closed arena {arena_ukey}");
+    true
+}
+
+// picks a non-full arena that hasn't started yet, making a new one if
+// there aren't any. the join itself can still fail if it fills up first
+pub async fn matchmake(arena_map: ArenaMapLock) -> Result<rtc::ArenaTicket> {
+    let arenas = arena_map.read().await.arenas();
+
+    let mut arena_ukey = None;
+    for (ukey, arena) in arenas {
+        if arena.read().await.is_joinable() {
+            arena_ukey = Some(ukey);
+            break;
+        }
+    }
+    let arena_ukey = match arena_ukey {
+        Some(ukey) => ukey,
+        None => {
+            let mut arena_map = arena_map.write().await;
+            let ukey = arena_map.free_ukey();
+            arena_map.get_or_insert_default(ukey)?;
+            ukey
         }
-        let client_id = arena.clients.len() as rtc::ClientId;
-        arena.alloc_client(client_id);
-        client_id
     };
 
-    Ok((client_id as _, arena_lock))
+    Ok(rtc::ArenaTicket {
+        arena_ukey,
+        resume: None,
+    })
+}
+
+// periodically drops finished/abandoned arenas from the map. their poll
+// tasks stop on their own once the last reference goes away
+pub async fn reap_arenas(arena_map: ArenaMapLock) {
+    let mut interval = tokio::time::interval(REAP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now = std::time::Instant::now();
+
+        let arenas = arena_map.read().await.arenas();
+        for (ukey, arena_lock) in arenas {
+            let mut arena = arena_lock.write().await;
+            if !arena.is_done(now) {
+                continue;
+            }
+            arena.shutdown();
+            std::mem::drop(arena);
+            if arena_map.write().await.remove_if_same(ukey, &arena_lock) {
+                info!("tearing down arena {ukey}");
+            }
+        }
+    }
 }
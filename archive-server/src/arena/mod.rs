@@ -1,5 +1,9 @@
 mod arena;
 mod arena_map;
+mod phase;
+mod replay_writer;
 
 pub use arena::*;
 pub use arena_map::*;
+pub use phase::*;
+pub use replay_writer::*;
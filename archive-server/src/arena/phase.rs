@@ -0,0 +1,108 @@
+use std::time::Instant;
+
+use crate::*;
+
+use log::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArenaPhase {
+    // not enough players yet, anyone can join
+    #[default]
+    Waiting,
+    // enough players, the match starts at the deadline unless people leave
+    Countdown {
+        starts_at: Instant,
+    },
+    // the realm is simulating, no new joins
+    Running,
+    // match is over, the arena is waiting to be torn down
+    Finished,
+}
+
+impl ArenaPhase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArenaPhase::Waiting => "waiting",
+            ArenaPhase::Countdown { .. } => "countdown",
+            ArenaPhase::Running => "running",
+            ArenaPhase::Finished => "finished",
+        }
+    }
+    pub fn is_joinable(&self) -> bool {
+        matches!(self, ArenaPhase::Waiting | ArenaPhase::Countdown { .. })
+    }
+
+    // connected is how many clients have a live session, alive is how many
+    // player entities are still in the realm
+    pub fn next(
+        self,
+        now: Instant,
+        connected: usize,
+        alive: usize,
+        config: &config::ServerConfig,
+    ) -> Self {
+        use ArenaPhase::*;
+        let next = match self {
+            Waiting if connected >= config.min_players => Countdown {
+                starts_at: now + config.countdown,
+            },
+            Countdown { .. } if connected < config.min_players => Waiting,
+            Countdown { starts_at } if now >= starts_at => Running,
+            // solo matches (min_players <= 1) only end once everyone is gone
+            Running if alive == 0 || (config.min_players > 1 && alive < 2) => Finished,
+            phase => phase,
+        };
+        if next != self {
+            info!("arena phase {:?} -> {:?}", self, next);
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn config(min_players: usize) -> config::ServerConfig {
+        config::ServerConfig {
+            min_players,
+            countdown: Duration::from_secs(5),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_countdown() {
+        use ArenaPhase::*;
+        let config = config(2);
+        let now = Instant::now();
+        let starts_at = now + config.countdown;
+
+        assert_eq!(Waiting.next(now, 1, 1, &config), Waiting);
+        assert_eq!(Waiting.next(now, 2, 2, &config), Countdown { starts_at });
+
+        let countdown = Countdown { starts_at };
+        assert_eq!(countdown.next(now, 2, 2, &config), countdown);
+        // someone leaving before the start puts it back
+        assert_eq!(countdown.next(now, 1, 2, &config), Waiting);
+        assert_eq!(countdown.next(starts_at, 2, 2, &config), Running);
+    }
+
+    #[test]
+    fn test_finish() {
+        use ArenaPhase::*;
+        let now = Instant::now();
+        let duo = config(2);
+        assert_eq!(Running.next(now, 2, 2, &duo), Running);
+        assert_eq!(Running.next(now, 2, 1, &duo), Finished);
+        // disconnected players still count while their entity is alive
+        assert_eq!(Running.next(now, 0, 2, &duo), Running);
+
+        let solo = config(1);
+        assert_eq!(Running.next(now, 1, 1, &solo), Running);
+        assert_eq!(Running.next(now, 1, 0, &solo), Finished);
+
+        assert_eq!(Finished.next(now, 2, 2, &duo), Finished);
+    }
+}
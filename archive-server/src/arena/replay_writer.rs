@@ -0,0 +1,103 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{error, info};
+use tokio::sync::mpsc;
+
+// appends an arena's replay to its file a chunk at a time as the arena
+// flushes it, so it never piles up in memory and most of it survives the
+// server going down. writes go in order on the blocking pool, so the
+// arena never waits on the disk with its lock held
+pub struct ReplayWriter {
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl ReplayWriter {
+    // creates the file with header, the rest gets appended
+    pub fn start(path: PathBuf, header: Vec<u8>) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        tokio::spawn(async move {
+            let mut bytes = header;
+            loop {
+                let chunk_path = path.clone();
+                match tokio::task::spawn_blocking(move || append(&chunk_path, &bytes)).await {
+                    Ok(Ok(())) => (),
+                    Ok(Err(e)) => {
+                        error!("failed to save replay: {e:?}");
+                        return;
+                    }
+                    Err(e) => {
+                        error!("failed to save replay: {e}");
+                        return;
+                    }
+                }
+                bytes = match rx.recv().await {
+                    Some(bytes) => bytes,
+                    // the arena is gone, so that was the last of it
+                    None => break,
+                };
+            }
+            info!("saved replay to {}", path.display());
+        });
+        ReplayWriter { tx }
+    }
+
+    pub fn write(&self, bytes: Vec<u8>) {
+        // only fails once the writer gave up, which it already logged
+        let _ = self.tx.send(bytes);
+    }
+}
+
+fn append(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(bytes))
+        .with_context(|| format!("writing {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use archive_engine::*;
+
+    #[tokio::test]
+    async fn test_replay_writer() {
+        let mut realm = ecs::Realm::new();
+        realm.start_recording(false).unwrap();
+        let path = std::env::temp_dir()
+            .join(format!("archive-replay-{}", std::process::id()))
+            .join("test.replay");
+        let _ = std::fs::remove_file(&path);
+
+        let writer = ReplayWriter::start(path.clone(), realm.replay().unwrap().encode_header());
+        realm.spawn_player(1);
+        realm.run_systems();
+        writer.write(ecs::Replay::encode_events(&realm.take_replay_events()));
+        realm.run_systems();
+        let replay = realm.take_replay().unwrap();
+        writer.write(ecs::Replay::encode_events(&replay.events));
+        drop(writer);
+
+        // the writer finishes on its own once it's dropped
+        let mut written = None;
+        for _ in 0..100 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            let bytes = std::fs::read(&path).unwrap_or_default();
+            if let Ok(decoded) = ecs::Replay::decode(&bytes) {
+                if decoded.events.len() == 3 {
+                    written = Some(decoded);
+                    break;
+                }
+            }
+        }
+        let written = written.expect("replay never finished writing");
+        assert_eq!(written.play(|_| ()).tick, 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+use anyhow::{Context, Result};
+use archive_engine::*;
+use clap::{App, Arg};
+
+// reruns a replay written with --replay-dir and prints how the match went
+fn main() -> Result<()> {
+    let matches = App::new("playback")
+        .arg(
+            Arg::new("replay")
+                .value_name("FILE")
+                .help("a replay written with --replay-dir")
+                .required(true),
+        )
+        .arg(
+            Arg::new("every")
+                .long("every")
+                .value_name("TICKS")
+                .help("ticks between status lines")
+                .takes_value(true),
+        )
+        .get_matches();
+    let path = matches.value_of("replay").unwrap();
+    let every: u64 = if matches.is_present("every") {
+        matches.value_of_t_or_exit("every")
+    } else {
+        60
+    };
+
+    let bytes = std::fs::read(path).with_context(|| format!("reading {path}"))?;
+    let replay = ecs::Replay::decode(&bytes).context("decoding replay")?;
+    if let Err(e) = rtc::ProtocolInfo::check(replay.protocol) {
+        // systems may have changed since, so this is only a best guess
+        eprintln!("warning: recorded on a different build, {e}");
+    }
+    println!(
+        "{} events from tick {}",
+        replay.events.len(),
+        replay.start_tick
+    );
+
+    let realm = replay.play(|realm| {
+        for event in realm.take_events() {
+            println!("tick {}: {event:?}", realm.tick);
+        }
+        if realm.tick % every.max(1) == 0 {
+            println!(
+                "tick {}: {} players, {} entities",
+                realm.tick,
+                realm.player_count(),
+                realm.entity_count()
+            );
+        }
+    });
+    println!(
+        "ended on tick {} with {} players",
+        realm.tick,
+        realm.player_count()
+    );
+    Ok(())
+}
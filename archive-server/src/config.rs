@@ -0,0 +1,121 @@
+use std::{path::PathBuf, time::Duration};
+
+use clap::{App, Arg, ArgMatches};
+
+pub struct ServerConfig {
+    // how long a client's slot (and player) survives a dropped connection
+    pub reconnect_grace: Duration,
+    // arenas past this count make new joins/matchmaking fail
+    pub max_arenas: usize,
+    // players per arena, can't be more than there are ClientIds
+    pub arena_capacity: usize,
+    // connected players needed before the countdown starts
+    pub min_players: usize,
+    pub countdown: Duration,
+    // bearer token for the /admin routes, which are disabled without one
+    pub admin_token: Option<String>,
+    // each arena's replay is written here when it's torn down
+    pub replay_dir: Option<PathBuf>,
+    // replays also keep every message sent to clients, which is big
+    pub record_sent: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            reconnect_grace: Duration::from_secs(10),
+            max_arenas: 16,
+            arena_capacity: 32,
+            min_players: 2,
+            countdown: Duration::from_secs(5),
+            admin_token: None,
+            replay_dir: None,
+            record_sent: false,
+        }
+    }
+}
+
+fn secs_arg(name: &'static str, help: &'static str) -> Arg<'static> {
+    Arg::new(name)
+        .long(name)
+        .value_name("SECS")
+        .help(help)
+        .takes_value(true)
+}
+fn count_arg(name: &'static str, help: &'static str) -> Arg<'static> {
+    Arg::new(name)
+        .long(name)
+        .value_name("N")
+        .help(help)
+        .takes_value(true)
+}
+
+impl ServerConfig {
+    pub fn from_args() -> Self {
+        let matches = App::new("archive-server")
+            .arg(secs_arg(
+                "reconnect-grace",
+                "seconds a disconnected client may take to reconnect",
+            ))
+            .arg(count_arg("max-arenas", "max concurrent arenas"))
+            .arg(count_arg("arena-capacity", "max players per arena"))
+            .arg(count_arg("min-players", "players needed to start a match"))
+            .arg(secs_arg(
+                "countdown",
+                "seconds between enough players and the start",
+            ))
+            .arg(
+                Arg::new("admin-token")
+                    .long("admin-token")
+                    .value_name("TOKEN")
+                    .help("enables the admin api, requests must send it as a bearer token")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::new("replay-dir")
+                    .long("replay-dir")
+                    .value_name("DIR")
+                    .help("records a replay of every arena into this directory")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::new("record-sent")
+                    .long("record-sent")
+                    .help("replays also keep what was sent to clients, for client playback"),
+            )
+            .get_matches();
+
+        let mut config = ServerConfig::default();
+        if let Some(secs) = parse_arg(&matches, "reconnect-grace") {
+            config.reconnect_grace = Duration::from_secs(secs);
+        }
+        if let Some(n) = parse_arg(&matches, "max-arenas") {
+            config.max_arenas = n;
+        }
+        if let Some(n) = parse_arg(&matches, "arena-capacity") {
+            config.arena_capacity = n;
+        }
+        if let Some(n) = parse_arg(&matches, "min-players") {
+            config.min_players = n;
+        }
+        if let Some(secs) = parse_arg(&matches, "countdown") {
+            config.countdown = Duration::from_secs(secs);
+        }
+        config.admin_token = matches.value_of("admin-token").map(String::from);
+        config.replay_dir = matches.value_of("replay-dir").map(PathBuf::from);
+        config.record_sent = matches.is_present("record-sent");
+        config
+    }
+}
+
+fn parse_arg<T>(matches: &ArgMatches, name: &str) -> Option<T>
+where
+    T: std::str::FromStr,
+    <T as std::str::FromStr>::Err: std::fmt::Display,
+{
+    if matches.is_present(name) {
+        Some(matches.value_of_t_or_exit(name))
+    } else {
+        None
+    }
+}
@@ -1,7 +1,9 @@
+mod admin;
 mod filters;
 mod req_handlers;
 mod tungstenite_serve;
 
+pub use admin::*;
 pub use filters::*;
 pub use req_handlers::*;
 pub use tungstenite_serve::*;
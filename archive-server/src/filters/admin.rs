@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use super::*;
+use crate::*;
+
+use anyhow::Result;
+use archive_engine::rtc::{ArenaUkey, ClientId};
+use archive_engine::*;
+use log::*;
+use warp::{http::StatusCode, reject::Reject, Filter};
+
+#[derive(Debug)]
+struct Unauthorized;
+impl Reject for Unauthorized {}
+
+// routes under /admin, all of which need the configured admin token.
+// without one configured they all 404
+pub fn admin_routes(
+    arena_map: arena::ArenaMapLock,
+    admin_token: Option<String>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    let auth = admin_auth(admin_token.map(Arc::new));
+    let add_map = add_map_filter(arena_map);
+
+    let list = warp::get()
+        .and(warp::path!("arenas"))
+        .and(add_map.clone())
+        .and_then(handle_list_arenas);
+
+    let close = warp::post()
+        .and(warp::path!("arenas" / ArenaUkey / "close"))
+        .and(add_map.clone())
+        .and_then(handle_close_arena);
+
+    let kick = warp::post()
+        .and(warp::path!(
+            "arenas" / ArenaUkey / "clients" / ClientId / "kick"
+        ))
+        .and(add_map)
+        .and_then(handle_kick_client);
+
+    // auth goes first, so that with the api off every admin path 404s
+    // instead of whichever route rejected last winning. recover inside
+    // the prefix so that non-admin requests still fall through to the
+    // other routes
+    warp::path("admin").and(
+        auth.and(list.or(close).or(kick))
+            .recover(handle_admin_rejection),
+    )
+}
+
+fn admin_auth(
+    admin_token: Option<Arc<String>>,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |auth: Option<String>| {
+            let admin_token = admin_token.clone();
+            async move {
+                let admin_token = match admin_token {
+                    Some(admin_token) => admin_token,
+                    None => return Err(warp::reject::not_found()),
+                };
+                let given = auth
+                    .as_deref()
+                    .and_then(|auth| auth.strip_prefix("Bearer "));
+                match given {
+                    Some(given) if auth::tokens_match(given, &admin_token) => Ok(()),
+                    _ => {
+                        warn!("rejected admin request with a bad or missing token");
+                        Err(warp::reject::custom(Unauthorized))
+                    }
+                }
+            }
+        })
+        .untuple_one()
+}
+
+async fn handle_admin_rejection(
+    err: warp::Rejection,
+) -> Result<impl warp::Reply, std::convert::Infallible> {
+    let status = if err.is_not_found() {
+        StatusCode::NOT_FOUND
+    } else if err.find::<Unauthorized>().is_some() {
+        StatusCode::UNAUTHORIZED
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+    let message = format!("{:?}", err);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&message),
+        status,
+    ))
+}
+
+fn not_found(what: String) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(warp::reply::json(&what), StatusCode::NOT_FOUND)
+}
+fn ok() -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(warp::reply::json(&"ok"), StatusCode::OK)
+}
+
+async fn handle_list_arenas(
+    arena_map: arena::ArenaMapLock,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let summaries = arena::summaries(arena_map).await;
+    Ok(warp::reply::json(&summaries))
+}
+
+async fn handle_close_arena(
+    arena_ukey: rtc::ArenaUkey,
+    arena_map: arena::ArenaMapLock,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if arena::close_arena(arena_map, arena_ukey).await {
+        info!("admin closed arena {arena_ukey}");
+        Ok(ok())
+    } else {
+        Ok(not_found(format!("no arena {arena_ukey}")))
+    }
+}
+
+async fn handle_kick_client(
+    arena_ukey: rtc::ArenaUkey,
+    client_id: rtc::ClientId,
+    arena_map: arena::ArenaMapLock,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let arena_lock = match arena_map.read().await.get(arena_ukey) {
+        Some(arena_lock) => arena_lock,
+        None => return Ok(not_found(format!("no arena {arena_ukey}"))),
+    };
+    if arena_lock.write().await.drop_client(client_id) {
+        info!("admin kicked client #{client_id} from arena {arena_ukey}");
+        Ok(ok())
+    } else {
+        Ok(not_found(format!(
+            "no client #{client_id} in arena {arena_ukey}"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::RwLock;
+    use warp::test::request;
+
+    const TOKEN: &str = "secret";
+
+    fn arena_map() -> arena::ArenaMapLock {
+        Arc::new(RwLock::new(arena::ArenaMap::new(Default::default())))
+    }
+
+    #[tokio::test]
+    async fn test_auth() {
+        let routes = admin_routes(arena_map(), Some(TOKEN.into()));
+        let list = |auth: Option<&str>| {
+            let req = request().method("GET").path("/admin/arenas");
+            match auth {
+                Some(auth) => req.header("authorization", auth),
+                None => req,
+            }
+        };
+        let missing = list(None).reply(&routes).await;
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+        let wrong = list(Some("Bearer hunter2")).reply(&routes).await;
+        assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+        let unprefixed = list(Some(TOKEN)).reply(&routes).await;
+        assert_eq!(unprefixed.status(), StatusCode::UNAUTHORIZED);
+        let right = list(Some("Bearer secret")).reply(&routes).await;
+        assert_eq!(right.status(), StatusCode::OK);
+
+        // no configured token turns the whole api off
+        let routes = admin_routes(arena_map(), None);
+        let disabled = list(Some("Bearer secret")).reply(&routes).await;
+        assert_eq!(disabled.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_kick_and_close() {
+        let arena_map = arena_map();
+        let routes = admin_routes(arena_map.clone(), Some(TOKEN.into()));
+        let post = |path: &str| {
+            request()
+                .method("POST")
+                .path(path)
+                .header("authorization", "Bearer secret")
+        };
+
+        let arena = arena_map.write().await.get_or_insert_default(0).unwrap();
+        let join = arena.write().await.alloc_client().unwrap();
+        let kick = format!("/admin/arenas/0/clients/{}/kick", join.client_id());
+
+        let kicked = post(&kick).reply(&routes).await;
+        assert_eq!(kicked.status(), StatusCode::OK);
+        assert!(arena.read().await.summary(0).clients.is_empty());
+        let gone = post(&kick).reply(&routes).await;
+        assert_eq!(gone.status(), StatusCode::NOT_FOUND);
+        let no_arena = post("/admin/arenas/1/clients/0/kick").reply(&routes).await;
+        assert_eq!(no_arena.status(), StatusCode::NOT_FOUND);
+
+        let closed = post("/admin/arenas/0/close").reply(&routes).await;
+        assert_eq!(closed.status(), StatusCode::OK);
+        assert!(arena_map.read().await.get(0).is_none());
+        assert_eq!(arena.read().await.phase(), arena::ArenaPhase::Finished);
+        let gone = post("/admin/arenas/0/close").reply(&routes).await;
+        assert_eq!(gone.status(), StatusCode::NOT_FOUND);
+    }
+}
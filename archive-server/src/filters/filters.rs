@@ -6,12 +6,6 @@ use crate::*;
 use futures::{FutureExt, StreamExt};
 use warp::Filter;
 
-pub async fn _handle_rejection(
-    err: warp::Rejection,
-) -> Result<impl warp::Reply, std::convert::Infallible> {
-    Ok(warp::reply::json(&format!("{:?}", err)))
-}
-
 pub async fn warp_serve(arena_map: arena::ArenaMapLock) {
     let cors = warp::cors()
         .allow_any_origin()
@@ -26,16 +20,37 @@ pub async fn warp_serve(arena_map: arena::ArenaMapLock) {
         ])
         .allow_methods(vec!["POST", "GET"]);
 
+    let admin_token = arena_map.read().await.config().admin_token.clone();
+    let admin = admin_routes(arena_map.clone(), admin_token);
+
     let add_map = add_map_filter(arena_map);
 
+    let metrics = warp::get().and(warp::path!("metrics")).map(|| {
+        warp::reply::with_header(
+            metrics::render(),
+            "content-type",
+            "text/plain; version=0.0.4",
+        )
+    });
+
+    let matchmake = warp::post()
+        .and(warp::path("matchmake"))
+        .and(add_map.clone())
+        .and_then(handle_matchmake);
+
+    // trickled candidates for a handshake /signal started
+    let ice = warp::post()
+        .and(warp::path!("signal" / "ice"))
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(warp::body::json())
+        .and_then(handle_rtc_ice);
+
     let signal = warp::post()
-        .and(warp::path("signal"))
+        .and(warp::path!("signal"))
         .and(warp::body::content_length_limit(1024 * 16))
         .and(warp::body::json())
         .and(add_map)
-        .and_then(handle_rtc_signal)
-        .recover(_handle_rejection)
-        .with(cors);
+        .and_then(handle_rtc_signal);
 
     let ws = warp::path("ws")
         .and(warp::query::<HashMap<String, String>>())
@@ -43,7 +58,12 @@ pub async fn warp_serve(arena_map: arena::ArenaMapLock) {
         .and(warp::ws())
         .and_then(handle_ws);
 
-    warp::serve(signal.or(ws)).run(([127, 0, 0, 1], 3030)).await
+    // recovering only once every route has had a go, so that a rejection
+    // from one doesn't stop the ones after it
+    let routes = admin.or(metrics).or(matchmake).or(ice).or(signal).or(ws);
+    warp::serve(routes.recover(handle_rejection).with(cors))
+        .run(([127, 0, 0, 1], 3030))
+        .await
 }
 
 pub fn add_map_filter(
@@ -4,21 +4,48 @@ use anyhow::{bail, Result};
 use archive_engine::*;
 use futures::{FutureExt, StreamExt};
 use log::*;
+use warp::http::StatusCode;
 use warp::reject::Reject;
+use warp::Reply;
 
 use crate::*;
 
 async fn handle_rtc_signal_anyhow(
     client_offer: rtc::ClientOffer,
     arena_map: arena::ArenaMapLock,
-) -> Result<impl warp::Reply> {
-    let (client_id, arena_lock) =
-        arena::process_client_ticket(client_offer.ticket, arena_map.clone()).await?;
+) -> Result<warp::reply::Response> {
+    if let Err(e) = rtc::ProtocolInfo::check(client_offer.protocol) {
+        metrics::PROTOCOL_MISMATCHES.inc();
+        debug!("turning down join: {e}");
+        let reply = warp::reply::with_status(warp::reply::json(&e), StatusCode::CONFLICT);
+        return Ok(reply.into_response());
+    }
+
+    let (join, arena_lock) =
+        arena::process_client_ticket(client_offer.ticket.clone(), arena_map.clone()).await?;
+    match negotiate_rtc(client_offer, join.clone(), arena_lock.clone()).await {
+        Ok(reply) => Ok(reply),
+        Err(e) => {
+            // the client never got its answer, so it won't be back for the slot
+            arena_lock.write().await.abandon_join(&join);
+            Err(e)
+        }
+    }
+}
+
+async fn negotiate_rtc(
+    client_offer: rtc::ClientOffer,
+    join: arena::ClientJoin,
+    arena_lock: arena::ArenaLock,
+) -> Result<warp::reply::Response> {
     debug!("attempting rtc negotiation");
 
     let peer_connection = session::create_peer_connection().await?;
 
-    let sdp = session::negotiate(peer_connection.clone(), client_offer).await?;
+    let (sdp, trickle) = session::negotiate(peer_connection.clone(), client_offer).await?;
+    let signal_id = session::register_trickle(trickle);
+    let task_signal_id = signal_id.clone();
+    let task_join = join.clone();
 
     // warp is going to respond with the SDP credentials, and this task
     // will wait expecting the client to connect using the information
@@ -28,22 +55,39 @@ async fn handle_rtc_signal_anyhow(
         match session::NativeRtcSession::new(peer_connection).await {
             Ok(session) => {
                 let mut arena = arena_lock.write().await;
-                if let Err(e) = arena.process_client_session(client_id, session.into()) {
+                if let Err(e) = arena.process_client_session(task_join.token, session.into()) {
                     error!("failed to process client session: {e}");
                 }
             }
-            Err(e) => error!("failed to set up rtc session: {e}"),
+            Err(e) => {
+                error!("failed to set up rtc session: {e}");
+                arena_lock.write().await.abandon_join(&task_join);
+            }
         };
+        // connected or timed out, either way the candidates are done
+        session::unregister_trickle(&task_signal_id);
     });
 
-    let server_answer = rtc::ServerAnswer { sdp, client_id };
-    Ok(warp::reply::json(&server_answer))
+    let server_answer = rtc::ServerAnswer {
+        client_id: join.client_id(),
+        resume_token: join.resume_token,
+        sdp,
+        signal_id,
+    };
+    Ok(warp::reply::json(&server_answer).into_response())
+}
+
+async fn handle_rtc_ice_anyhow(ice_offer: rtc::IceOffer) -> Result<impl warp::Reply> {
+    let trickle = match session::find_trickle(&ice_offer.signal_id) {
+        Some(trickle) => trickle,
+        None => bail!("unknown signal id"),
+    };
+    let ice_answer = trickle.exchange(ice_offer).await?;
+    Ok(warp::reply::json(&ice_answer))
 }
 
 #[derive(Debug)]
 struct AnyhowReject {
-    // used by the derive
-    #[allow(dead_code)]
     error: anyhow::Error,
 }
 impl Reject for AnyhowReject {}
@@ -51,6 +95,24 @@ fn error_to_reject(error: anyhow::Error) -> warp::Rejection {
     warp::reject::custom(AnyhowReject { error })
 }
 
+// turns whatever rejected the request into a json error for the client
+pub async fn handle_rejection(
+    err: warp::Rejection,
+) -> Result<impl warp::Reply, std::convert::Infallible> {
+    let (status, message) = if err.is_not_found() {
+        (StatusCode::NOT_FOUND, "not found".to_string())
+    } else if let Some(AnyhowReject { error }) = err.find() {
+        debug!("request failed: {error:?}");
+        (StatusCode::BAD_REQUEST, format!("{error}"))
+    } else {
+        (StatusCode::BAD_REQUEST, format!("{:?}", err))
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&message),
+        status,
+    ))
+}
+
 pub async fn handle_rtc_signal(
     client_offer: rtc::ClientOffer,
     arena_map: arena::ArenaMapLock,
@@ -60,6 +122,26 @@ pub async fn handle_rtc_signal(
         .map_err(error_to_reject)
 }
 
+pub async fn handle_rtc_ice(ice_offer: rtc::IceOffer) -> Result<impl warp::Reply, warp::Rejection> {
+    handle_rtc_ice_anyhow(ice_offer)
+        .await
+        .map_err(error_to_reject)
+}
+
+async fn handle_matchmake_anyhow(arena_map: arena::ArenaMapLock) -> Result<impl warp::Reply> {
+    let ticket = arena::matchmake(arena_map).await?;
+    debug!("matchmade into arena {}", ticket.arena_ukey);
+    Ok(warp::reply::json(&ticket))
+}
+
+pub async fn handle_matchmake(
+    arena_map: arena::ArenaMapLock,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    handle_matchmake_anyhow(arena_map)
+        .await
+        .map_err(error_to_reject)
+}
+
 async fn handle_ws_anyhow(
     p: HashMap<String, String>,
     ws: warp::ws::Ws,
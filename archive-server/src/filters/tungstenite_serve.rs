@@ -1,8 +1,8 @@
 use crate::{session::MpscRtcSession, *};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use archive_engine::*;
-use futures::StreamExt;
+use futures::{SinkExt, StreamExt};
 use std::time::Duration;
 
 use log::{debug, error, info};
@@ -11,6 +11,7 @@ use tokio::{
     net::{TcpListener, TcpStream},
     time::timeout,
 };
+use tokio_tungstenite::tungstenite::Message;
 
 const WS_TIMEOUT: Duration = Duration::from_secs(1);
 
@@ -48,24 +49,65 @@ async fn accept_connection_inner(stream: TcpStream, arena_map: arena::ArenaMapLo
     debug!("attempting ws negotiation");
     info!("New WebSocket connection: {}", addr);
 
-    let ticket = timeout(WS_TIMEOUT, ws_stream.next()).await;
+    let request = timeout(WS_TIMEOUT, ws_stream.next()).await;
+
+    let request = request.context("connection timed out")?;
+    let request = request.context("ws read yielded None (socket closed)")?;
+    let request = request.context("ws error while socket is open")?;
+    let request = request.into_data();
+
+    // check the version on its own first, the rest of a stale client's
+    // request might not parse
+    let protocol: rtc::ProtocolInfo = match bincode::deserialize(&request[..]) {
+        Ok(protocol) => protocol,
+        Err(e) => {
+            metrics::WS_PARSE_FAILURES.inc();
+            bail!("failed to parse protocol: {e}");
+        }
+    };
+    if let Err(e) = rtc::ProtocolInfo::check(protocol) {
+        metrics::PROTOCOL_MISMATCHES.inc();
+        let answer: rtc::JoinResult = Err(e.clone());
+        ws_stream
+            .send(Message::Binary(bincode::serialize(&answer)?))
+            .await?;
+        bail!("turned down join: {e}");
+    }
+
+    let request: rtc::JoinRequest = match bincode::deserialize(&request[..]) {
+        Ok(request) => request,
+        Err(e) => {
+            metrics::WS_PARSE_FAILURES.inc();
+            bail!("failed to parse ticket: {e}");
+        }
+    };
 
-    let ticket = ticket.context("connection timed out")?;
-    let ticket = ticket.context("ws read yielded None (socket closed)")?;
-    let ticket = ticket.context("ws error while socket is open")?;
-    let ticket = ticket.into_data();
+    let (join, arena_lock) =
+        arena::process_client_ticket(request.ticket, arena_map.clone()).await?;
 
-    let ticket: rtc::ArenaTicket =
-        bincode::deserialize(&ticket[..]).context("failed to parse ticket")?;
+    let result = attach_ws_session(ws_stream, &join, &arena_lock).await;
+    if result.is_err() {
+        arena_lock.write().await.abandon_join(&join);
+    }
+    result
+}
 
-    let (client_id, arena_lock) = arena::process_client_ticket(ticket, arena_map.clone()).await?;
+async fn attach_ws_session(
+    mut ws_stream: tokio_tungstenite::WebSocketStream<TcpStream>,
+    join: &arena::ClientJoin,
+    arena_lock: &arena::ArenaLock,
+) -> Result<()> {
+    // let the client know who it is, so it can resume later
+    let answer: rtc::JoinResult = Ok(join.answer());
+    let answer = bincode::serialize(&answer)?;
+    ws_stream.send(Message::Binary(answer)).await?;
 
     let session = MpscRtcSession::new_from_tungstenite(ws_stream).await?;
 
     let mut arena = arena_lock.write().await;
 
     // register this websocket session with the arena which takes over control
-    arena.process_client_session(client_id, session.into())?;
+    arena.process_client_session(join.token, session.into())?;
 
     Ok(())
 }
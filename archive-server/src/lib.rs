@@ -1,3 +1,6 @@
 pub mod arena;
+pub mod auth;
+pub mod config;
 pub mod filters;
+pub mod metrics;
 pub mod session;
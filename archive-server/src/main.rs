@@ -1,17 +1,19 @@
-use tokio::select;
+use std::sync::Arc;
 
-pub mod arena;
-pub mod filters;
-pub mod session;
+use archive_server::*;
+use tokio::select;
+use tokio::sync::RwLock;
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
 
-    let arena_map = arena::ArenaMapLock::default();
+    let config = Arc::new(config::ServerConfig::from_args());
+    let arena_map: arena::ArenaMapLock = Arc::new(RwLock::new(arena::ArenaMap::new(config)));
 
     select! {
         _ = filters::warp_serve(arena_map.clone()) => {},
-        _ = filters::tungstenite_serve(arena_map.clone()) => {}
+        _ = filters::tungstenite_serve(arena_map.clone()) => {},
+        _ = arena::reap_arenas(arena_map.clone()) => {}
     };
 }
@@ -0,0 +1,272 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+// process-wide metrics, rendered in the prometheus text format on /metrics.
+// everything is a plain atomic so recording never blocks a tick, except
+// the per-arena series which take a short lock once a tick
+
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+impl Counter {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Counter {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+    fn render(&self, out: &mut String) {
+        let Counter { name, help, .. } = self;
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        let _ = writeln!(out, "{name} {}", self.value.load(Ordering::Relaxed));
+    }
+}
+
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    // upper bounds, the +Inf bucket is implicit
+    bounds: &'static [f64],
+    // non-cumulative, cumulated at render time
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    // f64 bits
+    sum: AtomicU64,
+}
+impl Histogram {
+    fn new(name: &'static str, help: &'static str, bounds: &'static [f64]) -> Self {
+        Histogram {
+            name,
+            help,
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0f64.to_bits()),
+        }
+    }
+    pub fn observe(&self, value: f64) {
+        if let Some(i) = self.bounds.iter().position(|&bound| value <= bound) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + value).to_bits())
+            });
+    }
+    fn render(&self, out: &mut String) {
+        let Histogram { name, help, .. } = self;
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = f64::from_bits(self.sum.load(Ordering::Relaxed));
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum {sum}");
+        let _ = writeln!(out, "{name}_count {count}");
+    }
+}
+
+// a counter per arena, labelled with its ukey. torn down arenas are
+// removed so the series don't pile up
+pub struct ArenaCounter {
+    name: &'static str,
+    help: &'static str,
+    values: Mutex<BTreeMap<u64, u64>>,
+}
+impl ArenaCounter {
+    fn new(name: &'static str, help: &'static str) -> Self {
+        ArenaCounter {
+            name,
+            help,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+    pub fn add(&self, arena_ukey: u64, n: u64) {
+        *self.values.lock().unwrap().entry(arena_ukey).or_insert(0) += n;
+    }
+    pub fn remove(&self, arena_ukey: u64) {
+        self.values.lock().unwrap().remove(&arena_ukey);
+    }
+    fn render(&self, out: &mut String) {
+        let ArenaCounter { name, help, .. } = self;
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for (arena_ukey, value) in self.values.lock().unwrap().iter() {
+            let _ = writeln!(out, "{name}{{arena=\"{arena_ukey}\"}} {value}");
+        }
+    }
+}
+
+const TICK_SECONDS: &[f64] = &[
+    0.0005, 0.001, 0.002, 0.004, 0.008, 0.016, 0.032, 0.064, 0.128,
+];
+const ACTIONS: &[f64] = &[0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+const BYTES: &[f64] = &[64.0, 128.0, 256.0, 512.0, 1024.0, 2048.0, 4096.0, 16384.0];
+const BACKLOG: &[f64] = &[0.0, 1.0, 5.0, 10.0, 50.0, 100.0, 500.0, 1000.0];
+
+lazy_static! {
+    pub static ref TICK_DURATION: Histogram = Histogram::new(
+        "archive_tick_duration_seconds",
+        "time spent simulating and sending one arena tick",
+        TICK_SECONDS,
+    );
+    pub static ref DELTA_ACTIONS: Histogram = Histogram::new(
+        "archive_delta_actions",
+        "entity actions in each delta sent to a client",
+        ACTIONS,
+    );
+    pub static ref DELTA_BYTES: Histogram = Histogram::new(
+        "archive_delta_bytes",
+        "encoded size of each delta sent to a client",
+        BYTES,
+    );
+    pub static ref PRIORITY_BACKLOG: Histogram = Histogram::new(
+        "archive_priority_backlog",
+        "entities left out of each delta for lack of room",
+        BACKLOG,
+    );
+    pub static ref ARENA_DELTA_BYTES: ArenaCounter = ArenaCounter::new(
+        "archive_arena_delta_bytes_total",
+        "encoded bytes of the deltas sent to each arena's clients",
+    );
+}
+
+pub static SEND_FAILURES: Counter = Counter::new(
+    "archive_send_failures_total",
+    "messages that failed to send to a client",
+);
+pub static HANDSHAKE_TIMEOUTS: Counter = Counter::new(
+    "archive_handshake_timeouts_total",
+    "webrtc sessions that timed out before the data channel opened",
+);
+pub static WS_PARSE_FAILURES: Counter = Counter::new(
+    "archive_ws_parse_failures_total",
+    "websocket connections that sent an unreadable ticket",
+);
+
+pub static PROTOCOL_MISMATCHES: Counter = Counter::new(
+    "archive_protocol_mismatches_total",
+    "joins turned down for a stale protocol version or schema",
+);
+
+pub static RESYNC_REQUESTS: Counter = Counter::new(
+    "archive_resync_requests_total",
+    "clients whose snapshot didn't match a delta checksum",
+);
+
+pub fn render() -> String {
+    let mut out = String::new();
+    for histogram in [
+        &*TICK_DURATION,
+        &*DELTA_ACTIONS,
+        &*DELTA_BYTES,
+        &*PRIORITY_BACKLOG,
+    ] {
+        histogram.render(&mut out);
+    }
+    for counter in [
+        &SEND_FAILURES,
+        &HANDSHAKE_TIMEOUTS,
+        &WS_PARSE_FAILURES,
+        &PROTOCOL_MISMATCHES,
+        &RESYNC_REQUESTS,
+    ] {
+        counter.render(&mut out);
+    }
+    ARENA_DELTA_BYTES.render(&mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counter() {
+        let counter = Counter::new("test_total", "things");
+        counter.inc();
+        counter.inc();
+        let mut out = String::new();
+        counter.render(&mut out);
+        assert_eq!(
+            out,
+            "# HELP test_total things\n\
+             # TYPE test_total counter\n\
+             test_total 2\n"
+        );
+    }
+
+    #[test]
+    fn test_render_histogram() {
+        let histogram = Histogram::new("test_bytes", "sizes", &[1.0, 10.0]);
+        for value in [0.5, 5.0, 10.0, 50.0] {
+            histogram.observe(value);
+        }
+        let mut out = String::new();
+        histogram.render(&mut out);
+        // buckets are cumulative and inclusive of their bound
+        assert_eq!(
+            out,
+            "# HELP test_bytes sizes\n\
+             # TYPE test_bytes histogram\n\
+             test_bytes_bucket{le=\"1\"} 1\n\
+             test_bytes_bucket{le=\"10\"} 3\n\
+             test_bytes_bucket{le=\"+Inf\"} 4\n\
+             test_bytes_sum 65.5\n\
+             test_bytes_count 4\n"
+        );
+    }
+
+    #[test]
+    fn test_render_arena_counter() {
+        let counter = ArenaCounter::new("test_arena_total", "per arena");
+        counter.add(2, 1);
+        counter.add(0, 3);
+        counter.add(0, 2);
+        let mut out = String::new();
+        counter.render(&mut out);
+        assert_eq!(
+            out,
+            "# HELP test_arena_total per arena\n\
+             # TYPE test_arena_total counter\n\
+             test_arena_total{arena=\"0\"} 5\n\
+             test_arena_total{arena=\"2\"} 1\n"
+        );
+
+        counter.remove(0);
+        let mut out = String::new();
+        counter.render(&mut out);
+        assert!(!out.contains("arena=\"0\""));
+        assert!(out.contains("test_arena_total{arena=\"2\"} 1\n"));
+    }
+
+    #[test]
+    fn test_render_all() {
+        let out = render();
+        for name in [
+            "archive_tick_duration_seconds",
+            "archive_delta_bytes",
+            "archive_send_failures_total",
+            "archive_arena_delta_bytes_total",
+        ] {
+            assert!(out.contains(&format!("# TYPE {name} ")), "missing {name}");
+        }
+    }
+}
@@ -43,6 +43,12 @@ impl rtc::RtcSession for EnumRtcSession {
 }
 
 impl EnumRtcSession {
+    pub fn transport_name(&self) -> &'static str {
+        match self {
+            Native(_) => "native",
+            Mpsc(_) => "mpsc",
+        }
+    }
     pub async fn send_impl(&self, msg: Vec<u8>) -> bool {
         match self {
             Native(s) => s.send_impl(msg).await,
@@ -3,9 +3,11 @@ mod mpsc_rtc;
 mod native_rtc;
 mod rtc_helpers;
 mod server_rtc;
+mod trickle;
 
 pub use enum_rtc::*;
 pub use mpsc_rtc::*;
 pub use native_rtc::*;
 pub use rtc_helpers::*;
 pub use server_rtc::*;
+pub use trickle::*;
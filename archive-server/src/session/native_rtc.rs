@@ -20,10 +20,12 @@ use webrtc::peer_connection::RTCPeerConnection;
 use bytes::Bytes;
 
 use super::map_try_recv_to_std;
+use crate::metrics;
 
 // FIXME add logic to boot old clients when a double handshake happens
 // either that, or rate limit it in warp instead
-pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(2);
+// candidates trickle in during this, so it covers gathering as well
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
 
 // Buffer up to a dozen messages from the client, using a bounded channel
 // because we don't trust them. Why a dozen? 12 is a cool number
@@ -74,8 +76,26 @@ impl NativeRtcSession {
     // waits for data channel opening on an RTCPeerConnection and then returns
     // a NativeRtcSession wrapping the whole thing. The peer connection should
     // already have been set up with SDP and such.
-    // Agnostic to server/client side. Has a timeout.
+    // For the answering side, which waits for the other side's data channel.
+    // Has a timeout.
     pub async fn new(peer_connection: Arc<RTCPeerConnection>) -> Result<Self> {
+        Self::with_timeout(peer_connection, None).await
+    }
+
+    // for the offering side, which created the data channel itself before
+    // the offer. poll it before sending candidates so the handlers are
+    // registered before the channel can open
+    pub async fn new_with_channel(
+        peer_connection: Arc<RTCPeerConnection>,
+        data_channel: Arc<RTCDataChannel>,
+    ) -> Result<Self> {
+        Self::with_timeout(peer_connection, Some(data_channel)).await
+    }
+
+    async fn with_timeout(
+        peer_connection: Arc<RTCPeerConnection>,
+        data_channel: Option<Arc<RTCDataChannel>>,
+    ) -> Result<Self> {
         let (done_tx, mut done_rx) = tokio::sync::mpsc::channel::<()>(1);
 
         let peer_connection_done = peer_connection.clone();
@@ -89,10 +109,11 @@ impl NativeRtcSession {
         let timeout = tokio::time::sleep(HANDSHAKE_TIMEOUT);
         tokio::pin!(timeout);
 
-        let get_session = Self::finish_new(peer_connection, done_tx.clone());
+        let get_session = Self::finish_new(peer_connection, data_channel, done_tx.clone());
 
         tokio::select! {
             _ = timeout.as_mut() => {
+                metrics::HANDSHAKE_TIMEOUTS.inc();
                 let _ = done_tx.try_send(());
                 bail!("handshake timed out");
             }
@@ -102,6 +123,7 @@ impl NativeRtcSession {
 
     async fn finish_new(
         peer_connection: Arc<RTCPeerConnection>,
+        data_channel: Option<Arc<RTCDataChannel>>,
         done_tx: tokio::sync::mpsc::Sender<()>,
     ) -> Result<Self> {
         let done_tx_fail = done_tx.clone();
@@ -109,13 +131,13 @@ impl NativeRtcSession {
         // This will notify you when the peer has connected/disconnected
         peer_connection
             .on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
-                println!("Peer Connection State has changed: {}", s);
+                debug!("peer connection state has changed: {s}");
 
                 if s == RTCPeerConnectionState::Failed {
                     // Wait until PeerConnection has had no network activity for 30 seconds or another failure. It may be reconnected using an ICE Restart.
                     // Use webrtc.PeerConnectionStateDisconnected if you are interested in detecting faster timeout.
                     // Note that the PeerConnection may come back from PeerConnectionStateDisconnected.
-                    println!("Peer Connection has gone to failed exiting");
+                    warn!("peer connection failed, exiting");
                     let _ = done_tx_fail.try_send(());
                 }
 
@@ -126,60 +148,33 @@ impl NativeRtcSession {
         // dc channel is used to "trampoline" the datachannel out of the event handler.
         let (dc_tx, mut dc_rx) = tokio::sync::mpsc::channel::<Arc<RTCDataChannel>>(1);
         let (msg_tx, msg_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(MAX_MSG_BUF);
-        let dc_set = Arc::new(AtomicBool::new(false));
-
-        let done_tx_dc = done_tx.clone();
-        // Register data channel creation handling
-        peer_connection
-            .on_data_channel(Box::new(move |d: Arc<RTCDataChannel>| {
-                let d_label = d.label().to_owned();
-                let d_id = d.id();
-                println!("New DataChannel {} {}", d_label, d_id);
-
-                // only allow at most one onDataChannel event using atomic booleans
-                let dc_already_set = dc_set.swap(true, Ordering::Relaxed);
-                if dc_already_set || d.ordered() || d.max_retransmits() != 0 {
-                    println!("dc doesn't pass sanity checks, closing");
-                    let _ = done_tx_dc.try_send(());
-                }
 
-                // borrow checker makes us clone these for the async
-                let dc_tx = dc_tx.clone();
-                let done_tx_dc = done_tx_dc.clone();
-                let msg_tx = msg_tx.clone();
-
-                // Register channel opening handling
-                Box::pin(async move {
-                    let d2 = Arc::clone(&d);
-                    let d_label2 = d_label.clone();
-                    let d_id2 = d_id;
-                    d.on_open(Box::new(move || {
-                        println!("Data channel '{}'-'{}' open. Random messages will now be sent to any connected DataChannels every 5 seconds", d_label2, d_id2);
-
-                        if let Err(_) = dc_tx.try_send(d2) {
-                            println!("dc_tx send fail");
-                            let _ = done_tx_dc.try_send(());
-                        }
-
-                        Box::pin(async {})
-                    })).await;
-
-                    // Register text message handling
-                    d.on_message(Box::new(move |msg: DataChannelMessage| {
-                        println!("Message from DataChannel '{}'", d_label);
-                        let msg = msg.data.to_vec();
-
-                        if let Err(_) = msg_tx.try_send(msg) {
-                            println!("msg send fail");
-                            // FIXME tally up overflow packets here for client misbehavior
-                        }
-
-                        Box::pin(async {})
-                    })).await;
-
-                })
-            }))
-            .await;
+        if let Some(d) = data_channel {
+            Self::watch_channel(d, dc_tx, done_tx.clone(), msg_tx).await;
+        } else {
+            let dc_set = Arc::new(AtomicBool::new(false));
+            let done_tx_dc = done_tx.clone();
+            // Register data channel creation handling
+            peer_connection
+                .on_data_channel(Box::new(move |d: Arc<RTCDataChannel>| {
+                    debug!("new data channel {} {}", d.label(), d.id());
+
+                    // only allow at most one onDataChannel event using atomic booleans
+                    let dc_already_set = dc_set.swap(true, Ordering::Relaxed);
+                    if dc_already_set || d.ordered() || d.max_retransmits() != 0 {
+                        warn!("data channel doesn't pass sanity checks, closing");
+                        let _ = done_tx_dc.try_send(());
+                    }
+
+                    Box::pin(Self::watch_channel(
+                        d,
+                        dc_tx.clone(),
+                        done_tx_dc.clone(),
+                        msg_tx.clone(),
+                    ))
+                }))
+                .await;
+        }
 
         let data_channel = dc_rx.recv().await.context("dc hangup")?;
 
@@ -192,6 +187,46 @@ impl NativeRtcSession {
         })
     }
 
+    // hands the channel to dc_tx once it opens and its messages to msg_tx
+    async fn watch_channel(
+        d: Arc<RTCDataChannel>,
+        dc_tx: tokio::sync::mpsc::Sender<Arc<RTCDataChannel>>,
+        done_tx: tokio::sync::mpsc::Sender<()>,
+        msg_tx: tokio::sync::mpsc::Sender<Vec<u8>>,
+    ) {
+        let d_label = d.label().to_owned();
+        let d_id = d.id();
+
+        // Register channel opening handling
+        let d2 = Arc::clone(&d);
+        let d_label2 = d_label.clone();
+        d.on_open(Box::new(move || {
+            debug!("data channel '{d_label2}'-'{d_id}' open");
+
+            if dc_tx.try_send(d2).is_err() {
+                warn!("dc_tx send fail");
+                let _ = done_tx.try_send(());
+            }
+
+            Box::pin(async {})
+        }))
+        .await;
+
+        // Register text message handling
+        d.on_message(Box::new(move |msg: DataChannelMessage| {
+            trace!("message from data channel '{d_label}'");
+            let msg = msg.data.to_vec();
+
+            if msg_tx.try_send(msg).is_err() {
+                warn!("client message buffer full, dropping a message");
+                // FIXME tally up overflow packets here for client misbehavior
+            }
+
+            Box::pin(async {})
+        }))
+        .await;
+    }
+
     pub fn send_impl(&self, msg: Vec<u8>) -> impl Future<Output = bool> {
         let data_channel = self.data_channel.clone();
         async move {
@@ -6,12 +6,14 @@ use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 use webrtc::peer_connection::RTCPeerConnection;
 
-// returns SDP string for warp to respond with.
-// also creates a tokio task that awaits a connection
+use super::Trickle;
+
+// returns SDP string for warp to respond with, along with the trickle
+// that exchanges candidates with the client afterwards
 pub async fn negotiate<'a>(
     peer_connection: Arc<RTCPeerConnection>,
     client_offer: rtc::ClientOffer,
-) -> Result<String> {
+) -> Result<(String, Arc<Trickle>)> {
     debug!("parsing client SDP");
     // Wait for the offer to be pasted
     let mut offer = RTCSessionDescription::default();
@@ -25,21 +27,16 @@ pub async fn negotiate<'a>(
     // Create an answer
     let answer = peer_connection.create_answer(None).await?;
 
-    // Create channel that is blocked until ICE Gathering is complete
-    let mut gather_complete = peer_connection.gathering_complete_promise().await;
+    // has to be listening before gathering starts
+    let trickle = Trickle::new(peer_connection.clone()).await;
 
-    // Sets the LocalDescription, and starts our UDP listeners
+    // Sets the LocalDescription, and starts our UDP listeners. candidates
+    // go out through /signal/ice as they're found, so don't wait on them
     peer_connection.set_local_description(answer).await?;
 
-    // Block until ICE Gathering is complete, disabling trickle ICE
-    // we do this because we only can exchange one signaling message
-    // in a production application you should exchange ICE Candidates via OnICECandidate
-    let _ = gather_complete.recv().await;
-
-    // Output the answer in base64 so we can paste it in browser
     if let Some(local_desc) = peer_connection.local_description().await {
         debug!("succeeded negotiation");
-        Ok(local_desc.sdp)
+        Ok((local_desc.sdp, trickle))
     } else {
         bail!("failed to generate answer local description");
     }
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use archive_engine::*;
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use webrtc::peer_connection::{math_rand_alpha, RTCPeerConnection};
+
+const SIGNAL_ID_LEN: usize = 32;
+
+lazy_static! {
+    // server side handshakes still exchanging candidates. NativeRtcSession
+    // times out, so whoever registers one can always unregister it
+    static ref TRICKLES: Mutex<HashMap<rtc::SignalId, Arc<Trickle>>> = Default::default();
+}
+
+#[derive(Default)]
+struct LocalCandidates {
+    // found since the last take_local
+    candidates: Vec<rtc::IceCandidate>,
+    done: bool,
+}
+
+// collects our ice candidates as they're gathered and adds the other
+// side's as they come in. the same on both ends of the connection
+pub struct Trickle {
+    peer_connection: Arc<RTCPeerConnection>,
+    local: Arc<Mutex<LocalCandidates>>,
+}
+
+impl Trickle {
+    // call before set_local_description, which starts gathering
+    pub async fn new(peer_connection: Arc<RTCPeerConnection>) -> Arc<Self> {
+        let local = Arc::new(Mutex::new(LocalCandidates::default()));
+        let on_candidate_local = local.clone();
+        peer_connection
+            .on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+                let local = on_candidate_local.clone();
+                Box::pin(async move {
+                    // None means gathering finished
+                    let candidate = match candidate {
+                        Some(candidate) => candidate,
+                        None => {
+                            local.lock().unwrap().done = true;
+                            return;
+                        }
+                    };
+                    match candidate.to_json().await {
+                        Ok(init) => local.lock().unwrap().candidates.push(from_init(init)),
+                        Err(e) => warn!("failed to serialize ice candidate: {e}"),
+                    }
+                })
+            }))
+            .await;
+        Arc::new(Trickle {
+            peer_connection,
+            local,
+        })
+    }
+
+    // our candidates found since the last call, and whether that's all
+    pub fn take_local(&self) -> (Vec<rtc::IceCandidate>, bool) {
+        let mut local = self.local.lock().unwrap();
+        (std::mem::take(&mut local.candidates), local.done)
+    }
+
+    pub async fn add_remote(&self, candidates: Vec<rtc::IceCandidate>) -> Result<()> {
+        for candidate in candidates {
+            debug!("adding remote candidate {}", candidate.candidate);
+            self.peer_connection
+                .add_ice_candidate(to_init(candidate))
+                .await?;
+        }
+        Ok(())
+    }
+
+    // the server's half of a /signal/ice call
+    pub async fn exchange(&self, offer: rtc::IceOffer) -> Result<rtc::IceAnswer> {
+        self.add_remote(offer.candidates).await?;
+        let (candidates, done) = self.take_local();
+        Ok(rtc::IceAnswer { candidates, done })
+    }
+}
+
+// webrtc-rs leaves the mid empty, and there's only the one data channel
+// m-line, so send the index alone like a browser would
+fn from_init(init: RTCIceCandidateInit) -> rtc::IceCandidate {
+    rtc::IceCandidate {
+        candidate: init.candidate,
+        sdp_mid: Some(init.sdp_mid).filter(|mid| !mid.is_empty()),
+        sdp_mline_index: Some(init.sdp_mline_index),
+    }
+}
+
+fn to_init(candidate: rtc::IceCandidate) -> RTCIceCandidateInit {
+    RTCIceCandidateInit {
+        candidate: candidate.candidate,
+        sdp_mid: candidate.sdp_mid.unwrap_or_default(),
+        sdp_mline_index: candidate.sdp_mline_index.unwrap_or(0),
+        username_fragment: String::new(),
+    }
+}
+
+pub fn register_trickle(trickle: Arc<Trickle>) -> rtc::SignalId {
+    let signal_id = math_rand_alpha(SIGNAL_ID_LEN);
+    TRICKLES.lock().unwrap().insert(signal_id.clone(), trickle);
+    signal_id
+}
+
+pub fn find_trickle(signal_id: &str) -> Option<Arc<Trickle>> {
+    TRICKLES.lock().unwrap().get(signal_id).cloned()
+}
+
+pub fn unregister_trickle(signal_id: &str) {
+    TRICKLES.lock().unwrap().remove(signal_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::{create_peer_connection, negotiate, NativeRtcSession};
+    use archive_engine::rtc::RtcSession;
+    use tokio::time::Duration;
+    use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+    use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+    use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+    #[tokio::test]
+    async fn test_registry() {
+        let peer_connection = create_peer_connection().await.unwrap();
+        let trickle = Trickle::new(peer_connection.clone()).await;
+        let signal_id = register_trickle(trickle.clone());
+        assert_eq!(signal_id.len(), SIGNAL_ID_LEN);
+        assert!(Arc::ptr_eq(&find_trickle(&signal_id).unwrap(), &trickle));
+        assert!(find_trickle("not a signal id").is_none());
+
+        unregister_trickle(&signal_id);
+        assert!(find_trickle(&signal_id).is_none());
+        peer_connection.close().await.unwrap();
+    }
+
+    // a whole handshake over the local interfaces, with the client's
+    // /signal/ice calls made directly
+    #[tokio::test]
+    async fn test_trickle() {
+        let client_pc = create_peer_connection().await.unwrap();
+        let data_channel = client_pc
+            .create_data_channel(
+                "udp",
+                Some(RTCDataChannelInit {
+                    ordered: Some(false),
+                    max_packet_life_time: None,
+                    max_retransmits: Some(0),
+                    protocol: None,
+                    negotiated: None,
+                    id: None,
+                }),
+            )
+            .await
+            .unwrap();
+        let offer = client_pc.create_offer(None).await.unwrap();
+        let client_trickle = Trickle::new(client_pc.clone()).await;
+        client_pc.set_local_description(offer).await.unwrap();
+        let client_offer = rtc::ClientOffer {
+            protocol: rtc::ProtocolInfo::current(),
+            ticket: rtc::ArenaTicket {
+                arena_ukey: 0,
+                resume: None,
+            },
+            sdp: client_pc.local_description().await.unwrap().sdp,
+        };
+
+        let server_pc = create_peer_connection().await.unwrap();
+        let (sdp, server_trickle) = negotiate(server_pc.clone(), client_offer).await.unwrap();
+        let signal_id = register_trickle(server_trickle);
+        let mut answer = RTCSessionDescription::default();
+        answer.sdp_type = RTCSdpType::Answer;
+        answer.sdp = sdp;
+        client_pc.set_remote_description(answer).await.unwrap();
+
+        let sessions = async {
+            tokio::try_join!(
+                NativeRtcSession::new(server_pc),
+                NativeRtcSession::new_with_channel(client_pc, data_channel),
+            )
+        };
+        tokio::pin!(sessions);
+        let trickle_loop = async {
+            loop {
+                let (candidates, client_done) = client_trickle.take_local();
+                let ice_offer = rtc::IceOffer {
+                    signal_id: signal_id.clone(),
+                    candidates,
+                };
+                let trickle = find_trickle(&signal_id).unwrap();
+                let ice_answer = trickle.exchange(ice_offer).await.unwrap();
+                client_trickle
+                    .add_remote(ice_answer.candidates)
+                    .await
+                    .unwrap();
+                if client_done && ice_answer.done {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        tokio::pin!(trickle_loop);
+        let (server, client) = tokio::select! {
+            biased;
+            sessions = &mut sessions => sessions,
+            _ = &mut trickle_loop => sessions.await,
+        }
+        .unwrap();
+        unregister_trickle(&signal_id);
+
+        assert_eq!(server.get_state(), rtc::SessionState::Connected);
+        assert_eq!(client.get_state(), rtc::SessionState::Connected);
+        server.close();
+        client.close();
+    }
+}
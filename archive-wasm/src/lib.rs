@@ -1,6 +1,9 @@
+mod wasm_gamepad;
 mod wasm_random;
 mod wasm_rtc;
+mod wasm_ws;
 use wasm_rtc::*;
+use wasm_ws::*;
 use web_sys::HtmlCanvasElement;
 
 use std::cell::RefCell;
@@ -10,7 +13,10 @@ use std::sync::mpsc;
 use archive_client::*;
 use archive_engine::rtc::{RtcServerDescriptor, BoxedRtcSession};
 use archive_engine::*;
+use futures::future::{self, Either};
 use js_sys::Reflect;
+use log::warn;
+use wasm_gamepad::WasmGamepadBuilder;
 use wasm_random::WasmRandomBuilder;
 use wasm_rtc::WasmClientSession;
 use winit::event_loop::EventLoop;
@@ -27,7 +33,11 @@ pub struct WasmClient {
 #[wasm_bindgen(js_name=startClient)]
 pub async fn start_client() -> Result<JsValue, JsValue> {
     random::register(WasmRandomBuilder {});
-    launch_config::register(launch_config::LaunchConfig { sample_count: 4 });
+    gamepad::register(WasmGamepadBuilder {});
+    launch_config::register(launch_config::LaunchConfig {
+        sample_count: 4,
+        bindings: Default::default(),
+    });
 
     let canvas: HtmlCanvasElement = web_sys::window()
         .and_then(|win| win.document())
@@ -67,8 +77,26 @@ pub fn use_connection(
     wasm_client: &mut WasmClient,
     connection: WasmConnection,
 ) -> Result<(), JsValue> {
-    let WasmConnection { session } = connection;
-    let msg = client::ClientMessageFromApp::Connected(session);
+    let WasmConnection {
+        session,
+        servers,
+        resume_ticket,
+    } = connection;
+    // the client calls this on its own to get back into the same slot
+    let connect = move |ticket: rtc::ArenaTicket| -> SharedFuture<Result<BoxedRtcSession, String>> {
+        let servers = servers.clone();
+        Box::pin(async move {
+            match servers.connect(ticket).await {
+                Ok((session, _)) => Ok(session),
+                Err(e) => Err(format!("{:?}", e)),
+            }
+        })
+    };
+    let rejoin = client::Rejoin {
+        ticket: resume_ticket,
+        connect: Box::new(connect),
+    };
+    let msg = client::ClientMessageFromApp::Joined { session, rejoin };
 
     wasm_client.tx.send(msg).or_else(fmt_jserr)
 }
@@ -76,18 +104,57 @@ pub fn use_connection(
 #[wasm_bindgen]
 pub struct WasmConnection {
     session: BoxedRtcSession,
+    // for reconnecting
+    servers: Servers,
+    resume_ticket: rtc::ArenaTicket,
+}
+
+// how long webrtc gets to open a data channel before we fall back
+const RTC_TIMEOUT_MS: i32 = 5000;
+
+#[derive(Clone)]
+struct Servers {
+    rtc: WasmServerHandle,
+    ws: WasmWsServerHandle,
+}
+impl Servers {
+    // tries webrtc first, then the websocket if that fails or takes too
+    // long, e.g. because udp is blocked
+    async fn connect(
+        &self,
+        ticket: rtc::ArenaTicket,
+    ) -> Result<(BoxedRtcSession, rtc::JoinAnswer), JsValue> {
+        let timeout = Box::pin(sleep_ms(RTC_TIMEOUT_MS));
+        let rtc = Box::pin(self.rtc.rtc_connect_raw(ticket.clone()));
+        match future::select(rtc, timeout).await {
+            Either::Left((Ok((session, answer)), _)) => {
+                let boxed: BoxedRtcSession = Box::new(session);
+                return Ok((boxed, answer));
+            }
+            // the server turned us down, it would say the same over the websocket
+            Either::Left((Err(e @ ConnectError::Join(_)), _)) => return Err(e.into()),
+            Either::Left((Err(e), _)) => warn!("webrtc failed, falling back to websocket: {:?}", e),
+            Either::Right(_) => warn!("webrtc timed out, falling back to websocket"),
+        }
+        self.ws.rtc_connect(ticket).await
+    }
 }
 
 #[wasm_bindgen]
-pub async fn connect(hostname: String) -> Result<WasmConnection, JsValue> {
-    let handle = WasmServerHandle { hostname };
-    let session = handle.rtc_connect().await?;
-
-    // let res = js_sys::Object::new();
-    // let pc = JsValue::from(session.peer_connection);
-    // let dc = JsValue::from(session.data_channel);
-    // Reflect::set(&res, &JsValue::from("pc"), &pc)?;
-    // Reflect::set(&res, &JsValue::from("dc"), &dc)?;
-    // Ok(JsValue::from(res))
-    Ok(WasmConnection { session })
+pub async fn connect(hostname: String, ws_url: String) -> Result<WasmConnection, JsValue> {
+    let servers = Servers {
+        rtc: WasmServerHandle { hostname },
+        ws: WasmWsServerHandle { url: ws_url },
+    };
+    let arena_ukey = 0;
+    let ticket = rtc::ArenaTicket {
+        arena_ukey,
+        resume: None,
+    };
+    let (session, answer) = servers.connect(ticket).await?;
+    Ok(WasmConnection {
+        session,
+        servers,
+        resume_ticket: answer.resume_ticket(arena_ukey),
+    })
 }
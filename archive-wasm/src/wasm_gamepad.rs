@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use archive_client::gamepad::{Gamepad, GamepadBuilderImpl, GamepadImpl, GamepadState};
+use wasm_bindgen::JsCast;
+
+// indices in the "standard" gamepad mapping
+// https://w3c.github.io/gamepad/#remapping
+const AXIS_LEFT_X: u32 = 0;
+const AXIS_LEFT_Y: u32 = 1;
+const AXIS_RIGHT_X: u32 = 2;
+const AXIS_RIGHT_Y: u32 = 3;
+const BUTTON_SOUTH: u32 = 0;
+const BUTTON_WEST: u32 = 2;
+const BUTTON_RIGHT_BUMPER: u32 = 5;
+const BUTTON_RIGHT_TRIGGER: u32 = 7;
+
+pub struct WasmGamepadBuilder {}
+pub struct WasmGamepad {
+    // the browser has no events for pad input, so a pad counts as used
+    // when its timestamp moves
+    timestamps: HashMap<u32, f64>,
+    active: Option<u32>,
+}
+
+impl GamepadBuilderImpl for WasmGamepadBuilder {
+    fn create(&self) -> Gamepad {
+        Box::new(WasmGamepad {
+            timestamps: HashMap::new(),
+            active: None,
+        })
+    }
+}
+
+fn connected_gamepads() -> Vec<web_sys::Gamepad> {
+    let pads = match web_sys::window().map(|win| win.navigator().get_gamepads()) {
+        Some(Ok(pads)) => pads,
+        _ => return Vec::new(),
+    };
+    // unplugged slots are null
+    pads.iter()
+        .filter_map(|pad| pad.dyn_into::<web_sys::Gamepad>().ok())
+        .filter(|pad| pad.connected())
+        .collect()
+}
+
+fn axis(pad: &web_sys::Gamepad, i: u32) -> f32 {
+    pad.axes().get(i).as_f64().unwrap_or(0.0) as f32
+}
+fn pressed(pad: &web_sys::Gamepad, i: u32) -> bool {
+    pad.buttons()
+        .get(i)
+        .dyn_into::<web_sys::GamepadButton>()
+        .map_or(false, |button| button.pressed())
+}
+
+impl GamepadImpl for WasmGamepad {
+    fn poll(&mut self) -> Option<GamepadState> {
+        let pads = connected_gamepads();
+        for pad in &pads {
+            let last = self.timestamps.insert(pad.index(), pad.timestamp());
+            if last.map_or(false, |last| last != pad.timestamp()) {
+                self.active = Some(pad.index());
+            }
+        }
+        let pad = match self.active {
+            Some(active) => pads.into_iter().find(|pad| pad.index() == active)?,
+            // nothing pressed yet, go with the first pad
+            None => pads.into_iter().next()?,
+        };
+
+        // browser y axes point down
+        Some(GamepadState {
+            left_stick: [axis(&pad, AXIS_LEFT_X), -axis(&pad, AXIS_LEFT_Y)],
+            right_stick: [axis(&pad, AXIS_RIGHT_X), -axis(&pad, AXIS_RIGHT_Y)],
+            fire: pressed(&pad, BUTTON_RIGHT_TRIGGER) || pressed(&pad, BUTTON_RIGHT_BUMPER),
+            reload: pressed(&pad, BUTTON_WEST),
+            interact: pressed(&pad, BUTTON_SOUTH),
+        })
+    }
+}
@@ -1,28 +1,69 @@
+use std::cell::RefCell;
 use std::fmt::Display;
+use std::rc::Rc;
 use std::sync::mpsc;
 
+use futures::channel::oneshot;
+use futures::future::{self, Either};
 use js_sys::ArrayBuffer;
 use js_sys::Reflect;
 use js_sys::Uint8Array;
-use log::info;
+use log::{error, info, warn};
 
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::RtcDataChannelInit;
 use web_sys::{
     MessageEvent, Request, RequestInit, RequestMode, Response, RtcConfiguration, RtcDataChannel,
     RtcDataChannelType, RtcPeerConnection, RtcSdpType, RtcSessionDescriptionInit,
 };
+use web_sys::{
+    RtcDataChannelInit, RtcDataChannelState, RtcIceCandidateInit, RtcPeerConnectionIceEvent,
+};
 
 use archive_engine::rtc::{self, *};
 use archive_engine::SharedFuture;
 
 use wasm_bindgen::prelude::*;
 
+// between /signal/ice calls while either side is still gathering
+const TRICKLE_INTERVAL_MS: i32 = 100;
+
 pub fn fmt_jserr<T>(err: impl Display) -> Result<T, JsValue> {
     Err(JsValue::from(format!("{err}")))
 }
 
+// resolves after ms, for timeouts
+pub async fn sleep_ms(ms: i32) {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let window = web_sys::window().unwrap();
+        if let Err(e) = window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, ms) {
+            error!("failed to set timeout {:?}", e);
+        }
+    });
+    let _ = JsFuture::from(promise).await;
+}
+
+// a turned down join is kept apart from everything else that can go
+// wrong, since falling back to the websocket won't change the answer
+#[derive(Debug)]
+pub enum ConnectError {
+    Join(JoinError),
+    Other(JsValue),
+}
+impl From<JsValue> for ConnectError {
+    fn from(e: JsValue) -> Self {
+        ConnectError::Other(e)
+    }
+}
+impl From<ConnectError> for JsValue {
+    fn from(e: ConnectError) -> Self {
+        match e {
+            ConnectError::Join(e) => JsValue::from(e.to_string()),
+            ConnectError::Other(e) => e,
+        }
+    }
+}
+
 pub struct WasmClientSession {
     pub peer_connection: RtcPeerConnection,
     pub data_channel: RtcDataChannel,
@@ -30,36 +71,74 @@ pub struct WasmClientSession {
 }
 impl RtcSession for WasmClientSession {
     fn get_state(&self) -> SessionState {
-        todo!()
+        match self.data_channel.ready_state() {
+            RtcDataChannelState::Connecting => SessionState::Connecting,
+            RtcDataChannelState::Open => SessionState::Connected,
+            RtcDataChannelState::Closing => SessionState::Disconnected,
+            _ => SessionState::Closed,
+        }
     }
     fn close(&self) {
-        todo!()
+        self.data_channel.close();
+        self.peer_connection.close();
     }
 
     fn send(&self, msg: Vec<u8>) -> SharedFuture<bool> {
-        todo!()
+        // the browser queues the message itself, so the future is already done
+        let success = match self.data_channel.send_with_u8_array(&msg) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("send error {:?}", e);
+                false
+            }
+        };
+        Box::pin(async move { success })
     }
     fn try_recv(&mut self) -> Result<Vec<u8>, mpsc::TryRecvError> {
         self.rx.try_recv()
     }
 }
 
+// closes a connection that never made it into a session, including when
+// the connect future is dropped by a timeout
+struct CloseOnDrop(Option<(RtcPeerConnection, RtcDataChannel)>);
+impl CloseOnDrop {
+    fn disarm(mut self) -> (RtcPeerConnection, RtcDataChannel) {
+        self.0.take().unwrap()
+    }
+}
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        if let Some((pc, dc)) = self.0.take() {
+            dc.close();
+            pc.close();
+        }
+    }
+}
+
+// our candidates, filled in by onicecandidate as the browser finds them
+#[derive(Default)]
+struct LocalCandidates {
+    // found since the last IceOffer
+    candidates: Vec<IceCandidate>,
+    done: bool,
+}
+
+#[derive(Clone)]
 pub struct WasmServerHandle {
     pub hostname: String,
 }
 impl WasmServerHandle {
-    async fn rtc_signal(
-        hostname: String,
-        client_offer: ClientOffer,
-    ) -> Result<ServerAnswer, JsValue> {
-        let offer_serialized = serde_json::to_string(&client_offer).or_else(fmt_jserr)?;
+    async fn post_json<T: serde::Serialize, R: serde::de::DeserializeOwned>(
+        url: String,
+        body: &T,
+    ) -> Result<R, ConnectError> {
+        let body_serialized = serde_json::to_string(body).or_else(fmt_jserr)?;
 
         let mut opts = RequestInit::new();
         opts.method("POST");
         opts.mode(RequestMode::Cors);
-        opts.body(Some(&JsValue::from_str(&offer_serialized)));
-
-        let url = format!("{hostname}/signal");
+        opts.body(Some(&JsValue::from_str(&body_serialized)));
 
         let request = Request::new_with_str_and_init(&url, &opts)?;
 
@@ -73,16 +152,60 @@ impl WasmServerHandle {
         // `resp_value` is a `Response` object.
         assert!(resp_value.is_instance_of::<Response>());
         let resp: Response = resp_value.dyn_into()?;
+        if resp.status() == 409 {
+            // the server turned down the join itself
+            let json = JsFuture::from(resp.json()?).await?;
+            let error: JoinError = json.into_serde().or_else(fmt_jserr)?;
+            return Err(ConnectError::Join(error));
+        }
+        if !resp.ok() {
+            let error = format!("bad signal status: {}", resp.status());
+            return Err(ConnectError::Other(JsValue::from(error)));
+        }
 
         // Convert this other `Promise` into a rust `Future`.
         let json = JsFuture::from(resp.json()?).await?;
 
         // Use serde to parse the JSON into a struct.
-        let server_answer: ServerAnswer = json.into_serde().or_else(fmt_jserr)?;
-        Ok(server_answer)
+        Ok(json.into_serde().or_else(fmt_jserr)?)
+    }
+
+    // swaps candidates with the server until both sides are done gathering
+    async fn trickle_loop(
+        hostname: String,
+        signal_id: SignalId,
+        pc: RtcPeerConnection,
+        local: Rc<RefCell<LocalCandidates>>,
+    ) -> Result<(), ConnectError> {
+        loop {
+            let (candidates, local_done) = {
+                let mut local = local.borrow_mut();
+                (std::mem::take(&mut local.candidates), local.done)
+            };
+            let ice_offer = IceOffer {
+                signal_id: signal_id.clone(),
+                candidates,
+            };
+            let ice_answer: IceAnswer =
+                Self::post_json(format!("{hostname}/signal/ice"), &ice_offer).await?;
+            for candidate in ice_answer.candidates {
+                let mut init = RtcIceCandidateInit::new(&candidate.candidate);
+                init.sdp_mid(candidate.sdp_mid.as_deref());
+                init.sdp_m_line_index(candidate.sdp_mline_index);
+                let promise = pc.add_ice_candidate_with_opt_rtc_ice_candidate_init(Some(&init));
+                JsFuture::from(promise).await?;
+            }
+            if local_done && ice_answer.done {
+                return Ok(());
+            }
+            sleep_ms(TRICKLE_INTERVAL_MS).await;
+        }
     }
 
-    async fn rtc_connect_raw(hostname: String) -> Result<WasmClientSession, JsValue> {
+    pub async fn rtc_connect_raw(
+        &self,
+        ticket: ArenaTicket,
+    ) -> Result<(WasmClientSession, JoinAnswer), ConnectError> {
         // based off of https://jsfiddle.net/9tsx15mg/90/ and the webrtc samples
         let mut config = RtcConfiguration::new();
         let ice_servers = js_sys::JSON::parse("[{\"urls\":\"stun:stun.l.google.com:19302\"}]")?;
@@ -95,9 +218,24 @@ impl WasmServerHandle {
         let dc = pc.create_data_channel_with_data_channel_dict("udp", &dict);
 
         dc.set_binary_type(RtcDataChannelType::Arraybuffer);
-
-        let onclose_callback = Closure::wrap(Box::new(|| info!("closed")) as Box<dyn FnMut()>);
-        let onopen_callback = Closure::wrap(Box::new(|| info!("open")) as Box<dyn FnMut()>);
+        let guard = CloseOnDrop(Some((pc.clone(), dc.clone())));
+
+        // connect waits for the channel to open, so it can give up on
+        // networks where it never does
+        let (opened_tx, opened_rx) = oneshot::channel::<()>();
+        let opened = Rc::new(RefCell::new(Some(opened_tx)));
+        let onclose_opened = opened.clone();
+        let onclose_callback = Closure::wrap(Box::new(move || {
+            info!("closed");
+            // wakes up the connect with an error if it's still waiting
+            onclose_opened.borrow_mut().take();
+        }) as Box<dyn FnMut()>);
+        let onopen_callback = Closure::wrap(Box::new(move || {
+            info!("open");
+            if let Some(opened) = opened.borrow_mut().take() {
+                let _ = opened.send(());
+            }
+        }) as Box<dyn FnMut()>);
 
         let (tx, rx) = mpsc::channel::<Vec<u8>>();
 
@@ -122,6 +260,26 @@ impl WasmServerHandle {
         onopen_callback.forget();
         onmessage_callback.forget();
 
+        let local = Rc::new(RefCell::new(LocalCandidates::default()));
+        let onicecandidate_local = local.clone();
+        let onicecandidate_callback =
+            Closure::wrap(Box::new(move |ev: RtcPeerConnectionIceEvent| {
+                let mut local = onicecandidate_local.borrow_mut();
+                match ev.candidate() {
+                    // an empty candidate is the older way of saying it's done
+                    Some(candidate) if !candidate.candidate().is_empty() => {
+                        local.candidates.push(IceCandidate {
+                            candidate: candidate.candidate(),
+                            sdp_mid: candidate.sdp_mid(),
+                            sdp_mline_index: candidate.sdp_m_line_index(),
+                        })
+                    }
+                    _ => local.done = true,
+                }
+            }) as Box<dyn FnMut(RtcPeerConnectionIceEvent)>);
+        pc.set_onicecandidate(Some(onicecandidate_callback.as_ref().unchecked_ref()));
+        onicecandidate_callback.forget();
+
         // this was done in onnegotiationneeded in the fiddle, but the webrtc samples
         // don't bother and just do it directly during construction, which is easier
         let offer = JsFuture::from(pc.create_offer()).await?;
@@ -131,9 +289,8 @@ impl WasmServerHandle {
             .ok_or(JsValue::from("bad sdp"))?;
 
         let client_offer = ClientOffer {
-            ticket: ArenaTicket {
-                arena_ukey: 0
-            },
+            protocol: ProtocolInfo::current(),
+            ticket,
             sdp: offer_sdp,
         };
 
@@ -143,31 +300,60 @@ impl WasmServerHandle {
         JsFuture::from(sld_promise).await?;
 
         // fetch the server's answer SDP and use it.
-        let server_answer = WasmServerHandle::rtc_signal(hostname, client_offer).await?;
+        let server_answer: ServerAnswer =
+            Self::post_json(format!("{}/signal", self.hostname), &client_offer).await?;
+        info!("joined as client #{}", server_answer.client_id);
+        let join_answer = server_answer.join_answer();
 
         let mut answer_obj = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
         answer_obj.sdp(&server_answer.sdp);
         let srd_promise = pc.set_remote_description(&answer_obj);
         JsFuture::from(srd_promise).await?;
 
+        let trickle_loop = Box::pin(Self::trickle_loop(
+            self.hostname.clone(),
+            server_answer.signal_id,
+            pc.clone(),
+            local,
+        ));
+        let opened = match future::select(opened_rx, trickle_loop).await {
+            Either::Left((opened, _)) => opened,
+            Either::Right((result, opened_rx)) => {
+                // the server forgets the signal id once it's connected, so
+                // this can fail just before the channel opens
+                if let Err(e) = result {
+                    warn!("stopped trickling: {:?}", e);
+                }
+                opened_rx.await
+            }
+        };
+        opened.map_err(|_| JsValue::from("data channel closed before opening"))?;
+
+        let (pc, dc) = guard.disarm();
         let session = WasmClientSession {
             peer_connection: pc,
             data_channel: dc,
             rx,
         };
-        Ok(session)
+        Ok((session, join_answer))
     }
 }
 
 impl RtcServerDescriptor for WasmServerHandle {
     type Error = JsValue;
 
-    fn rtc_connect(&self) -> SharedFuture<Result<rtc::BoxedRtcSession, Self::Error>> {
-        let hostname = self.hostname.clone();
+    fn rtc_connect(
+        &self,
+        ticket: ArenaTicket,
+    ) -> SharedFuture<Result<(rtc::BoxedRtcSession, JoinAnswer), Self::Error>> {
+        let handle = self.clone();
         Box::pin(async move {
-            let session = Self::rtc_connect_raw(hostname).await?;
+            let (session, answer) = handle
+                .rtc_connect_raw(ticket)
+                .await
+                .map_err(JsValue::from)?;
             let boxed: Box<dyn RtcSession> = Box::new(session);
-            Ok(boxed)
+            Ok((boxed, answer))
         })
     }
 }
@@ -0,0 +1,163 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc;
+
+use futures::channel::oneshot;
+use js_sys::{ArrayBuffer, Uint8Array};
+use log::{error, info, warn};
+
+use wasm_bindgen::JsCast;
+use web_sys::{BinaryType, MessageEvent, WebSocket};
+
+use archive_engine::rtc::{self, *};
+use archive_engine::SharedFuture;
+
+use wasm_bindgen::prelude::*;
+
+use crate::wasm_rtc::fmt_jserr;
+
+// for networks that block udp. same messages as the data channel, but
+// ordered and reliable, so lag spikes are worse
+pub struct WasmWsSession {
+    pub socket: WebSocket,
+    pub rx: mpsc::Receiver<Vec<u8>>,
+}
+impl RtcSession for WasmWsSession {
+    fn get_state(&self) -> SessionState {
+        match self.socket.ready_state() {
+            WebSocket::CONNECTING => SessionState::Connecting,
+            WebSocket::OPEN => SessionState::Connected,
+            WebSocket::CLOSING => SessionState::Disconnected,
+            _ => SessionState::Closed,
+        }
+    }
+    fn close(&self) {
+        if let Err(e) = self.socket.close() {
+            warn!("error closing websocket {:?}", e);
+        }
+    }
+
+    fn send(&self, msg: Vec<u8>) -> SharedFuture<bool> {
+        // the browser queues the message itself, so the future is already done
+        let success = match self.socket.send_with_u8_array(&msg) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("send error {:?}", e);
+                false
+            }
+        };
+        Box::pin(async move { success })
+    }
+    fn try_recv(&mut self) -> Result<Vec<u8>, mpsc::TryRecvError> {
+        self.rx.try_recv()
+    }
+}
+
+// set to be taken by whichever callback fires first
+type Once<T> = Rc<RefCell<Option<oneshot::Sender<T>>>>;
+
+fn once<T>() -> (Once<T>, oneshot::Receiver<T>) {
+    let (tx, rx) = oneshot::channel();
+    (Rc::new(RefCell::new(Some(tx))), rx)
+}
+
+// connects to the server's websocket listener, see tungstenite_serve
+#[derive(Clone)]
+pub struct WasmWsServerHandle {
+    pub url: String,
+}
+impl WasmWsServerHandle {
+    async fn ws_connect_raw(
+        &self,
+        ticket: ArenaTicket,
+    ) -> Result<(WasmWsSession, JoinAnswer), JsValue> {
+        let socket = WebSocket::new(&self.url)?;
+        socket.set_binary_type(BinaryType::Arraybuffer);
+
+        let (tx, rx) = mpsc::channel::<Vec<u8>>();
+        let (opened, opened_rx) = once::<Result<(), JsValue>>();
+        // the first message is the join answer, the rest go to the client
+        let (answered, answer_rx) = once::<Vec<u8>>();
+
+        let onopen_opened = opened.clone();
+        let onopen_callback = Closure::wrap(Box::new(move || {
+            if let Some(opened) = onopen_opened.borrow_mut().take() {
+                let _ = opened.send(Ok(()));
+            }
+        }) as Box<dyn FnMut()>);
+        let onerror_callback = Closure::wrap(Box::new(move || {
+            if let Some(opened) = opened.borrow_mut().take() {
+                let _ = opened.send(Err(JsValue::from("websocket failed to open")));
+            }
+        }) as Box<dyn FnMut()>);
+        let onclose_answered = answered.clone();
+        let onclose_callback = Closure::wrap(Box::new(move || {
+            info!("websocket closed");
+            // wakes up the join with an error if it's still waiting
+            onclose_answered.borrow_mut().take();
+        }) as Box<dyn FnMut()>);
+        let onmessage_callback = Closure::wrap(Box::new(move |ev: MessageEvent| {
+            let buffer = match ev.data().dyn_into::<ArrayBuffer>() {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    error!("bad recv {:?}", e);
+                    return;
+                }
+            };
+            let vec = Uint8Array::new(&JsValue::from(buffer)).to_vec();
+            match answered.borrow_mut().take() {
+                Some(answered) => {
+                    let _ = answered.send(vec);
+                }
+                None => {
+                    let _ = tx.send(vec);
+                }
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+        socket.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
+        socket.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
+        socket.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
+        onopen_callback.forget();
+        onerror_callback.forget();
+        onclose_callback.forget();
+        onmessage_callback.forget();
+
+        opened_rx
+            .await
+            .map_err(|_| JsValue::from("websocket dropped"))??;
+
+        // same handshake as the native tungstenite client
+        let request = JoinRequest {
+            protocol: ProtocolInfo::current(),
+            ticket,
+        };
+        let request_bin = bincode::serialize(&request).or_else(fmt_jserr)?;
+        socket.send_with_u8_array(&request_bin)?;
+
+        let answer = answer_rx
+            .await
+            .map_err(|_| JsValue::from("websocket closed before join answer"))?;
+        let answer: rtc::JoinResult = bincode::deserialize(&answer).or_else(fmt_jserr)?;
+        let answer = answer.or_else(fmt_jserr)?;
+        info!("joined as client #{} over websocket", answer.client_id);
+
+        Ok((WasmWsSession { socket, rx }, answer))
+    }
+}
+
+impl RtcServerDescriptor for WasmWsServerHandle {
+    type Error = JsValue;
+
+    fn rtc_connect(
+        &self,
+        ticket: ArenaTicket,
+    ) -> SharedFuture<Result<(rtc::BoxedRtcSession, JoinAnswer), Self::Error>> {
+        let handle = self.clone();
+        Box::pin(async move {
+            let (session, answer) = handle.ws_connect_raw(ticket).await?;
+            let boxed: Box<dyn RtcSession> = Box::new(session);
+            Ok((boxed, answer))
+        })
+    }
+}
@@ -8,6 +8,8 @@ requestIdleCallback(() => wasmInit());
 
 export default function App() {
   const [client, setClient] = useState<WasmClient | null>(null);
+  // e.g. "please refresh" when this bundle is older than the server
+  const [error, setError] = useState<string | null>(null);
   useEffect(() => {
     (async () => {
       await wasmInit();
@@ -27,15 +29,18 @@ export default function App() {
     if (!client) return;
     (async () => {
       try {
-        let connection = await connect('http://localhost:3030');
+        setError(null);
+        let connection = await connect('http://localhost:3030', 'ws://localhost:8080');
         await useConnection(client, connection);
       } catch(e) {
         console.error(e);
+        setError(String(e));
       }
     })();
   }, [client]);
  return <div>
    <button type="button" class="play-btn" disabled={!client} onClick={onClickPlay}>Play</button><br/>
+   {error && <div class="error">{error}</div>}
    <canvas id="game" />
    </div>;
 }